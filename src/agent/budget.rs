@@ -0,0 +1,175 @@
+use crate::db::{Database, UsageTotals};
+use crate::provider::{PriceTable, Usage};
+
+use super::loop_runner::ModelUsage;
+
+/// Daily and monthly limits for one scope (a user, or everyone). 0 = unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub daily_tokens: u64,
+    pub monthly_tokens: u64,
+    pub daily_usd: f64,
+    pub monthly_usd: f64,
+}
+
+impl Limits {
+    fn is_unlimited(&self) -> bool {
+        self.daily_tokens == 0
+            && self.monthly_tokens == 0
+            && self.daily_usd <= 0.0
+            && self.monthly_usd <= 0.0
+    }
+
+    fn tokens_exceeded(&self, used: &UsageTotals) -> Option<&'static str> {
+        if self.daily_tokens > 0 && used.day_tokens >= self.daily_tokens {
+            Some("daily token")
+        } else if self.monthly_tokens > 0 && used.month_tokens >= self.monthly_tokens {
+            Some("monthly token")
+        } else {
+            None
+        }
+    }
+
+    fn usd_exceeded(&self, used: &UsageTotals) -> Option<&'static str> {
+        if self.daily_usd > 0.0 && used.day_usd >= self.daily_usd {
+            Some("daily cost")
+        } else if self.monthly_usd > 0.0 && used.month_usd >= self.monthly_usd {
+            Some("monthly cost")
+        } else {
+            None
+        }
+    }
+}
+
+/// Per-user and global budgets. Token limits stop the agent; cost limits
+/// only restrict it to free providers.
+#[derive(Debug, Clone, Default)]
+pub struct BudgetLimits {
+    pub user: Limits,
+    pub global: Limits,
+}
+
+impl BudgetLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.user.is_unlimited() && self.global.is_unlimited()
+    }
+}
+
+pub enum BudgetDecision {
+    Allow,
+    /// Paid budget exhausted: only free providers may be used.
+    FreeOnly(String),
+    /// Token budget exhausted: no further provider calls.
+    Deny(String),
+}
+
+/// The user a background call (summary, extraction, title) is made for: the
+/// call is checked against their budget and its usage is logged under them.
+#[derive(Clone, Copy)]
+pub struct Payer<'a> {
+    pub user_id: u64,
+    pub limits: &'a BudgetLimits,
+}
+
+/// Check budgets before a provider call. `pending` is the usage of the
+/// current run, which is not yet in `query_logs`.
+pub async fn check(
+    limits: &BudgetLimits,
+    db: &Database,
+    prices: &PriceTable,
+    user_id: u64,
+    pending: &[ModelUsage],
+) -> BudgetDecision {
+    if limits.is_unlimited() {
+        return BudgetDecision::Allow;
+    }
+
+    let mut user = db.usage_totals(Some(user_id)).await;
+    let mut global = db.usage_totals(None).await;
+    for u in pending {
+        let tokens = u.usage.total() as u64;
+        let usd = prices.cost(&u.provider, &u.model, &u.usage);
+        user.add(tokens, usd);
+        global.add(tokens, usd);
+    }
+
+    if let Some(kind) = limits.user.tokens_exceeded(&user) {
+        return BudgetDecision::Deny(format!("Your {kind} budget is used up. /usage for details."));
+    }
+    if let Some(kind) = limits.global.tokens_exceeded(&global) {
+        return BudgetDecision::Deny(format!("The bot's {kind} budget is used up. /usage for details."));
+    }
+    if let Some(kind) = limits.user.usd_exceeded(&user) {
+        return BudgetDecision::FreeOnly(format!("user {kind} budget reached"));
+    }
+    if let Some(kind) = limits.global.usd_exceeded(&global) {
+        return BudgetDecision::FreeOnly(format!("global {kind} budget reached"));
+    }
+    BudgetDecision::Allow
+}
+
+/// Log a query with its usage and cost per provider/model, where `check` will
+/// count it. `answered_by` is the (provider, model) of the last call.
+pub async fn record(
+    db: &Database,
+    prices: &PriceTable,
+    user_id: u64,
+    prompt: &str,
+    response_time_ms: u64,
+    answered_by: (&str, &str),
+    usage: &[ModelUsage],
+) {
+    let mut total = Usage::default();
+    for u in usage {
+        total.add(&u.usage);
+    }
+    let (provider, model) = answered_by;
+    let Some(query_id) = db.log_query(user_id, provider, model, prompt, response_time_ms, &total).await else {
+        return;
+    };
+    for u in usage {
+        let cost = prices.cost(&u.provider, &u.model, &u.usage);
+        db.log_query_usage(query_id, &u.provider, &u.model, &u.usage, cost).await;
+    }
+}
+
+/// Human-readable usage report for `/usage`.
+pub async fn usage_report(limits: &BudgetLimits, db: &Database, user_id: u64) -> String {
+    let mut lines = vec!["📊 Usage".to_string()];
+    let user = db.usage_totals(Some(user_id)).await;
+    lines.push(format!("Today: {}", describe(user.day_tokens, user.day_usd, limits.user.daily_tokens, limits.user.daily_usd)));
+    lines.push(format!(
+        "This month: {}",
+        describe(user.month_tokens, user.month_usd, limits.user.monthly_tokens, limits.user.monthly_usd)
+    ));
+
+    if !limits.global.is_unlimited() {
+        let global = db.usage_totals(None).await;
+        lines.push(String::new());
+        lines.push("All users".to_string());
+        lines.push(format!(
+            "Today: {}",
+            describe(global.day_tokens, global.day_usd, limits.global.daily_tokens, limits.global.daily_usd)
+        ));
+        lines.push(format!(
+            "This month: {}",
+            describe(global.month_tokens, global.month_usd, limits.global.monthly_tokens, limits.global.monthly_usd)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn describe(tokens: u64, usd: f64, token_limit: u64, usd_limit: f64) -> String {
+    let mut text = format!("{tokens} tokens, ${usd:.4}");
+    let mut remaining = Vec::new();
+    if token_limit > 0 {
+        remaining.push(format!("{} tokens", token_limit.saturating_sub(tokens)));
+    }
+    if usd_limit > 0.0 {
+        remaining.push(format!("${:.4}", (usd_limit - usd).max(0.0)));
+    }
+    if !remaining.is_empty() {
+        text.push_str(&format!(" (left: {})", remaining.join(", ")));
+    }
+    text
+}
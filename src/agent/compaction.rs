@@ -0,0 +1,154 @@
+use std::time::Instant;
+
+use tracing::{info, warn};
+
+use crate::db::Database;
+use crate::provider::{ChatOptions, LlmResponse, Message, MessageContent, ProviderError, ProviderPool, Role};
+
+use super::budget::{self, BudgetDecision, Payer};
+use super::loop_runner::ModelUsage;
+
+/// The most recent exchanges stay verbatim; only older ones are summarized.
+pub const KEEP_RECENT_PAIRS: usize = 4;
+/// Each message is cut to this many bytes in the summarization prompt.
+const MAX_MESSAGE_BYTES: usize = 2000;
+
+const SUMMARY_PROMPT: &str = "You maintain a running summary of a conversation between a user \
+and an AI assistant. Merge the previous summary (if any) and the new messages into one concise \
+summary. Keep facts about the user, decisions, names, numbers, open questions and unfinished \
+tasks; drop small talk and details that no longer matter. Write in the language of the \
+conversation, as plain text or short bullet points. Reply with the summary only.";
+
+/// Fold older messages of a session into its summary once it has at least
+/// `min_messages` unsummarized messages, keeping the last `KEEP_RECENT_PAIRS`
+/// exchanges verbatim. `provider` pins the summarizing model; otherwise free
+/// models are preferred. Returns how many messages were compacted.
+pub async fn compact_session(
+    pool: &ProviderPool,
+    db: &Database,
+    payer: Payer<'_>,
+    session_id: &str,
+    min_messages: usize,
+    provider: Option<&str>,
+) -> Result<usize, String> {
+    let messages = db.unsummarized_messages(session_id).await;
+    if messages.len() < min_messages {
+        return Ok(0);
+    }
+
+    // Split before a user message so the kept part starts a fresh exchange
+    let mut split = messages.len().saturating_sub(KEEP_RECENT_PAIRS * 2);
+    while split > 0 && messages[split].1 != "user" {
+        split -= 1;
+    }
+    if split == 0 {
+        return Ok(0);
+    }
+    let older = &messages[..split];
+
+    let mut transcript = String::new();
+    if let Some(summary) = db.session_summary(session_id).await {
+        transcript.push_str(&format!("Previous summary:\n{summary}\n\n"));
+    }
+    transcript.push_str("New messages:\n");
+    for (_, role, content) in older {
+        let speaker = match role.as_str() {
+            "user" => "User",
+            "tool" => "Tool result",
+            _ => "Assistant",
+        };
+        transcript.push_str(&format!("{speaker}: {}\n", clip(content, MAX_MESSAGE_BYTES)));
+    }
+
+    let prompt = [
+        Message {
+            role: Role::System,
+            content: MessageContent::Text(SUMMARY_PROMPT.into()),
+        },
+        Message {
+            role: Role::User,
+            content: MessageContent::Text(transcript),
+        },
+    ];
+    let (response, provider_name, model) = chat_cheaply(pool, db, payer, &prompt, provider, "[session summary]")
+        .await
+        .map_err(|e| format!("Summarization failed: {e}"))?;
+    let summary = response
+        .content
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .ok_or("Summarization returned an empty response")?;
+
+    let up_to = older[older.len() - 1].0;
+    if !db.save_session_summary(session_id, &summary, up_to).await? {
+        info!("Session {session_id} was compacted concurrently, keeping the newer summary");
+        return Ok(0);
+    }
+    info!(
+        "Compacted {} messages of session {session_id} via {provider_name}/{model} ({} tokens)",
+        older.len(),
+        response.usage.total()
+    );
+    Ok(older.len())
+}
+
+/// One tool-less call for background work: on `provider` (`provider[:model]`)
+/// when given, else on a free model, falling back to any model when none is free.
+/// Budgets apply as for agent runs: only free models once the payer's paid
+/// budget is used up, no call at all once their tokens are. The usage is
+/// logged under `purpose`.
+pub(super) async fn chat_cheaply(
+    pool: &ProviderPool,
+    db: &Database,
+    payer: Payer<'_>,
+    prompt: &[Message],
+    provider: Option<&str>,
+    purpose: &str,
+) -> Result<(LlmResponse, String, String), String> {
+    let start = Instant::now();
+    let free_only = match budget::check(payer.limits, db, pool.prices(), payer.user_id, &[]).await {
+        BudgetDecision::Allow => false,
+        BudgetDecision::FreeOnly(_) => true,
+        BudgetDecision::Deny(reason) => return Err(reason),
+    };
+    let opts = ChatOptions {
+        free_only,
+        ..Default::default()
+    };
+    let free = ChatOptions {
+        free_only: true,
+        ..opts
+    };
+    let result = match provider {
+        Some(spec) => pool.chat_with_provider(prompt, &[], spec, opts).await,
+        None => match pool.chat(prompt, &[], free).await {
+            Err(ProviderError::Unsupported(reason)) if !free_only => {
+                warn!("No free model available ({reason}), using any provider");
+                pool.chat(prompt, &[], opts).await
+            }
+            result => result,
+        },
+    };
+    let (response, provider_name, model) = result.map_err(|e| e.to_string())?;
+
+    let usage = [ModelUsage {
+        provider: provider_name.clone(),
+        model: model.clone(),
+        usage: response.usage.clone(),
+    }];
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    budget::record(db, pool.prices(), payer.user_id, purpose, elapsed_ms, (&provider_name, &model), &usage).await;
+    Ok((response, provider_name, model))
+}
+
+/// Cut `text` to about `max_bytes` on a char boundary.
+pub(super) fn clip(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
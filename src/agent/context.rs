@@ -0,0 +1,173 @@
+use tracing::{info, warn};
+
+use crate::provider::{Message, MessageContent, Role};
+
+/// Older tool results are cut down to this many bytes first.
+const OLD_RESULT_BYTES: usize = 1000;
+/// Results of the latest tool turn are never cut below this.
+const MIN_RESULT_BYTES: usize = 200;
+/// Results within this many bytes of the limit are left alone, so a result that
+/// was already cut isn't cut again just for its truncation note.
+const TRUNCATE_SLACK: usize = 100;
+/// Stand-in for tool results dropped entirely.
+const DROPPED_RESULT: &str = "[result dropped to fit the context window]";
+
+/// Shrink `messages` until `estimate` fits `budget`, least valuable content first:
+///
+/// 1. truncate tool results from earlier turns of this run,
+/// 2. drop history exchanges before the current user message, oldest first,
+/// 3. replace earlier tool results with a short placeholder,
+/// 4. truncate the latest turn's tool results, largest first.
+///
+/// The system prompt and the current user message are always kept, and tool
+/// calls are never separated from their results: only result contents change,
+/// and history is dropped a whole user exchange at a time.
+pub fn trim_to_budget(messages: &mut Vec<Message>, budget: u32, estimate: impl Fn(&[Message]) -> u32) {
+    let before = estimate(messages);
+    if before <= budget {
+        return;
+    }
+    let over = |m: &[Message]| estimate(m) > budget;
+
+    // Results at or after `latest` belong to the most recent tool turn
+    let latest_turn = |m: &[Message]| {
+        m.iter()
+            .rposition(|m| matches!(m.content, MessageContent::AssistantWithToolCalls { .. }))
+            .unwrap_or(m.len())
+    };
+
+    // 1. Earlier tool results, oldest first
+    for i in 0..latest_turn(messages) {
+        if !over(messages) {
+            break;
+        }
+        if let MessageContent::ToolResult { content, .. } = &mut messages[i].content {
+            truncate_result(content, OLD_RESULT_BYTES);
+        }
+    }
+
+    // 2. History before the current user message, one exchange at a time
+    while over(messages) {
+        let Some(current) = messages.iter().rposition(|m| m.role == Role::User) else {
+            break;
+        };
+        let Some(start) = messages[..current].iter().position(|m| m.role != Role::System) else {
+            break;
+        };
+        let end = messages[start + 1..current]
+            .iter()
+            .position(|m| m.role == Role::User)
+            .map_or(current, |p| start + 1 + p);
+        messages.drain(start..end);
+    }
+
+    // 3. Earlier tool results, replaced outright
+    for i in 0..latest_turn(messages) {
+        if !over(messages) {
+            break;
+        }
+        if let MessageContent::ToolResult { content, .. } = &mut messages[i].content {
+            *content = DROPPED_RESULT.into();
+        }
+    }
+
+    // 4. The latest results, largest first, cut by roughly the excess
+    let start = latest_turn(messages);
+    while over(messages) {
+        let excess_bytes = (estimate(messages).saturating_sub(budget) as usize) * 4;
+        let largest = messages[start..]
+            .iter_mut()
+            .filter_map(|m| match &mut m.content {
+                MessageContent::ToolResult { content, .. }
+                    if content.len() > MIN_RESULT_BYTES + TRUNCATE_SLACK =>
+                {
+                    Some(content)
+                }
+                _ => None,
+            })
+            .max_by_key(|c| c.len());
+        let Some(content) = largest else {
+            break;
+        };
+        let keep = content
+            .len()
+            .saturating_sub(excess_bytes + TRUNCATE_SLACK)
+            .max(MIN_RESULT_BYTES);
+        truncate_result(content, keep);
+    }
+
+    let after = estimate(messages);
+    if after > budget {
+        warn!("Prompt still ~{after} tokens after trimming (budget {budget})");
+    } else {
+        info!("Trimmed prompt from ~{before} to ~{after} tokens (budget {budget})");
+    }
+}
+
+/// Cut a tool result to about `max_bytes`, noting how much was removed.
+fn truncate_result(content: &mut String, max_bytes: usize) {
+    if content.len() <= max_bytes + TRUNCATE_SLACK {
+        return;
+    }
+    let mut end = max_bytes;
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    let omitted = content.len() - end;
+    content.truncate(end);
+    content.push_str(&format!("\n…[truncated {omitted} bytes to fit the context window]"));
+}
+
+/// Tool results kept in conversation history are cut to this many bytes.
+const HISTORY_RESULT_BYTES: usize = 4000;
+/// String arguments of tool calls kept in history (file contents, email
+/// bodies, ...) are cut to this many bytes.
+const HISTORY_ARGUMENT_BYTES: usize = 1000;
+
+/// The tool calls and results of a run as they should be kept in history:
+/// large results and string arguments are cut, everything else is kept so
+/// follow-up messages can refer to IDs, paths and earlier results.
+pub fn for_history(messages: &[Message]) -> Vec<Message> {
+    messages
+        .iter()
+        .cloned()
+        .map(|mut m| {
+            match &mut m.content {
+                MessageContent::ToolResult { content, .. } => {
+                    truncate_result(content, HISTORY_RESULT_BYTES);
+                }
+                MessageContent::AssistantWithToolCalls { tool_calls, .. } => {
+                    for tc in tool_calls {
+                        shrink_arguments(&mut tc.function.arguments);
+                    }
+                }
+                _ => {}
+            }
+            m
+        })
+        .collect()
+}
+
+/// Cut long string values in a tool call's JSON arguments, keeping it valid JSON.
+fn shrink_arguments(arguments: &mut String) {
+    if arguments.len() <= HISTORY_ARGUMENT_BYTES {
+        return;
+    }
+    let Ok(serde_json::Value::Object(mut args)) = serde_json::from_str(arguments) else {
+        return;
+    };
+    for value in args.values_mut() {
+        if let serde_json::Value::String(s) = value {
+            let mut end = HISTORY_ARGUMENT_BYTES.min(s.len());
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end < s.len() {
+                let omitted = s.len() - end;
+                s.truncate(end);
+                s.push_str(&format!("…[truncated {omitted} bytes]"));
+            }
+        }
+    }
+    *arguments = serde_json::Value::Object(args).to_string();
+}
@@ -0,0 +1,179 @@
+use tracing::{info, warn};
+
+use crate::db::{Database, FactMeta, SaveOutcome};
+use crate::provider::{Embedder, Message, MessageContent, ProviderPool, Role};
+
+use super::budget::Payer;
+use super::compaction::{chat_cheaply, clip};
+
+/// What to do with facts found in a finished exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractionMode {
+    Off,
+    /// Save them right away.
+    Auto,
+    /// Ask the user first.
+    Confirm,
+}
+
+impl ExtractionMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "off" | "none" | "false" | "0" => Some(Self::Off),
+            "auto" | "on" | "true" | "1" => Some(Self::Auto),
+            "confirm" | "ask" => Some(Self::Confirm),
+            _ => None,
+        }
+    }
+}
+
+/// A fact proposed by the extraction pass, not yet in memory.
+#[derive(Debug, Clone)]
+pub struct ProposedFact {
+    pub fact: String,
+    pub category: String,
+    pub importance: u8,
+    /// Embedding of `fact` from the configured embedder, if any.
+    pub embedding: Option<Vec<f32>>,
+}
+
+/// At most this many facts are taken from one exchange.
+const MAX_FACTS: usize = 5;
+/// Each side of the exchange is cut to this many bytes in the prompt.
+const MAX_TEXT_BYTES: usize = 4000;
+const CATEGORIES: [&str; 7] = ["preference", "decision", "personal", "technical", "project", "workflow", "general"];
+
+const EXTRACTION_PROMPT: &str = "You pick long-term memories from one exchange between a user \
+and an AI assistant. List durable facts about the user worth knowing in future conversations: \
+identity, preferences, decisions, projects, plans, technical setup and workflows. Skip small \
+talk, one-off questions, general knowledge, anything only relevant to this exchange and \
+anything already known. Write each fact as a short standalone sentence in the language of the \
+conversation. Reply with a JSON array only, at most 5 items, like \
+[{\"fact\": \"...\", \"category\": \"preference\", \"importance\": 3}]. Categories: preference, \
+decision, personal, technical, project, workflow, general. Importance: 1 = trivia to \
+5 = essential. Reply [] when there is nothing worth remembering.";
+
+/// Ask a cheap model for durable facts in one exchange, minus anything the
+/// user's memory already says. `provider` pins the model; otherwise free
+/// models are preferred.
+pub async fn extract_facts(
+    pool: &ProviderPool,
+    db: &Database,
+    embedder: Option<&dyn Embedder>,
+    payer: Payer<'_>,
+    user_text: &str,
+    reply: &str,
+    provider: Option<&str>,
+) -> Result<Vec<ProposedFact>, String> {
+    let user_id = payer.user_id;
+    let mut exchange = String::new();
+    let known = db.list_facts(user_id, None).await.unwrap_or_default();
+    if !known.is_empty() {
+        exchange.push_str("Already known:\n");
+        for (_, fact, _) in &known {
+            exchange.push_str(&format!("- {fact}\n"));
+        }
+        exchange.push('\n');
+    }
+    exchange.push_str(&format!(
+        "User: {}\n\nAssistant: {}",
+        clip(user_text, MAX_TEXT_BYTES),
+        clip(reply, MAX_TEXT_BYTES)
+    ));
+
+    let prompt = [
+        Message {
+            role: Role::System,
+            content: MessageContent::Text(EXTRACTION_PROMPT.into()),
+        },
+        Message {
+            role: Role::User,
+            content: MessageContent::Text(exchange),
+        },
+    ];
+    let (response, provider_name, model) = chat_cheaply(pool, db, payer, &prompt, provider, "[memory extraction]")
+        .await
+        .map_err(|e| format!("Memory extraction failed: {e}"))?;
+    let mut facts = parse_facts(response.content.as_deref().unwrap_or(""));
+    if facts.is_empty() {
+        return Ok(facts);
+    }
+
+    // Embed the batch once: the vectors serve both the duplicate check and saving
+    if let Some(embedder) = embedder {
+        let texts: Vec<String> = facts.iter().map(|f| f.fact.clone()).collect();
+        match embedder.embed(&texts).await {
+            Ok(vectors) => {
+                for (fact, vector) in facts.iter_mut().zip(vectors) {
+                    fact.embedding = Some(vector);
+                }
+            }
+            Err(e) => warn!("Failed to embed extracted memories: {e}"),
+        }
+    }
+
+    let found = facts.len();
+    let mut new = Vec::with_capacity(found);
+    for f in facts {
+        let embedding = embedder.zip(f.embedding.as_deref()).map(|(e, v)| (e.model(), v));
+        if db.find_duplicate_fact(user_id, &f.fact, embedding).await.is_none() {
+            new.push(f);
+        }
+    }
+    info!(
+        "Extracted {found} memories for user {user_id} via {provider_name}/{model}, {} new",
+        new.len()
+    );
+    Ok(new)
+}
+
+/// Save extracted facts. Returns how many were new.
+pub async fn save_facts(db: &Database, embedder: Option<&dyn Embedder>, user_id: u64, facts: &[ProposedFact]) -> usize {
+    let mut saved = 0;
+    for f in facts {
+        let meta = FactMeta {
+            category: f.category.clone(),
+            pinned: false,
+            importance: f.importance,
+            expires_in_days: None,
+        };
+        let embedding = embedder.zip(f.embedding.as_deref()).map(|(e, v)| (e.model(), v));
+        match db.save_fact(user_id, &f.fact, meta, embedding).await {
+            Ok(SaveOutcome::Saved(_)) => saved += 1,
+            Ok(SaveOutcome::Duplicate(..)) => {}
+            Err(e) => warn!("Failed to save extracted memory: {e}"),
+        }
+    }
+    saved
+}
+
+/// The JSON array in a model reply, tolerating prose or code fences around it.
+fn parse_facts(reply: &str) -> Vec<ProposedFact> {
+    let (Some(start), Some(end)) = (reply.find('['), reply.rfind(']')) else {
+        return Vec::new();
+    };
+    if end < start {
+        return Vec::new();
+    }
+    let items: Vec<serde_json::Value> = serde_json::from_str(&reply[start..=end]).unwrap_or_default();
+    items
+        .iter()
+        .filter_map(|item| {
+            let fact = item["fact"].as_str()?.trim();
+            if fact.is_empty() {
+                return None;
+            }
+            let category = item["category"]
+                .as_str()
+                .filter(|c| CATEGORIES.contains(c))
+                .unwrap_or("general");
+            Some(ProposedFact {
+                fact: fact.to_string(),
+                category: category.to_string(),
+                importance: item["importance"].as_u64().map_or(3, |v| v.clamp(1, 5) as u8),
+                embedding: None,
+            })
+        })
+        .take(MAX_FACTS)
+        .collect()
+}
@@ -1,13 +1,11 @@
-use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use tracing::{debug, info, warn};
 
-use crate::db::Database;
-use crate::provider::{Message, MessageContent, ProviderPool, Role};
-use crate::tools::gmail::GmailCreds;
-use crate::tools::claude_code::ClaudeCodeManager;
+use crate::provider::{ChatOptions, Message, MessageContent, ProviderPool, Role, StreamEvent, Usage};
 
-use super::tool_registry::ToolRegistry;
+use super::budget::{self, BudgetDecision, BudgetLimits};
+use super::context;
+use super::tool_registry::{ToolContext, ToolRegistry};
 
 /// Progress updates sent during agent execution.
 pub enum AgentProgress {
@@ -15,6 +13,9 @@ pub enum AgentProgress {
     ToolUse(String),
     /// LLM is being called (new turn starting).
     Thinking,
+    /// A chunk of streamed text. The chunks since the last `Thinking` make up
+    /// the current turn's text so far.
+    Streaming(String),
 }
 
 /// Result of an agent loop execution.
@@ -25,7 +26,59 @@ pub struct AgentResult {
     /// Counts for each tool (parallel to tools_used).
     pub tools_count: Vec<usize>,
     pub provider: String,
+    /// Model used for the final turn.
+    pub model: String,
     pub turns: usize,
+    /// Token usage summed over all turns, per provider/model in first-use order.
+    pub usage: Vec<ModelUsage>,
+    /// Tool calls and results of this run in order, trimmed for keeping in
+    /// history. The final reply is `response`, not part of it.
+    pub transcript: Vec<Message>,
+}
+
+/// A failed agent run. Provider calls that completed before the failure
+/// still cost tokens, so their usage comes along to be logged.
+#[derive(Debug)]
+pub struct AgentError {
+    pub message: String,
+    pub usage: Vec<ModelUsage>,
+}
+
+impl std::fmt::Display for AgentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Token usage attributed to one provider/model during an agent run.
+#[derive(Debug)]
+pub struct ModelUsage {
+    pub provider: String,
+    pub model: String,
+    pub usage: Usage,
+}
+
+impl AgentResult {
+    /// Usage summed over every provider/model.
+    pub fn total_usage(&self) -> Usage {
+        let mut total = Usage::default();
+        for u in &self.usage {
+            total.add(&u.usage);
+        }
+        total
+    }
+}
+
+/// Everything an agent run depends on besides the conversation itself.
+pub struct AgentContext<'a> {
+    pub pool: &'a ProviderPool,
+    pub tools: ToolContext<'a>,
+    pub max_turns: usize,
+    /// Provider chosen with /model; tried instead of the pool's own order.
+    pub preferred_provider: Option<&'a str>,
+    pub budget_limits: &'a BudgetLimits,
+    /// Set by /stop; checked before every turn.
+    pub cancel_flag: &'a AtomicBool,
 }
 
 pub struct AgentLoop;
@@ -34,28 +87,33 @@ impl AgentLoop {
     /// Run the agent loop: send messages to LLM, execute tool calls, repeat.
     /// Calls `on_progress` between turns so the caller can update the UI.
     pub async fn run<F>(
-        pool: &ProviderPool,
+        ctx: &AgentContext<'_>,
         system_prompt: &str,
         user_content: MessageContent,
-        user_id: u64,
-        db: &Database,
-        gmail_creds: &GmailCreds,
-        system_tools_enabled: bool,
-        working_dir: &str,
-        bash_timeout: u64,
-        max_turns: usize,
         history: Vec<Message>,
-        preferred_provider: Option<&str>,
-        cc_manager: Option<&ClaudeCodeManager>,
-        cancel_flag: &Arc<AtomicBool>,
         on_progress: F,
-    ) -> Result<AgentResult, String>
+    ) -> Result<AgentResult, AgentError>
     where
-        F: Fn(AgentProgress),
+        F: Fn(AgentProgress) + Send + Sync,
     {
-        let tools = ToolRegistry::definitions(gmail_creds.is_configured(), system_tools_enabled, cc_manager.is_some());
+        let AgentContext {
+            pool,
+            tools: tool_ctx,
+            max_turns,
+            preferred_provider,
+            budget_limits,
+            cancel_flag,
+        } = *ctx;
+        let ToolContext { user_id, db, .. } = tool_ctx;
+        let tools = ToolRegistry::definitions(
+            tool_ctx.gmail_creds.is_configured(),
+            tool_ctx.system_tools_enabled,
+            tool_ctx.cc_manager.is_some(),
+        );
         let mut tools_used: Vec<String> = Vec::new();
         let mut last_provider = String::new();
+        let mut last_model = String::new();
+        let mut usage: Vec<ModelUsage> = Vec::new();
 
         // Build messages: system + history + current user message
         let mut messages = vec![Message {
@@ -88,39 +146,89 @@ impl AgentLoop {
                     tools_used: deduped,
                     tools_count: counts,
                     provider: last_provider.clone(),
+                    model: last_model.clone(),
                     turns: turn,
+                    usage,
+                    transcript: run_transcript(&messages),
                 });
             }
 
             debug!("Agent turn {}/{}", turn + 1, max_turns);
             on_progress(AgentProgress::Thinking);
 
-            let (response, provider_name) = match preferred_provider {
-                Some(name) => pool.chat_with_provider(&messages, &tools, name).await,
-                None => pool.chat(&messages, &tools).await,
+            // Pass text chunks on as they arrive so the caller can render them live
+            let on_event = |event: StreamEvent| match event {
+                StreamEvent::TextDelta(delta) => on_progress(AgentProgress::Streaming(delta)),
+                // Tool progress is reported once, where the call is executed
+                StreamEvent::ToolCall(_) => {}
+                // Retried or fell back: the partial text belongs to a failed attempt
+                StreamEvent::Restart => on_progress(AgentProgress::Thinking),
+            };
+
+            // Enforce budgets before every call, counting this run's usage so far
+            let free_only = match budget::check(budget_limits, db, pool.prices(), user_id, &usage).await {
+                BudgetDecision::Allow => false,
+                BudgetDecision::FreeOnly(reason) => {
+                    info!("Restricting user {user_id} to free providers: {reason}");
+                    true
+                }
+                BudgetDecision::Deny(message) => return Err(AgentError { message, usage }),
+            };
+            let opts = ChatOptions {
+                on_event: Some(&on_event),
+                free_only,
+                ..Default::default()
+            };
+
+            // Keep the prompt within the context window of the model about to be used
+            if let Some(ctx) = pool.context_budget(&messages, &tools, preferred_provider, opts) {
+                context::trim_to_budget(&mut messages, ctx.max_tokens, |m| ctx.estimate(m, &tools));
             }
-            .map_err(|e| format!("LLM error: {e}"))?;
+
+            let reply = match preferred_provider {
+                Some(name) => pool.chat_with_provider(&messages, &tools, name, opts).await,
+                None => pool.chat(&messages, &tools, opts).await,
+            };
+            let (response, provider_name, model) = match reply {
+                Ok(reply) => reply,
+                Err(e) => {
+                    return Err(AgentError {
+                        message: format!("LLM error: {e}"),
+                        usage,
+                    });
+                }
+            };
 
             last_provider = provider_name;
+            last_model = model;
+            record_usage(&mut usage, &last_provider, &last_model, &response.usage);
 
             // If no tool calls, return the text content
             if response.tool_calls.is_empty() {
                 let content = response.content.unwrap_or_default();
-                info!(
-                    "Agent completed in {} turns via {} ({} + {} tokens)",
-                    turn + 1,
-                    last_provider,
-                    response.usage.prompt_tokens,
-                    response.usage.completion_tokens
-                );
                 let (deduped, counts) = dedup_with_counts(&tools_used);
-                return Ok(AgentResult {
+                let result = AgentResult {
                     response: content,
                     tools_used: deduped,
                     tools_count: counts,
                     provider: last_provider,
+                    model: last_model,
                     turns: turn + 1,
-                });
+                    usage,
+                    transcript: run_transcript(&messages),
+                };
+                let total = result.total_usage();
+                info!(
+                    "Agent completed in {} turns via {}/{} ({} + {} tokens, {} cache read, {} cache write)",
+                    result.turns,
+                    result.provider,
+                    result.model,
+                    total.prompt_tokens,
+                    total.completion_tokens,
+                    total.cache_read_tokens,
+                    total.cache_write_tokens
+                );
+                return Ok(result);
             }
 
             // Add assistant message with tool calls to history
@@ -141,17 +249,7 @@ impl AgentLoop {
                 tools_used.push(tool_name.clone());
                 on_progress(AgentProgress::ToolUse(tool_name.clone()));
 
-                let result = ToolRegistry::execute(
-                    tool_name,
-                    &tc.function.arguments,
-                    user_id,
-                    db,
-                    gmail_creds,
-                    working_dir,
-                    bash_timeout,
-                    cc_manager,
-                )
-                .await;
+                let result = ToolRegistry::execute(tool_name, &tc.function.arguments, tool_ctx).await;
 
                 messages.push(Message {
                     role: Role::Tool,
@@ -178,11 +276,35 @@ impl AgentLoop {
             tools_used: deduped,
             tools_count: counts,
             provider: last_provider,
+            model: last_model,
             turns: max_turns,
+            usage,
+            transcript: run_transcript(&messages),
         })
     }
 }
 
+/// Messages after the current user message, ready to be stored in history.
+fn run_transcript(messages: &[Message]) -> Vec<Message> {
+    let start = messages
+        .iter()
+        .rposition(|m| m.role == Role::User)
+        .map_or(messages.len(), |i| i + 1);
+    context::for_history(&messages[start..])
+}
+
+/// Add one turn's usage to the running per-provider/model totals.
+fn record_usage(totals: &mut Vec<ModelUsage>, provider: &str, model: &str, usage: &Usage) {
+    match totals.iter_mut().find(|u| u.provider == provider && u.model == model) {
+        Some(entry) => entry.usage.add(usage),
+        None => totals.push(ModelUsage {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            usage: usage.clone(),
+        }),
+    }
+}
+
 /// Deduplicate a list of tool names while counting occurrences.
 fn dedup_with_counts(tools: &[String]) -> (Vec<String>, Vec<usize>) {
     use std::collections::BTreeMap;
@@ -194,3 +316,218 @@ fn dedup_with_counts(tools: &[String]) -> (Vec<String>, Vec<usize>) {
     let cnts: Vec<usize> = counts.values().copied().collect();
     (names, cnts)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::db::Database;
+    use crate::provider::{LlmResponse, ProviderError, ToolCall, ToolCallFunction};
+    use crate::provider::cassette::ScriptedProvider;
+    use crate::tools::gmail::GmailCreds;
+
+    const USER: u64 = 42;
+
+    fn pool_with(provider: ScriptedProvider) -> ProviderPool {
+        let mut pool = ProviderPool::for_tests("scripted", false);
+        pool.register(Box::new(provider), vec!["key".into()], vec!["model".into()])
+            .unwrap();
+        pool
+    }
+
+    /// Run one message through the loop, returning the result and every progress update.
+    async fn run(pool: &ProviderPool, db: &Database) -> (AgentResult, Vec<AgentProgress>) {
+        let (result, progress) = run_within(pool, db, &BudgetLimits::default()).await;
+        (result.unwrap(), progress)
+    }
+
+    async fn run_within(
+        pool: &ProviderPool,
+        db: &Database,
+        budget_limits: &BudgetLimits,
+    ) -> (Result<AgentResult, AgentError>, Vec<AgentProgress>) {
+        let gmail_creds = GmailCreds {
+            client_id: String::new(),
+            client_secret: String::new(),
+            refresh_token: String::new(),
+        };
+        let cancel_flag = AtomicBool::new(false);
+        let ctx = AgentContext {
+            pool,
+            tools: ToolContext {
+                user_id: USER,
+                db,
+                embedder: None,
+                gmail_creds: &gmail_creds,
+                system_tools_enabled: false,
+                working_dir: ".",
+                bash_timeout: 5,
+                cc_manager: None,
+            },
+            max_turns: 5,
+            preferred_provider: None,
+            budget_limits,
+            cancel_flag: &cancel_flag,
+        };
+        let progress = Mutex::new(Vec::new());
+        let result = AgentLoop::run(
+            &ctx,
+            "You are a test.",
+            MessageContent::Text("Hello".into()),
+            Vec::new(),
+            |p| progress.lock().unwrap().push(p),
+        )
+        .await;
+        (result, progress.into_inner().unwrap())
+    }
+
+    /// Text streamed since the last `Thinking`, as the Telegram handler rebuilds it.
+    fn streamed_text(progress: &[AgentProgress]) -> String {
+        let start = progress
+            .iter()
+            .rposition(|p| matches!(p, AgentProgress::Thinking))
+            .map_or(0, |i| i + 1);
+        progress[start..]
+            .iter()
+            .filter_map(|p| match p {
+                AgentProgress::Streaming(delta) => Some(delta.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn text_turn_returns_the_reply() {
+        let db = Database::open(":memory:").unwrap();
+        let pool = pool_with(ScriptedProvider::new("scripted").text("Hi there"));
+
+        let (result, progress) = run(&pool, &db).await;
+
+        assert_eq!(result.response, "Hi there");
+        assert_eq!(result.turns, 1);
+        assert_eq!(result.provider, "scripted");
+        assert!(result.tools_used.is_empty());
+        assert!(result.transcript.is_empty());
+        assert_eq!(streamed_text(&progress), "Hi there");
+    }
+
+    #[tokio::test]
+    async fn tool_call_turn_runs_the_tool_and_continues() {
+        let db = Database::open(":memory:").unwrap();
+        let pool = pool_with(
+            ScriptedProvider::new("scripted")
+                .tool_call("memory_save", serde_json::json!({ "fact": "Likes green tea" }))
+                .text("Noted."),
+        );
+
+        let (result, progress) = run(&pool, &db).await;
+
+        assert_eq!(result.response, "Noted.");
+        assert_eq!(result.turns, 2);
+        assert_eq!(result.tools_used, ["memory_save"]);
+        assert_eq!(result.tools_count, [1]);
+        let tool_events: Vec<_> = progress.iter().filter(|p| matches!(p, AgentProgress::ToolUse(_))).collect();
+        assert!(matches!(tool_events[..], [AgentProgress::ToolUse(name)] if name == "memory_save"));
+        // The call and its result are kept for the session history
+        assert!(matches!(
+            &result.transcript[..],
+            [
+                Message { content: MessageContent::AssistantWithToolCalls { .. }, .. },
+                Message { content: MessageContent::ToolResult { name, .. }, .. },
+            ] if name == "memory_save"
+        ));
+        let facts = db.list_facts(USER, None).await.unwrap();
+        assert_eq!(facts.len(), 1);
+        assert_eq!(facts[0].1, "Likes green tea");
+    }
+
+    /// A tool call that used `tokens_in` + `tokens_out` tokens.
+    fn paid_tool_call(tokens_in: u32, tokens_out: u32) -> LlmResponse {
+        LlmResponse {
+            content: None,
+            tool_calls: vec![ToolCall {
+                id: "call_0".into(),
+                function: ToolCallFunction {
+                    name: "get_datetime".into(),
+                    arguments: "{}".into(),
+                },
+            }],
+            usage: Usage {
+                prompt_tokens: tokens_in,
+                completion_tokens: tokens_out,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_run_keeps_the_usage_of_completed_turns() {
+        let db = Database::open(":memory:").unwrap();
+        let pool = pool_with(
+            ScriptedProvider::new("scripted")
+                .respond(paid_tool_call(1000, 50))
+                .fail(ProviderError::RequestError("HTTP 400".into())),
+        );
+
+        let (result, _) = run_within(&pool, &db, &BudgetLimits::default()).await;
+
+        let Err(err) = result else {
+            panic!("the run should fail");
+        };
+        assert!(err.message.starts_with("LLM error"), "{err}");
+        assert_eq!(err.usage.len(), 1);
+        assert_eq!((err.usage[0].provider.as_str(), err.usage[0].model.as_str()), ("scripted", "model"));
+        assert_eq!(err.usage[0].usage.total(), 1050);
+    }
+
+    #[tokio::test]
+    async fn failed_runs_count_toward_the_budget() {
+        let db = Database::open(":memory:").unwrap();
+        let limits = BudgetLimits {
+            user: budget::Limits {
+                daily_tokens: 1000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let failing = pool_with(
+            ScriptedProvider::new("scripted")
+                .respond(paid_tool_call(1000, 50))
+                .fail(ProviderError::RequestError("HTTP 400".into())),
+        );
+        let (result, _) = run_within(&failing, &db, &limits).await;
+        let Err(err) = result else {
+            panic!("the run should fail");
+        };
+        let last = err.usage.last().unwrap();
+        budget::record(&db, failing.prices(), USER, "Hello", 0, (&last.provider, &last.model), &err.usage).await;
+
+        let pool = pool_with(ScriptedProvider::new("scripted").text("Hi"));
+        let (result, _) = run_within(&pool, &db, &limits).await;
+        let Err(err) = result else {
+            panic!("the daily token budget should be used up");
+        };
+        assert!(err.message.contains("daily token budget"), "{err}");
+        assert!(err.usage.is_empty());
+    }
+
+    #[tokio::test]
+    async fn transient_failure_is_retried_and_restarts_the_stream() {
+        let db = Database::open(":memory:").unwrap();
+        let pool = pool_with(
+            ScriptedProvider::new("scripted")
+                .fail(ProviderError::Transient("HTTP 503".into()))
+                .text("Recovered"),
+        );
+
+        let (result, progress) = run(&pool, &db).await;
+
+        assert_eq!(result.response, "Recovered");
+        assert_eq!(result.turns, 1);
+        // One `Thinking` for the turn, one for the restarted attempt
+        let thinking = progress.iter().filter(|p| matches!(p, AgentProgress::Thinking)).count();
+        assert_eq!(thinking, 2);
+        assert_eq!(streamed_text(&progress), "Recovered");
+    }
+}
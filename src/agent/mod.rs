@@ -1,4 +1,10 @@
+pub mod budget;
+pub mod compaction;
+mod context;
+pub mod extraction;
+pub mod titles;
 mod loop_runner;
 mod tool_registry;
 
-pub use loop_runner::{AgentLoop, AgentProgress};
+pub use loop_runner::{AgentContext, AgentLoop, AgentProgress};
+pub use tool_registry::ToolContext;
@@ -0,0 +1,50 @@
+use crate::db::Database;
+use crate::provider::{Message, MessageContent, ProviderPool, Role};
+
+use super::budget::Payer;
+use super::compaction::{chat_cheaply, clip};
+
+/// Titles longer than this many characters are cut.
+const MAX_TITLE_CHARS: usize = 60;
+/// Each side of the exchange is cut to this many bytes in the prompt.
+const MAX_TEXT_BYTES: usize = 1500;
+
+const TITLE_PROMPT: &str = "Write a short title (at most 6 words) for a conversation that \
+starts with the exchange below, in the language of the conversation. Reply with the title only, \
+without quotes or trailing punctuation.";
+
+/// Name a session after its first exchange using a cheap model, falling back
+/// to the start of the user's message when the model fails.
+pub async fn session_title(pool: &ProviderPool, db: &Database, payer: Payer<'_>, user_text: &str, reply: &str) -> String {
+    let prompt = [
+        Message {
+            role: Role::System,
+            content: MessageContent::Text(TITLE_PROMPT.into()),
+        },
+        Message {
+            role: Role::User,
+            content: MessageContent::Text(format!(
+                "User: {}\n\nAssistant: {}",
+                clip(user_text, MAX_TEXT_BYTES),
+                clip(reply, MAX_TEXT_BYTES)
+            )),
+        },
+    ];
+    let generated = chat_cheaply(pool, db, payer, &prompt, None, "[session title]")
+        .await
+        .ok()
+        .and_then(|(response, _, _)| response.content)
+        .map(|t| t.trim().trim_matches(['"', '\'', '*', '.']).trim().to_string())
+        .filter(|t| !t.is_empty() && !t.contains('\n'));
+    shorten(generated.as_deref().unwrap_or(user_text))
+}
+
+/// First line of `text`, cut to `MAX_TITLE_CHARS` characters.
+fn shorten(text: &str) -> String {
+    let line = text.trim().lines().next().unwrap_or("").trim();
+    if line.chars().count() <= MAX_TITLE_CHARS {
+        return line.to_string();
+    }
+    let cut: String = line.chars().take(MAX_TITLE_CHARS).collect();
+    format!("{}…", cut.trim_end())
+}
@@ -1,10 +1,24 @@
 use serde_json::json;
 
-use crate::provider::{ToolDef, FunctionDef};
+use crate::db::{Database, FactMeta, FactUpdate};
+use crate::provider::{Embedder, ToolDef, FunctionDef};
 use crate::tools;
 use crate::tools::gmail::GmailCreds;
 use crate::tools::claude_code::ClaudeCodeManager;
 
+/// What tools need to act for one user.
+#[derive(Clone, Copy)]
+pub struct ToolContext<'a> {
+    pub user_id: u64,
+    pub db: &'a Database,
+    pub embedder: Option<&'a dyn Embedder>,
+    pub gmail_creds: &'a GmailCreds,
+    pub system_tools_enabled: bool,
+    pub working_dir: &'a str,
+    pub bash_timeout: u64,
+    pub cc_manager: Option<&'a ClaudeCodeManager>,
+}
+
 /// Registry of all available tools with definitions and executor
 pub struct ToolRegistry;
 
@@ -35,7 +49,7 @@ impl ToolRegistry {
             ),
             // --- Memory ---
             tool_def("memory_save",
-                "Save an important fact to long-term memory for future conversations.",
+                "Save an important fact to long-term memory for future conversations. Facts already in memory are not saved again; use memory_update to change them.",
                 json!({
                     "type": "object",
                     "properties": {
@@ -44,17 +58,75 @@ impl ToolRegistry {
                             "type": "string",
                             "enum": ["preference", "decision", "personal", "technical", "project", "workflow", "general"],
                             "description": "Category of the fact"
+                        },
+                        "pinned": {
+                            "type": "boolean",
+                            "description": "Always include this fact in context, not only when relevant. Only for core facts (name, language, key preferences)"
+                        },
+                        "importance": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": 5,
+                            "description": "1 = trivia, 3 = normal (default), 5 = essential"
+                        },
+                        "expires_in_days": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Forget the fact after this many days (for temporary facts like travel plans or deadlines). Omit to keep it forever"
                         }
                     },
                     "required": ["fact"]
                 }),
             ),
+            tool_def("memory_update",
+                "Change a saved memory (e.g. when a fact becomes outdated or the user corrects it). Only the given fields change.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer", "description": "The memory fact ID to update" },
+                        "fact": { "type": "string", "description": "New text of the fact" },
+                        "category": {
+                            "type": "string",
+                            "enum": ["preference", "decision", "personal", "technical", "project", "workflow", "general"],
+                            "description": "New category"
+                        },
+                        "pinned": { "type": "boolean", "description": "Always include this fact in context" },
+                        "importance": { "type": "integer", "minimum": 1, "maximum": 5, "description": "1 = trivia, 5 = essential" },
+                        "expires_in_days": {
+                            "type": "integer",
+                            "minimum": 0,
+                            "description": "Forget the fact this many days from now; 0 keeps it forever"
+                        }
+                    },
+                    "required": ["id"]
+                }),
+            ),
+            tool_def("memory_merge",
+                "Merge several memories that overlap or duplicate each other into one fact. The first ID is kept, the others are deleted.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "ids": {
+                            "type": "array",
+                            "items": { "type": "integer" },
+                            "description": "IDs of the memories to merge (at least two)"
+                        },
+                        "fact": { "type": "string", "description": "Text of the merged fact, combining what the memories say" },
+                        "category": {
+                            "type": "string",
+                            "enum": ["preference", "decision", "personal", "technical", "project", "workflow", "general"],
+                            "description": "Category of the merged fact (default: that of the first memory)"
+                        }
+                    },
+                    "required": ["ids", "fact"]
+                }),
+            ),
             tool_def("memory_search",
-                "Search long-term memory for previously saved facts.",
+                "Search long-term memory for previously saved facts. Matches keywords and, when available, meaning (paraphrases, other languages, missing diacritics).",
                 json!({
                     "type": "object",
                     "properties": {
-                        "keyword": { "type": "string", "description": "Keyword to search for" }
+                        "keyword": { "type": "string", "description": "Keywords or a short description of what to find" }
                     },
                     "required": ["keyword"]
                 }),
@@ -78,6 +150,17 @@ impl ToolRegistry {
                     "required": ["id"]
                 }),
             ),
+            tool_def("conversation_search",
+                "Full-text search over everything said in past conversations with this user (all sessions). Use it when the user refers to an earlier discussion, decision or result that is not in the current conversation. Returns matching excerpts with session and time.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "query": { "type": "string", "description": "Keywords to look for, e.g. 'backup script cron'" },
+                        "days": { "type": "integer", "description": "Only messages from the last N days (e.g. 7 for 'last week')" }
+                    },
+                    "required": ["query"]
+                }),
+            ),
             // --- Datetime ---
             tool_def("get_datetime",
                 "Get current date and time in UTC and common timezones (Vietnam, US Eastern).",
@@ -404,16 +487,17 @@ impl ToolRegistry {
     }
 
     /// Execute a tool by name with given arguments
-    pub async fn execute(
-        tool_name: &str,
-        args_json: &str,
-        user_id: u64,
-        db: &crate::db::Database,
-        gmail_creds: &GmailCreds,
-        working_dir: &str,
-        bash_timeout: u64,
-        cc_manager: Option<&ClaudeCodeManager>,
-    ) -> String {
+    pub async fn execute(tool_name: &str, args_json: &str, ctx: ToolContext<'_>) -> String {
+        let ToolContext {
+            user_id,
+            db,
+            embedder,
+            gmail_creds,
+            working_dir,
+            bash_timeout,
+            cc_manager,
+            ..
+        } = ctx;
         let args: serde_json::Value = serde_json::from_str(args_json).unwrap_or_default();
 
         match tool_name {
@@ -430,11 +514,37 @@ impl ToolRegistry {
             "memory_save" => {
                 let fact = args["fact"].as_str().unwrap_or("");
                 let category = args["category"].as_str().unwrap_or("general");
-                tools::memory_save(db, user_id, fact, category).await
+                let meta = FactMeta {
+                    category: category.to_string(),
+                    pinned: args["pinned"].as_bool().unwrap_or(false),
+                    importance: args["importance"].as_u64().map_or(3, |v| v.clamp(1, 5) as u8),
+                    expires_in_days: args["expires_in_days"].as_u64().filter(|d| *d > 0).map(|d| d.min(36_500) as u32),
+                };
+                tools::memory_save(db, embedder, user_id, fact, meta).await
+            }
+            "memory_update" => {
+                let id = args["id"].as_i64().unwrap_or(0);
+                let update = FactUpdate {
+                    fact: args["fact"].as_str().map(str::to_string),
+                    category: args["category"].as_str().map(str::to_string),
+                    importance: args["importance"].as_u64().map(|v| v.clamp(1, 5) as u8),
+                    pinned: args["pinned"].as_bool(),
+                    expires_in_days: args["expires_in_days"].as_u64().map(|d| d.min(36_500) as u32),
+                };
+                tools::memory_update(db, embedder, user_id, id, update).await
+            }
+            "memory_merge" => {
+                let ids: Vec<i64> = args["ids"]
+                    .as_array()
+                    .map(|a| a.iter().filter_map(|v| v.as_i64()).collect())
+                    .unwrap_or_default();
+                let fact = args["fact"].as_str().unwrap_or("");
+                let category = args["category"].as_str();
+                tools::memory_merge(db, embedder, user_id, &ids, fact, category).await
             }
             "memory_search" => {
                 let keyword = args["keyword"].as_str().unwrap_or("");
-                tools::memory_search(db, user_id, keyword).await
+                tools::memory_search(db, embedder, user_id, keyword).await
             }
             "memory_list" => {
                 let category = args["category"].as_str();
@@ -444,6 +554,11 @@ impl ToolRegistry {
                 let id = args["id"].as_i64().unwrap_or(0);
                 tools::memory_delete(db, user_id, id).await
             }
+            "conversation_search" => {
+                let query = args["query"].as_str().unwrap_or("");
+                let days = args["days"].as_u64().map(|d| d.min(36500) as u32);
+                tools::conversation_search(db, user_id, query, days).await
+            }
             // --- Datetime ---
             "get_datetime" => tools::get_datetime().await,
             // --- Plan ---
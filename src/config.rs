@@ -1,5 +1,10 @@
 use std::env;
+use std::time::Duration;
 
+use crate::agent::budget::{BudgetLimits, Limits};
+use crate::agent::extraction::ExtractionMode;
+use crate::db::{MemoryContextLimits, RetentionPolicy};
+use crate::provider::{CapabilityOverrides, OpenAiCompatConfig, PriceTable, RetryPolicy};
 use crate::tools::gmail::GmailCreds;
 
 #[derive(Debug, Clone)]
@@ -13,11 +18,55 @@ pub struct Config {
     pub groq_keys: Vec<String>,
     pub mistral_keys: Vec<String>,
 
+    // Model catalogs per provider (first = default; empty = built-in default)
+    pub claude_models: Vec<String>,
+    pub gemini_models: Vec<String>,
+    pub groq_models: Vec<String>,
+    pub mistral_models: Vec<String>,
+
+    // Extra OpenAI-compatible endpoints (Ollama, llama.cpp, vLLM, OpenRouter, ...)
+    pub openai_compat: Vec<OpenAiCompatConfig>,
+    /// Overrides for the built-in per-model capability guesses
+    pub capabilities: CapabilityOverrides,
+    /// Describe tools in the prompt for models without native function calling
+    pub tool_emulation: bool,
+    /// Embeddings backend for semantic memory search: `gemini`, `mistral`,
+    /// an OpenAI-compatible provider name, or `none`
+    pub embeddings_provider: Option<String>,
+    pub embeddings_model: Option<String>,
+
+    /// Persist key cooldowns in SQLite so they survive restarts
+    pub persist_key_state: bool,
+    /// Retry policy for transient provider errors
+    pub retry: RetryPolicy,
+    /// Record every provider call to this cassette file
+    pub provider_record: Option<String>,
+    /// Serve provider calls from this cassette instead of live APIs
+    pub provider_replay: Option<String>,
+
+    // Spending control
+    pub prices: PriceTable,
+    pub budget: BudgetLimits,
+
     // Defaults
     pub default_provider: String,
     pub max_agent_turns: usize,
+    /// Summarize older history once a session has this many unsummarized messages (0 = off)
+    pub compact_threshold: usize,
+    /// `provider[:model]` used for summaries; free models are preferred when unset
+    pub compact_provider: Option<String>,
     #[allow(dead_code)]
     pub max_queue_depth: usize,
+    /// How many saved facts go into each system prompt
+    pub memory_context: MemoryContextLimits,
+    /// Look for facts worth remembering after each reply
+    pub memory_extraction: ExtractionMode,
+    /// `provider[:model]` used for extraction; free models are preferred when unset
+    pub extraction_provider: Option<String>,
+    /// What history and logs to delete in the background
+    pub retention: RetentionPolicy,
+    /// Hours between pruning/vacuum passes (0 = off)
+    pub prune_interval_hours: u64,
 
     // Google OAuth (Gmail + Sheets)
     pub gmail_creds: GmailCreds,
@@ -50,15 +99,67 @@ impl Config {
             gemini_keys: parse_keys("GEMINI_API_KEYS"),
             groq_keys: parse_keys("GROQ_API_KEYS"),
             mistral_keys: parse_keys("MISTRAL_API_KEYS"),
+            claude_models: parse_keys("CLAUDE_MODELS"),
+            gemini_models: parse_keys("GEMINI_MODELS"),
+            groq_models: parse_keys("GROQ_MODELS"),
+            mistral_models: parse_keys("MISTRAL_MODELS"),
+            openai_compat: parse_openai_compat(),
+            capabilities: CapabilityOverrides::parse(&env::var("MODEL_CAPABILITIES").unwrap_or_default()),
+            tool_emulation: env::var("TOOL_EMULATION")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(true),
+            embeddings_provider: env::var("EMBEDDINGS_PROVIDER")
+                .ok()
+                .map(|v| v.trim().to_lowercase())
+                .filter(|v| !v.is_empty()),
+            embeddings_model: env::var("EMBEDDINGS_MODEL").ok().filter(|v| !v.is_empty()),
+            persist_key_state: env::var("PERSIST_KEY_STATE")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(true),
+            retry: parse_retry_policy(),
+            provider_record: env::var("PROVIDER_RECORD").ok().filter(|v| !v.is_empty()),
+            provider_replay: env::var("PROVIDER_REPLAY").ok().filter(|v| !v.is_empty()),
+            prices: PriceTable::with_overrides(&env::var("MODEL_PRICES").unwrap_or_default()),
+            budget: BudgetLimits {
+                user: parse_limits("BUDGET_USER"),
+                global: parse_limits("BUDGET_GLOBAL"),
+            },
             default_provider: env::var("DEFAULT_PROVIDER").unwrap_or_else(|_| "gemini".into()),
             max_agent_turns: env::var("MAX_AGENT_TURNS")
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(10),
+            compact_threshold: env::var("COMPACT_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20),
+            compact_provider: env::var("COMPACT_PROVIDER").ok().filter(|v| !v.is_empty()),
             max_queue_depth: env::var("MAX_QUEUE_DEPTH")
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(3),
+            memory_context: MemoryContextLimits {
+                top_k: env::var("MEMORY_TOP_K")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(8),
+                max_tokens: env::var("MEMORY_CONTEXT_TOKENS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(800),
+            },
+            memory_extraction: env::var("MEMORY_EXTRACTION")
+                .ok()
+                .and_then(|v| ExtractionMode::parse(&v))
+                .unwrap_or(ExtractionMode::Off),
+            extraction_provider: env::var("MEMORY_EXTRACTION_PROVIDER").ok().filter(|v| !v.is_empty()),
+            retention: RetentionPolicy {
+                session_days: num_var("SESSION_RETENTION_DAYS", 0),
+                max_session_messages: num_var("SESSION_MAX_MESSAGES", 0),
+                query_log_days: num_var("QUERY_LOG_RETENTION_DAYS", 0),
+                max_query_logs: num_var("QUERY_LOG_MAX_ROWS", 0),
+            },
+            prune_interval_hours: num_var("PRUNE_INTERVAL_HOURS", 24),
             gmail_creds: GmailCreds {
                 client_id: env::var("GMAIL_CLIENT_ID").unwrap_or_default(),
                 client_secret: env::var("GMAIL_CLIENT_SECRET").unwrap_or_default(),
@@ -85,6 +186,60 @@ impl Config {
     }
 }
 
+/// A numeric env var, or `default` when unset or invalid.
+fn num_var<T: std::str::FromStr>(var: &str, default: T) -> T {
+    env::var(var).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+/// Retry policy from `RETRY_*` env vars; unset or invalid values keep the defaults.
+fn parse_retry_policy() -> RetryPolicy {
+    let defaults = RetryPolicy::default();
+    let num = |var: &str| env::var(var).ok().and_then(|v| v.trim().parse::<u64>().ok());
+    RetryPolicy {
+        max_attempts: num("RETRY_MAX_ATTEMPTS")
+            .map(|n| n.clamp(1, 10) as u32)
+            .unwrap_or(defaults.max_attempts),
+        base_delay: num("RETRY_BASE_DELAY_MS")
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.base_delay),
+        max_delay: num("RETRY_MAX_DELAY_MS")
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.max_delay),
+        jitter: env::var("RETRY_JITTER")
+            .ok()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|j| (0.0..=1.0).contains(j))
+            .unwrap_or(defaults.jitter),
+        deadline: num("RETRY_DEADLINE_SECS")
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.deadline),
+    }
+}
+
+/// Budget limits from `<PREFIX>_DAILY_TOKENS`, `_MONTHLY_TOKENS`, `_DAILY_USD`
+/// and `_MONTHLY_USD`; unset means unlimited.
+fn parse_limits(prefix: &str) -> Limits {
+    let tokens = |suffix: &str| {
+        env::var(format!("{prefix}_{suffix}"))
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+    let usd = |suffix: &str| {
+        env::var(format!("{prefix}_{suffix}"))
+            .ok()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|v| v.is_finite())
+            .unwrap_or(0.0)
+    };
+    Limits {
+        daily_tokens: tokens("DAILY_TOKENS"),
+        monthly_tokens: tokens("MONTHLY_TOKENS"),
+        daily_usd: usd("DAILY_USD"),
+        monthly_usd: usd("MONTHLY_USD"),
+    }
+}
+
 /// Expand `~` to home directory. Works on both macOS and Linux.
 fn expand_tilde(path: &str) -> String {
     if path == "~" || path.starts_with("~/") {
@@ -103,3 +258,59 @@ fn parse_keys(env_var: &str) -> Vec<String> {
         .filter(|s| !s.is_empty())
         .collect()
 }
+
+/// Parse extra OpenAI-compatible providers.
+/// `OPENAI_COMPAT_PROVIDERS=ollama,openrouter` lists the names; each name then reads
+/// `OPENAI_COMPAT_<NAME>_BASE_URL` (required), `_MODELS` (required, first = default),
+/// `_API_KEYS` and `_HEADERS` (a JSON object, `{"Name": "value"}`).
+fn parse_openai_compat() -> Vec<OpenAiCompatConfig> {
+    parse_keys("OPENAI_COMPAT_PROVIDERS")
+        .into_iter()
+        .filter_map(|name| {
+            let prefix = format!(
+                "OPENAI_COMPAT_{}",
+                name.to_uppercase().replace(['-', '.', ' '], "_")
+            );
+            let base_url = env::var(format!("{prefix}_BASE_URL")).ok().filter(|s| !s.is_empty());
+            let models = parse_keys(&format!("{prefix}_MODELS"));
+            let Some(base_url) = base_url.filter(|_| !models.is_empty()) else {
+                tracing::warn!("OpenAI-compatible provider '{name}' needs {prefix}_BASE_URL and {prefix}_MODELS, skipping");
+                return None;
+            };
+            let headers = parse_headers(&format!("{prefix}_HEADERS"));
+
+            Some(OpenAiCompatConfig {
+                name: name.to_lowercase(),
+                base_url,
+                models,
+                keys: parse_keys(&format!("{prefix}_API_KEYS")),
+                headers,
+            })
+        })
+        .collect()
+}
+
+/// Extra headers as a JSON object of strings. Values may contain any
+/// character, so nothing needs escaping; entries that aren't strings are
+/// skipped with a warning.
+fn parse_headers(var: &str) -> Vec<(String, String)> {
+    let Some(raw) = env::var(var).ok().filter(|s| !s.trim().is_empty()) else {
+        return Vec::new();
+    };
+    let map = match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&raw) {
+        Ok(map) => map,
+        Err(e) => {
+            tracing::warn!("{var} must be a JSON object like {{\"Name\": \"value\"}}, ignoring it: {e}");
+            return Vec::new();
+        }
+    };
+    map.into_iter()
+        .filter_map(|(name, value)| match value {
+            serde_json::Value::String(v) if !name.trim().is_empty() => Some((name.trim().to_string(), v)),
+            other => {
+                tracing::warn!("{var}: skipping header '{name}' with value {other}, expected a string");
+                None
+            }
+        })
+        .collect()
+}
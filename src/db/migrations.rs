@@ -0,0 +1,408 @@
+//! Versioned schema changes. `PRAGMA user_version` holds the number of
+//! migrations a database has had; `migrate` applies the rest in order.
+
+use rusqlite::Connection;
+use tracing::{info, warn};
+
+struct Migration {
+    description: &'static str,
+    apply: fn(&Connection) -> rusqlite::Result<()>,
+    /// Whether `apply` runs inside a transaction. Only for statements SQLite
+    /// refuses there, such as `VACUUM`.
+    transactional: bool,
+}
+
+/// Append new migrations at the end; never edit or reorder released ones.
+/// Each must be safe to run again on a database that already has it, since a
+/// crash between applying it and recording the version would repeat it.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "baseline schema",
+        apply: baseline,
+        transactional: true,
+    },
+    Migration {
+        description: "structured tool calls and results in session history",
+        apply: |conn| add_column_if_missing(conn, "session_messages", "message", "TEXT"),
+        transactional: true,
+    },
+    Migration {
+        description: "full-text index over conversation messages",
+        apply: conversation_index,
+        transactional: true,
+    },
+    Migration {
+        description: "persist disabled provider keys",
+        apply: |conn| add_column_if_missing(conn, "key_states", "disabled", "INTEGER NOT NULL DEFAULT 0"),
+        transactional: true,
+    },
+    Migration {
+        description: "incremental auto-vacuum",
+        // Changing auto_vacuum on an existing database takes one full VACUUM;
+        // afterwards the prune task only runs `PRAGMA incremental_vacuum`
+        apply: |conn| conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;"),
+        transactional: false,
+    },
+];
+
+/// Bring the schema up to date. Refuses databases written by a newer build,
+/// and copies an existing database next to itself before changing it.
+pub fn migrate(conn: &mut Connection, path: &str) -> Result<(), String> {
+    let version: i64 = conn
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let latest = MIGRATIONS.len();
+    let version = usize::try_from(version).unwrap_or(usize::MAX);
+    if version > latest {
+        return Err(format!(
+            "{path} has schema version {version} but this build only knows up to {latest}; \
+             upgrade free-agent or restore a backup"
+        ));
+    }
+    if version == latest {
+        return Ok(());
+    }
+
+    let has_tables: bool = conn
+        .query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if has_tables && path != ":memory:" {
+        let backup = format!("{path}.v{version}-{}.bak", chrono::Utc::now().format("%Y%m%d%H%M%S"));
+        conn.execute("VACUUM INTO ?1", [&backup])
+            .map_err(|e| format!("Backup before migrating failed: {e}"))?;
+        info!("Backed up {path} to {backup}");
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let to = i + 1;
+        let applied = if migration.transactional {
+            conn.transaction().and_then(|tx| {
+                (migration.apply)(&tx)?;
+                tx.pragma_update(None, "user_version", to as i64)?;
+                tx.commit()
+            })
+        } else {
+            (migration.apply)(conn).and_then(|_| conn.pragma_update(None, "user_version", to as i64))
+        };
+        applied.map_err(|e| {
+            warn!("Migration {to} ({}) failed, schema left at version {}", migration.description, to - 1);
+            format!("Migration {to} ({}) failed: {e}", migration.description)
+        })?;
+        info!("Migrated database to version {to}: {}", migration.description);
+    }
+    Ok(())
+}
+
+/// Everything up to the first versioned release. Databases from before then
+/// already have some of it, so every step here is idempotent.
+fn baseline(conn: &Connection) -> rusqlite::Result<()> {
+    // Memory facts table
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS memory_facts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            fact TEXT NOT NULL,
+            category TEXT NOT NULL DEFAULT 'general',
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            access_count INTEGER NOT NULL DEFAULT 0,
+            last_accessed_at TEXT
+        );
+
+        CREATE VIRTUAL TABLE IF NOT EXISTS memory_facts_fts USING fts5(
+            fact,
+            content='memory_facts',
+            content_rowid='id'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS memory_facts_ai AFTER INSERT ON memory_facts BEGIN
+            INSERT INTO memory_facts_fts(rowid, fact) VALUES (new.id, new.fact);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS memory_facts_ad AFTER DELETE ON memory_facts BEGIN
+            INSERT INTO memory_facts_fts(memory_facts_fts, rowid, fact) VALUES('delete', old.id, old.fact);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS memory_facts_au AFTER UPDATE OF fact ON memory_facts BEGIN
+            INSERT INTO memory_facts_fts(memory_facts_fts, rowid, fact) VALUES('delete', old.id, old.fact);
+            INSERT INTO memory_facts_fts(rowid, fact) VALUES (new.id, new.fact);
+        END;
+
+        CREATE TABLE IF NOT EXISTS memory_proposals (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            fact TEXT NOT NULL,
+            category TEXT NOT NULL DEFAULT 'general',
+            importance INTEGER NOT NULL DEFAULT 3,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+        "
+    )?;
+
+    // Conversation sessions
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            title TEXT,
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            last_active_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+
+        CREATE TABLE IF NOT EXISTS session_messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id TEXT NOT NULL REFERENCES sessions(id),
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+
+        CREATE TABLE IF NOT EXISTS query_logs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            provider TEXT,
+            prompt_preview TEXT,
+            response_time_ms INTEGER,
+            tokens_in INTEGER DEFAULT 0,
+            tokens_out INTEGER DEFAULT 0,
+            created_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+
+        CREATE TABLE IF NOT EXISTS query_usage (
+            query_id INTEGER NOT NULL REFERENCES query_logs(id),
+            provider TEXT NOT NULL,
+            model TEXT NOT NULL,
+            tokens_in INTEGER NOT NULL DEFAULT 0,
+            tokens_out INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS idx_query_usage_query ON query_usage(query_id);
+
+        CREATE TABLE IF NOT EXISTS plans (
+            user_id INTEGER PRIMARY KEY,
+            content TEXT NOT NULL DEFAULT '',
+            updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+
+        CREATE TABLE IF NOT EXISTS todos (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            completed_at TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_todos_user ON todos(user_id);
+
+        CREATE TABLE IF NOT EXISTS user_settings (
+            user_id INTEGER PRIMARY KEY,
+            model TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS key_states (
+            provider TEXT NOT NULL,
+            key_id TEXT NOT NULL,
+            cooldown_until_ms INTEGER NOT NULL DEFAULT 0,
+            failures INTEGER NOT NULL DEFAULT 0,
+            updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+            PRIMARY KEY (provider, key_id)
+        );
+        "
+    )?;
+
+    // Columns added after the initial schema, before versioned migrations
+    add_column_if_missing(conn, "query_logs", "model", "TEXT")?;
+    add_column_if_missing(conn, "query_usage", "cost_usd", "REAL NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "memory_facts", "embedding", "BLOB")?;
+    add_column_if_missing(conn, "memory_facts", "embedding_model", "TEXT")?;
+    add_column_if_missing(conn, "memory_facts", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "memory_facts", "importance", "INTEGER NOT NULL DEFAULT 3")?;
+    add_column_if_missing(conn, "memory_facts", "expires_at", "TEXT")?;
+    add_column_if_missing(conn, "memory_facts", "updated_at", "TEXT")?;
+    add_column_if_missing(conn, "sessions", "summary", "TEXT")?;
+    add_column_if_missing(conn, "sessions", "summarized_up_to", "INTEGER NOT NULL DEFAULT 0")?;
+
+    Ok(())
+}
+
+/// Plain user and assistant messages are indexed; tool calls and results
+/// (rows with a structured `message`) are left out as noise.
+fn conversation_index(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS session_messages_fts USING fts5(
+            content,
+            content='session_messages',
+            content_rowid='id'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS session_messages_ai AFTER INSERT ON session_messages
+        WHEN new.message IS NULL BEGIN
+            INSERT INTO session_messages_fts(rowid, content) VALUES (new.id, new.content);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS session_messages_ad AFTER DELETE ON session_messages
+        WHEN old.message IS NULL BEGIN
+            INSERT INTO session_messages_fts(session_messages_fts, rowid, content) VALUES('delete', old.id, old.content);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS session_messages_au AFTER UPDATE OF content ON session_messages
+        WHEN old.message IS NULL BEGIN
+            INSERT INTO session_messages_fts(session_messages_fts, rowid, content) VALUES('delete', old.id, old.content);
+            INSERT INTO session_messages_fts(rowid, content) VALUES (new.id, new.content);
+        END;
+
+        INSERT INTO session_messages_fts(session_messages_fts) VALUES('delete-all');
+        INSERT INTO session_messages_fts(rowid, content)
+            SELECT id, content FROM session_messages WHERE message IS NULL;",
+    )
+}
+
+/// Add a column to an existing table unless it is already present
+/// (`CREATE TABLE IF NOT EXISTS` never alters deployed databases).
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> Result<(), rusqlite::Error> {
+    let exists = conn
+        .prepare(&format!("PRAGMA table_info({table})"))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|r| r.ok())
+        .any(|name| name == column);
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0))
+            .unwrap() as usize
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    fn search_hits(conn: &Connection, term: &str) -> i64 {
+        conn.query_row(
+            "SELECT COUNT(*) FROM session_messages_fts WHERE session_messages_fts MATCH ?1",
+            [term],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    fn migrated() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, ":memory:").unwrap();
+        conn
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let conn = migrated();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        for table in ["memory_facts", "sessions", "session_messages", "session_messages_fts", "key_states"] {
+            let exists = count(&conn, &format!("SELECT COUNT(*) FROM sqlite_master WHERE name = '{table}'"));
+            assert_eq!(exists, 1, "{table} is missing");
+        }
+    }
+
+    #[test]
+    fn unversioned_database_keeps_its_data() {
+        // Schema as written before versioned migrations existed
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE memory_facts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                fact TEXT NOT NULL,
+                category TEXT NOT NULL DEFAULT 'general',
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                access_count INTEGER NOT NULL DEFAULT 0,
+                last_accessed_at TEXT
+            );
+            CREATE TABLE sessions (
+                id TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                title TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                last_active_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            CREATE TABLE session_messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL REFERENCES sessions(id),
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            INSERT INTO memory_facts (user_id, fact) VALUES (1, 'Lives in Hanoi');
+            INSERT INTO sessions (id, user_id) VALUES ('1-1', 1);
+            INSERT INTO session_messages (session_id, role, content) VALUES ('1-1', 'user', 'What is the weather in Hanoi?');",
+        )
+        .unwrap();
+
+        migrate(&mut conn, ":memory:").unwrap();
+
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        let (fact, pinned): (String, bool) = conn
+            .query_row("SELECT fact, pinned FROM memory_facts WHERE user_id = 1", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((fact.as_str(), pinned), ("Lives in Hanoi", false));
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM session_messages WHERE message IS NULL"), 1);
+        // Existing messages are indexed for search
+        assert_eq!(search_hits(&conn, "weather"), 1);
+    }
+
+    #[test]
+    fn migrations_can_run_twice() {
+        let conn = migrated();
+        conn.execute_batch(
+            "INSERT INTO sessions (id, user_id) VALUES ('1-1', 1);
+             INSERT INTO session_messages (session_id, role, content) VALUES ('1-1', 'user', 'hello world');",
+        )
+        .unwrap();
+
+        for migration in MIGRATIONS {
+            (migration.apply)(&conn).unwrap_or_else(|e| panic!("{} failed on rerun: {e}", migration.description));
+        }
+
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM session_messages"), 1);
+        assert_eq!(search_hits(&conn, "hello"), 1);
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let mut conn = migrated();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1)
+            .unwrap();
+
+        let err = migrate(&mut conn, ":memory:").unwrap_err();
+        assert!(err.contains("upgrade free-agent"), "{err}");
+        assert_eq!(user_version(&conn), MIGRATIONS.len() + 1);
+    }
+
+    #[test]
+    fn conversation_index_follows_inserts_and_deletes() {
+        let conn = migrated();
+        conn.execute_batch(
+            "INSERT INTO sessions (id, user_id) VALUES ('1-1', 1);
+             INSERT INTO session_messages (session_id, role, content) VALUES ('1-1', 'user', 'plan a trip to Hue');
+             INSERT INTO session_messages (session_id, role, content, message)
+                 VALUES ('1-1', 'tool', 'trip itinerary', '{}');",
+        )
+        .unwrap();
+        assert_eq!(search_hits(&conn, "trip"), 1, "tool rows are not indexed");
+
+        conn.execute("DELETE FROM session_messages WHERE role = 'user'", []).unwrap();
+        assert_eq!(search_hits(&conn, "trip"), 0);
+        conn.execute("DELETE FROM session_messages", []).unwrap();
+        assert_eq!(search_hits(&conn, "itinerary"), 0);
+    }
+}
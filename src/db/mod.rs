@@ -1,418 +1,1780 @@
-use rusqlite::{Connection, params};
-use std::sync::Mutex;
-use tracing::info;
+mod migrations;
 
-pub struct Database {
-    conn: Mutex<Connection>,
+use rusqlite::{Connection, OpenFlags, params};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::provider::{KeySnapshot, Message, MessageContent, Role, Usage, cosine_similarity};
+
+/// Token and estimated cost totals for the current UTC day and month.
+#[derive(Debug, Clone, Default)]
+pub struct UsageTotals {
+    pub day_tokens: u64,
+    pub day_usd: f64,
+    pub month_tokens: u64,
+    pub month_usd: f64,
 }
 
-impl Database {
-    pub fn open(path: &str) -> Result<Self, rusqlite::Error> {
-        let conn = Connection::open(path)?;
-
-        conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA busy_timeout=5000;")?;
-
-        // Memory facts table
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS memory_facts (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id INTEGER NOT NULL,
-                fact TEXT NOT NULL,
-                category TEXT NOT NULL DEFAULT 'general',
-                created_at TEXT NOT NULL DEFAULT (datetime('now')),
-                access_count INTEGER NOT NULL DEFAULT 0,
-                last_accessed_at TEXT
-            );
+impl UsageTotals {
+    pub fn add(&mut self, tokens: u64, usd: f64) {
+        self.day_tokens += tokens;
+        self.day_usd += usd;
+        self.month_tokens += tokens;
+        self.month_usd += usd;
+    }
+}
 
-            CREATE VIRTUAL TABLE IF NOT EXISTS memory_facts_fts USING fts5(
-                fact,
-                content='memory_facts',
-                content_rowid='id'
-            );
+/// How much memory goes into each system prompt.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryContextLimits {
+    /// Relevant (non-pinned) facts to include.
+    pub top_k: usize,
+    /// Rough token cap for the whole memory section, pinned facts included.
+    pub max_tokens: usize,
+}
 
-            CREATE TRIGGER IF NOT EXISTS memory_facts_ai AFTER INSERT ON memory_facts BEGIN
-                INSERT INTO memory_facts_fts(rowid, fact) VALUES (new.id, new.fact);
-            END;
-
-            CREATE TRIGGER IF NOT EXISTS memory_facts_ad AFTER DELETE ON memory_facts BEGIN
-                INSERT INTO memory_facts_fts(memory_facts_fts, rowid, fact) VALUES('delete', old.id, old.fact);
-            END;
-            "
-        )?;
-
-        // Conversation sessions
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                id TEXT PRIMARY KEY,
-                user_id INTEGER NOT NULL,
-                title TEXT,
-                created_at TEXT NOT NULL DEFAULT (datetime('now')),
-                last_active_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );
+/// How long conversation history and query logs are kept; 0 means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    /// Delete sessions inactive for this many days.
+    pub session_days: u32,
+    /// Keep at most this many session messages in total, oldest deleted first.
+    pub max_session_messages: u64,
+    /// Delete query logs older than this many days.
+    pub query_log_days: u32,
+    /// Keep at most this many query logs, oldest deleted first.
+    pub max_query_logs: u64,
+}
 
-            CREATE TABLE IF NOT EXISTS session_messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                session_id TEXT NOT NULL REFERENCES sessions(id),
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                created_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );
+/// Rows removed by one pruning pass.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PruneStats {
+    pub facts: usize,
+    pub sessions: usize,
+    pub messages: usize,
+    pub query_logs: usize,
+}
 
-            CREATE TABLE IF NOT EXISTS query_logs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id INTEGER NOT NULL,
-                provider TEXT,
-                prompt_preview TEXT,
-                response_time_ms INTEGER,
-                tokens_in INTEGER DEFAULT 0,
-                tokens_out INTEGER DEFAULT 0,
-                created_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );
+/// Metadata stored with a new fact.
+#[derive(Debug, Clone)]
+pub struct FactMeta {
+    pub category: String,
+    pub pinned: bool,
+    /// 1 (trivia) to 5 (essential); ranks the fact for the system prompt.
+    pub importance: u8,
+    /// Delete the fact this many days from now (`None` = keep forever).
+    pub expires_in_days: Option<u32>,
+}
 
-            CREATE TABLE IF NOT EXISTS plans (
-                user_id INTEGER PRIMARY KEY,
-                content TEXT NOT NULL DEFAULT '',
-                updated_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );
+/// Changes to an existing fact; `None` leaves a field as it is.
+#[derive(Debug, Clone, Default)]
+pub struct FactUpdate {
+    pub fact: Option<String>,
+    pub category: Option<String>,
+    pub importance: Option<u8>,
+    pub pinned: Option<bool>,
+    /// Expire this many days from now; `Some(0)` removes the expiry.
+    pub expires_in_days: Option<u32>,
+}
 
-            CREATE TABLE IF NOT EXISTS todos (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id INTEGER NOT NULL,
-                content TEXT NOT NULL,
-                status TEXT NOT NULL DEFAULT 'pending',
-                created_at TEXT NOT NULL DEFAULT (datetime('now')),
-                completed_at TEXT
-            );
-            CREATE INDEX IF NOT EXISTS idx_todos_user ON todos(user_id);
-            "
-        )?;
+/// Result of saving a fact.
+pub enum SaveOutcome {
+    Saved(i64),
+    /// An existing fact already says the same: (id, text).
+    Duplicate(i64, String),
+}
+
+/// One of a user's conversation sessions, most recently active first.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    /// Short number users refer to the session by.
+    pub number: i64,
+    /// Title, or the start of the first message when it has none yet.
+    pub title: String,
+    /// `YYYY-MM-DD HH:MM` in UTC.
+    pub last_active_at: String,
+    pub messages: i64,
+}
+
+/// A stored history message as exported or imported.
+#[derive(Debug, Clone)]
+pub struct HistoryRow {
+    /// `user`, `assistant` or `tool`.
+    pub role: String,
+    /// Readable text of the message.
+    pub content: String,
+    /// JSON of the structured message, for tool calls and results.
+    pub message: Option<String>,
+    /// `YYYY-MM-DD HH:MM:SS` in UTC.
+    pub created_at: String,
+}
+
+/// A past message matching a conversation search.
+#[derive(Debug, Clone)]
+pub struct ConversationHit {
+    pub session_number: i64,
+    pub session_title: Option<String>,
+    /// `user` or `assistant`.
+    pub role: String,
+    /// Excerpt around the match, matched words in «».
+    pub snippet: String,
+    /// `YYYY-MM-DD HH:MM:SS` in UTC.
+    pub created_at: String,
+}
+
+/// Read-only connections opened next to the writer.
+const READERS: usize = 4;
+
+/// SQLite access for async code. Queries run on Tokio's blocking pool, so a
+/// slow query never stalls the runtime; writes share one connection while
+/// reads use their own (WAL lets them proceed during a write). Cheap to clone.
+#[derive(Clone)]
+pub struct Database {
+    conns: Arc<Connections>,
+}
+
+struct Connections {
+    writer: Mutex<Connection>,
+    /// Empty for in-memory databases, where reads go to the writer.
+    readers: Vec<Mutex<Connection>>,
+    next_reader: AtomicUsize,
+}
+
+impl Database {
+    /// Open (or create) the database and bring its schema up to date.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut conn = Connection::open(path).map_err(|e| e.to_string())?;
+
+        conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA busy_timeout=5000;")
+            .map_err(|e| e.to_string())?;
+        migrations::migrate(&mut conn, path)?;
+
+        let mut readers = Vec::new();
+        if path != ":memory:" {
+            for _ in 0..READERS {
+                let reader = Connection::open_with_flags(
+                    path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_URI,
+                )
+                .map_err(|e| e.to_string())?;
+                reader
+                    .execute_batch("PRAGMA busy_timeout=5000;")
+                    .map_err(|e| e.to_string())?;
+                readers.push(Mutex::new(reader));
+            }
+        }
 
         info!("Database initialized: {path}");
         Ok(Self {
-            conn: Mutex::new(conn),
+            conns: Arc::new(Connections {
+                writer: Mutex::new(conn),
+                readers,
+                next_reader: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    /// Run `f` on the write connection, off the async runtime.
+    async fn write<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Connection) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let conns = self.conns.clone();
+        tokio::task::spawn_blocking(move || f(&mut conns.writer.lock().unwrap()))
+            .await
+            .expect("database task panicked")
+    }
+
+    /// Run `f` on a read-only connection, off the async runtime.
+    async fn read<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&Connection) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let conns = self.conns.clone();
+        tokio::task::spawn_blocking(move || {
+            if conns.readers.is_empty() {
+                return f(&conns.writer.lock().unwrap());
+            }
+            let i = conns.next_reader.fetch_add(1, Ordering::Relaxed) % conns.readers.len();
+            f(&conns.readers[i].lock().unwrap())
         })
+        .await
+        .expect("database task panicked")
     }
 
     // --- Memory ---
 
-    pub fn save_fact(&self, user_id: u64, fact: &str, category: &str) -> Result<i64, String> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO memory_facts (user_id, fact, category) VALUES (?1, ?2, ?3)",
-            params![user_id as i64, fact, category],
-        )
-        .map_err(|e| e.to_string())?;
-        Ok(conn.last_insert_rowid())
+    /// Save a fact unless the user already has one saying the same (same text
+    /// once normalized, or a near-identical embedding when `embedding` is given).
+    pub async fn save_fact(
+        &self,
+        user_id: u64,
+        fact: &str,
+        meta: FactMeta,
+        embedding: Option<(&str, &[f32])>,
+    ) -> Result<SaveOutcome, String> {
+        let fact = fact.to_string();
+        let embedding = owned_embedding(embedding);
+        self.write(move |conn| {
+            purge_expired_facts(conn, user_id);
+            if let Some((id, existing)) = find_duplicate(conn, user_id, &fact, as_embedding(&embedding)) {
+                return Ok(SaveOutcome::Duplicate(id, existing));
+            }
+            conn.execute(
+                "INSERT INTO memory_facts (user_id, fact, category, pinned, importance, expires_at, embedding, embedding_model)
+                 VALUES (?1, ?2, ?3, ?4, ?5, CASE WHEN ?6 IS NULL THEN NULL ELSE datetime('now', '+' || ?6 || ' days') END, ?7, ?8)",
+                params![
+                    user_id as i64,
+                    fact,
+                    meta.category,
+                    meta.pinned,
+                    meta.importance,
+                    meta.expires_in_days,
+                    embedding.as_ref().map(|(_, vector)| encode_vector(vector)),
+                    embedding.as_ref().map(|(model, _)| model),
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(SaveOutcome::Saved(conn.last_insert_rowid()))
+        })
+        .await
     }
 
-    pub fn search_facts(&self, user_id: u64, keyword: &str) -> Result<Vec<(i64, String, String)>, String> {
-        let conn = self.conn.lock().unwrap();
+    /// Apply `update` to one of the user's facts. A new text drops the stored
+    /// embedding, which no longer matches it. Returns false if the fact doesn't exist.
+    pub async fn update_fact(&self, user_id: u64, fact_id: i64, update: FactUpdate) -> Result<bool, String> {
+        self.write(move |conn| {
+            let affected = conn
+                .execute(
+                    "UPDATE memory_facts SET
+                        fact = COALESCE(?3, fact),
+                        category = COALESCE(?4, category),
+                        importance = COALESCE(?5, importance),
+                        pinned = COALESCE(?6, pinned),
+                        expires_at = CASE
+                            WHEN ?7 IS NULL THEN expires_at
+                            WHEN ?7 = 0 THEN NULL
+                            ELSE datetime('now', '+' || ?7 || ' days')
+                        END,
+                        embedding = CASE WHEN ?3 IS NULL OR ?3 = fact THEN embedding END,
+                        embedding_model = CASE WHEN ?3 IS NULL OR ?3 = fact THEN embedding_model END,
+                        updated_at = datetime('now')
+                     WHERE id = ?1 AND user_id = ?2",
+                    params![
+                        fact_id,
+                        user_id as i64,
+                        update.fact,
+                        update.category,
+                        update.importance,
+                        update.pinned,
+                        update.expires_in_days,
+                    ],
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
+    }
 
-        // Try FTS5 first, fall back to LIKE
-        let results: Vec<(i64, String, String)> = conn
-            .prepare(
-                "SELECT mf.id, mf.fact, mf.category FROM memory_facts mf
-                 JOIN memory_facts_fts fts ON mf.id = fts.rowid
-                 WHERE fts.fact MATCH ?1 AND mf.user_id = ?2
-                 ORDER BY rank LIMIT 20"
+    /// Replace several of the user's facts with one new text, kept under the
+    /// first ID. The merged fact keeps the highest importance, stays pinned if
+    /// any was pinned, sums access counts and expires only if all of them did.
+    pub async fn merge_facts(&self, user_id: u64, ids: &[i64], fact: &str, category: Option<&str>) -> Result<i64, String> {
+        let mut unique: Vec<i64> = Vec::new();
+        for id in ids {
+            if !unique.contains(id) {
+                unique.push(*id);
+            }
+        }
+        if unique.len() < 2 {
+            return Err("Need at least two different memory IDs to merge".into());
+        }
+
+        let fact = fact.to_string();
+        let category = category.map(str::to_string);
+        self.write(move |conn| {
+            let mut merged: Option<(String, i64, bool, i64, Option<String>)> = None;
+            for id in &unique {
+                let (cat, importance, pinned, accesses, expires): (String, i64, bool, i64, Option<String>) = conn
+                    .query_row(
+                        "SELECT category, importance, pinned, access_count, expires_at FROM memory_facts
+                         WHERE id = ?1 AND user_id = ?2",
+                        params![id, user_id as i64],
+                        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+                    )
+                    .map_err(|_| format!("Memory ID {id} not found or not yours"))?;
+                merged = Some(match merged {
+                    None => (cat, importance, pinned, accesses, expires),
+                    Some((c, i, p, a, e)) => (c, i.max(importance), p || pinned, a + accesses, e.zip(expires).map(|(x, y)| x.max(y))),
+                });
+            }
+            let Some((first_category, importance, pinned, accesses, expires)) = merged else {
+                return Err("Nothing to merge".into());
+            };
+
+            let keep = unique[0];
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            tx.execute(
+                "UPDATE memory_facts SET fact = ?2, category = ?3, importance = ?4, pinned = ?5, access_count = ?6,
+                    expires_at = ?7, embedding = NULL, embedding_model = NULL, updated_at = datetime('now')
+                 WHERE id = ?1",
+                params![keep, fact, category.as_deref().unwrap_or(&first_category), importance, pinned, accesses, expires],
             )
-            .and_then(|mut stmt| {
-                let rows = stmt.query_map(params![keyword, user_id as i64], |row| {
-                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-                })?;
-                rows.collect()
-            })
-            .unwrap_or_else(|_| {
-                // Fallback to LIKE
-                conn.prepare(
-                    "SELECT id, fact, category FROM memory_facts
-                     WHERE user_id = ?1 AND fact LIKE '%' || ?2 || '%'
-                     ORDER BY created_at DESC LIMIT 20"
+            .map_err(|e| e.to_string())?;
+            for id in &unique[1..] {
+                tx.execute("DELETE FROM memory_facts WHERE id = ?1", params![id])
+                    .map_err(|e| e.to_string())?;
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(keep)
+        })
+        .await
+    }
+
+    /// Search facts by keyword, and by meaning when `semantic` carries the query's
+    /// embedding (model, vector): both rankings are merged with reciprocal rank fusion.
+    pub async fn search_facts(
+        &self,
+        user_id: u64,
+        keyword: &str,
+        semantic: Option<(&str, &[f32])>,
+    ) -> Result<Vec<(i64, String, String)>, String> {
+        let keyword = keyword.to_string();
+        let semantic = owned_embedding(semantic);
+        let results = self.read(move |conn| {
+            // Try FTS5 first, fall back to LIKE
+            let keyword_hits: Vec<(i64, String, String)> = conn
+                .prepare(
+                    "SELECT mf.id, mf.fact, mf.category FROM memory_facts mf
+                     JOIN memory_facts_fts fts ON mf.id = fts.rowid
+                     WHERE fts.fact MATCH ?1 AND mf.user_id = ?2 AND (mf.expires_at IS NULL OR mf.expires_at > datetime('now'))
+                     ORDER BY rank LIMIT 20"
                 )
                 .and_then(|mut stmt| {
-                    let rows = stmt.query_map(params![user_id as i64, keyword], |row| {
+                    let rows = stmt.query_map(params![keyword, user_id as i64], |row| {
                         Ok((row.get(0)?, row.get(1)?, row.get(2)?))
                     })?;
                     rows.collect()
                 })
-                .unwrap_or_default()
-            });
+                .unwrap_or_else(|_| {
+                    // Fallback to LIKE
+                    conn.prepare(
+                        "SELECT id, fact, category FROM memory_facts
+                         WHERE user_id = ?1 AND fact LIKE '%' || ?2 || '%' AND (expires_at IS NULL OR expires_at > datetime('now'))
+                         ORDER BY created_at DESC LIMIT 20"
+                    )
+                    .and_then(|mut stmt| {
+                        let rows = stmt.query_map(params![user_id as i64, keyword], |row| {
+                            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                        })?;
+                        rows.collect()
+                    })
+                    .unwrap_or_default()
+                });
 
-        // Update access count
-        for (id, _, _) in &results {
-            let _ = conn.execute(
-                "UPDATE memory_facts SET access_count = access_count + 1, last_accessed_at = datetime('now') WHERE id = ?1",
-                params![id],
-            );
-        }
+            let results = match as_embedding(&semantic) {
+                Some((model, query)) => {
+                    let semantic_hits = semantic_search(conn, user_id, model, query);
+                    fuse_ranks(&[&keyword_hits, &semantic_hits])
+                        .into_iter()
+                        .take(20)
+                        .map(|(_, hit)| hit)
+                        .collect()
+                }
+                None => keyword_hits,
+            };
+            Ok::<_, String>(results)
+        })
+        .await?;
 
+        self.mark_facts_accessed(results.iter().map(|hit| hit.0).collect()).await;
         Ok(results)
     }
 
-    pub fn list_facts(&self, user_id: u64, category: Option<&str>) -> Result<Vec<(i64, String, String)>, String> {
-        let conn = self.conn.lock().unwrap();
-        let (sql, p): (&str, Vec<Box<dyn rusqlite::types::ToSql>>) = match category {
-            Some(cat) => (
-                "SELECT id, fact, category FROM memory_facts WHERE user_id = ?1 AND category = ?2 ORDER BY created_at DESC LIMIT 30",
-                vec![Box::new(user_id as i64), Box::new(cat.to_string())],
-            ),
-            None => (
-                "SELECT id, fact, category FROM memory_facts WHERE user_id = ?1 ORDER BY created_at DESC LIMIT 30",
-                vec![Box::new(user_id as i64)],
-            ),
-        };
+    /// Bump the access count and time of facts that were returned or injected.
+    async fn mark_facts_accessed(&self, ids: Vec<i64>) {
+        if ids.is_empty() {
+            return;
+        }
+        self.write(move |conn| {
+            for id in ids {
+                let _ = conn.execute(
+                    "UPDATE memory_facts SET access_count = access_count + 1, last_accessed_at = datetime('now') WHERE id = ?1",
+                    params![id],
+                );
+            }
+        })
+        .await
+    }
 
-        let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
-        let params_refs: Vec<&dyn rusqlite::types::ToSql> = p.iter().map(|b| b.as_ref()).collect();
-        let rows = stmt
-            .query_map(params_refs.as_slice(), |row| {
-                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-            })
+    /// Store the embedding of a fact's current text.
+    pub async fn set_fact_embedding(&self, fact_id: i64, model: &str, vector: &[f32]) -> Result<(), String> {
+        let model = model.to_string();
+        let vector = vector.to_vec();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE memory_facts SET embedding = ?2, embedding_model = ?3 WHERE id = ?1",
+                params![fact_id, encode_vector(&vector), model],
+            )
             .map_err(|e| e.to_string())?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Facts (of all users) with no embedding from `model`, for backfilling: (id, fact).
+    pub async fn facts_missing_embedding(&self, model: &str, limit: usize) -> Vec<(i64, String)> {
+        let model = model.to_string();
+        self.read(move |conn| {
+            conn.prepare(
+                "SELECT id, fact FROM memory_facts
+                 WHERE embedding IS NULL OR embedding_model IS NOT ?1
+                 ORDER BY id LIMIT ?2",
+            )
+            .and_then(|mut stmt| {
+                let rows = stmt.query_map(params![model, limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            })
+            .unwrap_or_default()
+        })
+        .await
+    }
+
+    pub async fn list_facts(&self, user_id: u64, category: Option<&str>) -> Result<Vec<(i64, String, String)>, String> {
+        let category = category.map(str::to_string);
+        self.read(move |conn| {
+            let (sql, p): (&str, Vec<Box<dyn rusqlite::types::ToSql>>) = match category {
+                Some(cat) => (
+                    "SELECT id, fact, category FROM memory_facts
+                     WHERE user_id = ?1 AND category = ?2 AND (expires_at IS NULL OR expires_at > datetime('now'))
+                     ORDER BY created_at DESC LIMIT 30",
+                    vec![Box::new(user_id as i64), Box::new(cat.to_string())],
+                ),
+                None => (
+                    "SELECT id, fact, category FROM memory_facts
+                     WHERE user_id = ?1 AND (expires_at IS NULL OR expires_at > datetime('now'))
+                     ORDER BY created_at DESC LIMIT 30",
+                    vec![Box::new(user_id as i64)],
+                ),
+            };
+
+            let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+            let params_refs: Vec<&dyn rusqlite::types::ToSql> = p.iter().map(|b| b.as_ref()).collect();
+            let rows = stmt
+                .query_map(params_refs.as_slice(), |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })
+                .map_err(|e| e.to_string())?;
 
-        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+        })
+        .await
     }
 
     // --- Memory context for system prompt ---
 
-    pub fn build_memory_context(&self, user_id: u64) -> String {
-        let facts = self.list_facts(user_id, None).unwrap_or_default();
-        if facts.is_empty() {
-            return String::new();
-        }
+    /// Memory section of the system prompt for one incoming message: pinned facts
+    /// plus the `top_k` facts most relevant to `query` (by keyword, and by meaning
+    /// when `semantic` carries its embedding), favoring important facts and those
+    /// used often and recently, within a token cap.
+    pub async fn build_memory_context(
+        &self,
+        user_id: u64,
+        query: &str,
+        semantic: Option<(&str, &[f32])>,
+        limits: MemoryContextLimits,
+    ) -> String {
+        let query = query.to_string();
+        let semantic = owned_embedding(semantic);
+        let (ctx, used) = self.read(move |conn| {
+            let pinned: Vec<(i64, String, String)> = conn
+                .prepare(
+                    "SELECT id, fact, category FROM memory_facts
+                     WHERE user_id = ?1 AND pinned = 1 AND (expires_at IS NULL OR expires_at > datetime('now'))
+                     ORDER BY created_at",
+                )
+                .and_then(|mut stmt| {
+                    let rows = stmt.query_map(params![user_id as i64], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                    })?;
+                    rows.collect()
+                })
+                .unwrap_or_default();
 
-        let mut grouped: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
-        for (_, fact, category) in &facts {
-            grouped
-                .entry(category.clone())
-                .or_default()
-                .push(fact.clone());
-        }
+            let keyword_hits = any_term_query(&query)
+                .and_then(|q| {
+                    conn.prepare(
+                        "SELECT mf.id, mf.fact, mf.category FROM memory_facts mf
+                         JOIN memory_facts_fts fts ON mf.id = fts.rowid
+                         WHERE fts.fact MATCH ?1 AND mf.user_id = ?2
+                           AND (mf.expires_at IS NULL OR mf.expires_at > datetime('now'))
+                         ORDER BY rank LIMIT 50",
+                    )
+                    .and_then(|mut stmt| {
+                        let rows = stmt.query_map(params![q, user_id as i64], |row| {
+                            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                        })?;
+                        rows.collect::<Result<Vec<_>, _>>()
+                    })
+                    .ok()
+                })
+                .unwrap_or_default();
+            let semantic_hits = as_embedding(&semantic)
+                .map(|(model, vector)| semantic_search(conn, user_id, model, vector))
+                .unwrap_or_default();
 
-        let mut ctx = String::from("\n--- MEMORY ---\n");
-        for (cat, items) in &grouped {
-            ctx.push_str(&format!("\n[{cat}]\n"));
-            for item in items {
-                ctx.push_str(&format!("- {item}\n"));
+            let mut ranked = fuse_ranks(&[&keyword_hits, &semantic_hits]);
+            for (score, hit) in ranked.iter_mut() {
+                *score *= fact_weight(conn, hit.0);
             }
-        }
-        ctx.push_str("\n--- END MEMORY ---\n");
+            ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let relevant = ranked
+                .into_iter()
+                .map(|(_, hit)| hit)
+                .filter(|hit| !pinned.iter().any(|p| p.0 == hit.0))
+                .take(limits.top_k);
+
+            // Pinned facts first; anything that would overflow the cap is skipped
+            let mut chosen: Vec<(i64, String, String)> = Vec::new();
+            let mut tokens = 0;
+            let mut used: Vec<i64> = Vec::new();
+            let candidates = pinned
+                .iter()
+                .cloned()
+                .map(|hit| (hit, true))
+                .chain(relevant.map(|hit| (hit, false)));
+            for (hit, is_pinned) in candidates {
+                let cost = hit.1.len() / 4 + 4;
+                if tokens + cost > limits.max_tokens {
+                    continue;
+                }
+                tokens += cost;
+                if !is_pinned {
+                    used.push(hit.0);
+                }
+                chosen.push(hit);
+            }
+            if chosen.is_empty() {
+                return (String::new(), Vec::new());
+            }
+
+            // Group by category, in order of first (most relevant) appearance
+            let mut grouped: Vec<(String, Vec<String>)> = Vec::new();
+            for (_, fact, category) in chosen {
+                match grouped.iter_mut().find(|(c, _)| *c == category) {
+                    Some((_, items)) => items.push(fact),
+                    None => grouped.push((category, vec![fact])),
+                }
+            }
+
+            let mut ctx = String::from("\n--- MEMORY ---\n");
+            ctx.push_str("Pinned facts and those relevant to this message; use memory_search to look up others.\n");
+            for (cat, items) in &grouped {
+                ctx.push_str(&format!("\n[{cat}]\n"));
+                for item in items {
+                    ctx.push_str(&format!("- {item}\n"));
+                }
+            }
+            ctx.push_str("\n--- END MEMORY ---\n");
+            (ctx, used)
+        })
+        .await;
+
+        // Injected facts count as used, which keeps them ranking well next time
+        self.mark_facts_accessed(used).await;
         ctx
     }
 
-    pub fn delete_fact(&self, user_id: u64, fact_id: i64) -> Result<bool, String> {
-        let conn = self.conn.lock().unwrap();
-        let affected = conn
-            .execute(
-                "DELETE FROM memory_facts WHERE id = ?1 AND user_id = ?2",
+    /// Pin or unpin a fact so it is always (or no longer always) in the system prompt.
+    pub async fn set_fact_pinned(&self, user_id: u64, fact_id: i64, pinned: bool) -> Result<bool, String> {
+        self.write(move |conn| {
+            let affected = conn
+                .execute(
+                    "UPDATE memory_facts SET pinned = ?3 WHERE id = ?1 AND user_id = ?2",
+                    params![fact_id, user_id as i64, pinned],
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
+    }
+
+    /// Whether a fact of this user is pinned (`None` if it doesn't exist).
+    pub async fn is_fact_pinned(&self, user_id: u64, fact_id: i64) -> Option<bool> {
+        self.read(move |conn| {
+            conn.query_row(
+                "SELECT pinned FROM memory_facts WHERE id = ?1 AND user_id = ?2",
                 params![fact_id, user_id as i64],
+                |row| row.get(0),
+            )
+            .ok()
+        })
+        .await
+    }
+
+    /// A fact of the user that says the same as `fact` (see `save_fact`): (id, text).
+    pub async fn find_duplicate_fact(
+        &self,
+        user_id: u64,
+        fact: &str,
+        embedding: Option<(&str, &[f32])>,
+    ) -> Option<(i64, String)> {
+        let fact = fact.to_string();
+        let embedding = owned_embedding(embedding);
+        self.read(move |conn| {
+            find_duplicate(conn, user_id, &fact, as_embedding(&embedding))
+        })
+        .await
+    }
+
+    /// Keep an extracted fact until the user confirms or skips it. Returns its id.
+    pub async fn add_memory_proposal(&self, user_id: u64, fact: &str, category: &str, importance: u8) -> Result<i64, String> {
+        let fact = fact.to_string();
+        let category = category.to_string();
+        self.write(move |conn| {
+            // Unanswered suggestions don't need to live forever
+            let _ = conn.execute(
+                "DELETE FROM memory_proposals WHERE created_at < datetime('now', '-7 days')",
+                [],
+            );
+            conn.execute(
+                "INSERT INTO memory_proposals (user_id, fact, category, importance) VALUES (?1, ?2, ?3, ?4)",
+                params![user_id as i64, fact, category, importance],
             )
             .map_err(|e| e.to_string())?;
-        Ok(affected > 0)
+            Ok(conn.last_insert_rowid())
+        })
+        .await
+    }
+
+    /// Remove a pending proposal and return it: (fact, category, importance).
+    pub async fn take_memory_proposal(&self, user_id: u64, proposal_id: i64) -> Option<(String, String, u8)> {
+        self.write(move |conn| {
+            let proposal = conn
+                .query_row(
+                    "SELECT fact, category, importance FROM memory_proposals WHERE id = ?1 AND user_id = ?2",
+                    params![proposal_id, user_id as i64],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .ok()?;
+            conn.execute("DELETE FROM memory_proposals WHERE id = ?1", params![proposal_id])
+                .ok()?;
+            Some(proposal)
+        })
+        .await
+    }
+
+    pub async fn delete_fact(&self, user_id: u64, fact_id: i64) -> Result<bool, String> {
+        self.write(move |conn| {
+            let affected = conn
+                .execute(
+                    "DELETE FROM memory_facts WHERE id = ?1 AND user_id = ?2",
+                    params![fact_id, user_id as i64],
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
     }
 
     // --- Conversation history ---
 
-    /// Get or create the active session for a user. Returns session_id.
-    pub fn get_or_create_session(&self, user_id: u64) -> String {
-        let conn = self.conn.lock().unwrap();
-        // Try to find existing session (most recent)
-        let existing: Option<String> = conn
-            .query_row(
-                "SELECT id FROM sessions WHERE user_id = ?1 ORDER BY last_active_at DESC LIMIT 1",
-                params![user_id as i64],
-                |row| row.get(0),
-            )
-            .ok();
+    /// Get or create the active session for a user (the most recently active
+    /// one). Returns session_id.
+    pub async fn get_or_create_session(&self, user_id: u64) -> String {
+        self.write(move |conn| {
+            let existing: Option<String> = conn
+                .query_row(
+                    "SELECT id FROM sessions WHERE user_id = ?1
+                     ORDER BY last_active_at DESC, rowid DESC LIMIT 1",
+                    params![user_id as i64],
+                    |row| row.get(0),
+                )
+                .ok();
 
-        if let Some(id) = existing {
-            // Update last_active_at
+            if let Some(id) = existing {
+                let _ = conn.execute(
+                    &format!("UPDATE sessions SET last_active_at = {NOW_MS} WHERE id = ?1"),
+                    params![&id],
+                );
+                return id;
+            }
+            create_session(conn, user_id)
+        })
+        .await
+    }
+
+    /// Start a fresh session and make it the active one. Earlier sessions are
+    /// kept, except empty ones. Returns session_id.
+    pub async fn new_session(&self, user_id: u64) -> String {
+        self.write(move |conn| {
             let _ = conn.execute(
-                "UPDATE sessions SET last_active_at = datetime('now') WHERE id = ?1",
-                params![&id],
+                "DELETE FROM sessions WHERE user_id = ?1 AND summary IS NULL
+                   AND NOT EXISTS (SELECT 1 FROM session_messages WHERE session_id = sessions.id)",
+                params![user_id as i64],
             );
-            return id;
-        }
+            create_session(conn, user_id)
+        })
+        .await
+    }
 
-        // Create new session
-        let id = format!("{}-{}", user_id, chrono::Utc::now().timestamp());
-        let _ = conn.execute(
-            "INSERT INTO sessions (id, user_id) VALUES (?1, ?2)",
-            params![&id, user_id as i64],
-        );
-        id
+    /// A user's sessions, most recently active (the current one) first.
+    pub async fn list_sessions(&self, user_id: u64) -> Vec<SessionInfo> {
+        self.read(move |conn| {
+            conn.prepare(
+                "SELECT s.id, s.rowid,
+                        COALESCE(NULLIF(s.title, ''), (
+                            SELECT substr(content, 1, 60) FROM session_messages
+                            WHERE session_id = s.id AND role = 'user' ORDER BY id LIMIT 1
+                        ), '(empty)'),
+                        substr(s.last_active_at, 1, 16),
+                        (SELECT COUNT(*) FROM session_messages WHERE session_id = s.id)
+                 FROM sessions s WHERE s.user_id = ?1
+                 ORDER BY s.last_active_at DESC, s.rowid DESC",
+            )
+            .and_then(|mut stmt| {
+                let rows = stmt.query_map(params![user_id as i64], |row| {
+                    Ok(SessionInfo {
+                        id: row.get(0)?,
+                        number: row.get(1)?,
+                        title: row.get(2)?,
+                        last_active_at: row.get(3)?,
+                        messages: row.get(4)?,
+                    })
+                })?;
+                rows.collect()
+            })
+            .unwrap_or_default()
+        })
+        .await
     }
 
-    /// Load recent conversation history for a session (last N user+assistant message pairs).
-    pub fn load_history(&self, session_id: &str, max_pairs: usize) -> Vec<(String, String)> {
-        let conn = self.conn.lock().unwrap();
-        let limit = (max_pairs * 2) as i64;
-        // Get last N messages ordered by id
-        let mut stmt = match conn.prepare(
-            "SELECT role, content FROM session_messages WHERE session_id = ?1 ORDER BY id DESC LIMIT ?2"
-        ) {
-            Ok(s) => s,
-            Err(_) => return vec![],
-        };
-        let rows: Vec<(String, String)> = stmt
-            .query_map(params![session_id, limit], |row| {
-                Ok((row.get(0)?, row.get(1)?))
+    /// Make one of the user's sessions the active one. Returns false if it doesn't exist.
+    pub async fn resume_session(&self, user_id: u64, number: i64) -> Result<bool, String> {
+        self.write(move |conn| {
+            let affected = conn
+                .execute(
+                    &format!("UPDATE sessions SET last_active_at = {NOW_MS} WHERE rowid = ?1 AND user_id = ?2"),
+                    params![number, user_id as i64],
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
+    }
+
+    /// Every message of a session in order, including those already summarized.
+    pub async fn session_messages(&self, session_id: &str) -> Vec<HistoryRow> {
+        let session_id = session_id.to_string();
+        self.read(move |conn| {
+            conn.prepare(
+                "SELECT role, content, message, created_at FROM session_messages
+                 WHERE session_id = ?1 ORDER BY id",
+            )
+            .and_then(|mut stmt| {
+                let rows = stmt.query_map(params![session_id], |row| {
+                    Ok(HistoryRow {
+                        role: row.get(0)?,
+                        content: row.get(1)?,
+                        message: row.get(2)?,
+                        created_at: row.get(3)?,
+                    })
+                })?;
+                rows.collect()
             })
-            .map(|r| r.collect::<Result<Vec<_>, _>>().unwrap_or_default())
-            .unwrap_or_default();
+            .unwrap_or_default()
+        })
+        .await
+    }
+
+    /// Recreate a session from exported messages and make it the active one.
+    /// Returns its number.
+    pub async fn import_session(&self, user_id: u64, title: &str, messages: Vec<HistoryRow>) -> Result<i64, String> {
+        let title = title.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let session_id = create_session(&tx, user_id);
+            tx.execute(
+                "UPDATE sessions SET title = NULLIF(?2, '') WHERE id = ?1",
+                params![session_id, title],
+            )
+            .map_err(|e| e.to_string())?;
+            for m in &messages {
+                tx.execute(
+                    "INSERT INTO session_messages (session_id, role, content, message, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![session_id, m.role, m.content, m.message, m.created_at],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            let number = tx
+                .query_row("SELECT rowid FROM sessions WHERE id = ?1", params![session_id], |row| row.get(0))
+                .map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(number)
+        })
+        .await
+    }
+
+    /// Full-text search over the user's past messages in every session, best
+    /// matches first. `days` limits it to messages from the last N days.
+    pub async fn search_conversations(
+        &self,
+        user_id: u64,
+        query: &str,
+        days: Option<u32>,
+        limit: usize,
+    ) -> Result<Vec<ConversationHit>, String> {
+        let Some(fts_query) = any_term_query(query) else {
+            return Ok(Vec::new());
+        };
+        let since = days.map(|d| format!("-{d} days"));
+        self.read(move |conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT s.rowid, NULLIF(s.title, ''), m.role,
+                            snippet(session_messages_fts, 0, '«', '»', '…', 24), m.created_at
+                     FROM session_messages_fts
+                     JOIN session_messages m ON m.id = session_messages_fts.rowid
+                     JOIN sessions s ON s.id = m.session_id
+                     WHERE session_messages_fts MATCH ?1 AND s.user_id = ?2
+                       AND (?3 IS NULL OR m.created_at >= datetime('now', ?3))
+                     ORDER BY rank LIMIT ?4",
+                )
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map(params![fts_query, user_id as i64, since, limit as i64], |row| {
+                    Ok(ConversationHit {
+                        session_number: row.get(0)?,
+                        session_title: row.get(1)?,
+                        role: row.get(2)?,
+                        snippet: row.get(3)?,
+                        created_at: row.get(4)?,
+                    })
+                })
+                .map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+        })
+        .await
+    }
 
-        // Reverse to chronological order
-        let mut result = rows;
-        result.reverse();
-        result
+    /// Title of a session, if it has one.
+    pub async fn session_title(&self, session_id: &str) -> Option<String> {
+        let session_id = session_id.to_string();
+        self.read(move |conn| {
+            conn.query_row(
+                "SELECT title FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .ok()
+            .flatten()
+        })
+        .await
+    }
+
+    /// Set a session's title. With `keep_existing`, a title that is already
+    /// set (e.g. by /rename while one was being generated) wins.
+    pub async fn set_session_title(&self, session_id: &str, title: &str, keep_existing: bool) -> Result<bool, String> {
+        let session_id = session_id.to_string();
+        let title = title.to_string();
+        self.write(move |conn| {
+            let sql = if keep_existing {
+                "UPDATE sessions SET title = ?2 WHERE id = ?1 AND title IS NULL"
+            } else {
+                "UPDATE sessions SET title = ?2 WHERE id = ?1"
+            };
+            let affected = conn.execute(sql, params![session_id, title]).map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
+    }
+
+    /// Delete one of the user's sessions and its messages. Returns false if it doesn't exist.
+    pub async fn delete_session(&self, user_id: u64, number: i64) -> Result<bool, String> {
+        self.write(move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let Some(session_id) = tx
+                .query_row(
+                    "SELECT id FROM sessions WHERE rowid = ?1 AND user_id = ?2",
+                    params![number, user_id as i64],
+                    |row| row.get::<_, String>(0),
+                )
+                .ok()
+            else {
+                return Ok(false);
+            };
+            tx.execute("DELETE FROM session_messages WHERE session_id = ?1", params![session_id])
+                .map_err(|e| e.to_string())?;
+            tx.execute("DELETE FROM sessions WHERE id = ?1", params![session_id])
+                .map_err(|e| e.to_string())?;
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Load recent conversation history for a session: the last `max_pairs`
+    /// user messages and everything after each of them, tool calls and results
+    /// included. Messages already folded into the session summary are left out.
+    pub async fn load_history(&self, session_id: &str, max_pairs: usize) -> Vec<Message> {
+        let session_id = session_id.to_string();
+        self.read(move |conn| {
+            // Cut at a user message so tool results never lose their calls
+            let mut stmt = match conn.prepare(
+                "SELECT role, content, message FROM session_messages
+                 WHERE session_id = ?1
+                   AND id > COALESCE((SELECT summarized_up_to FROM sessions WHERE id = ?1), 0)
+                   AND id >= COALESCE((
+                       SELECT id FROM session_messages
+                       WHERE session_id = ?1 AND role = 'user'
+                       ORDER BY id DESC LIMIT 1 OFFSET ?2
+                   ), 0)
+                 ORDER BY id"
+            ) {
+                Ok(s) => s,
+                Err(_) => return vec![],
+            };
+            let offset = max_pairs.saturating_sub(1) as i64;
+            let mut messages: Vec<Message> = stmt
+                .query_map(params![session_id, offset], |row| {
+                    Ok(history_message(row.get(0)?, row.get(1)?, row.get(2)?))
+                })
+                .map(|rows| rows.filter_map(|r| r.ok().flatten()).collect())
+                .unwrap_or_default();
+            // Pruning may have cut an exchange; start at its next user message
+            let start = messages.iter().position(|m| m.role == Role::User).unwrap_or(messages.len());
+            messages.drain(..start);
+            messages
+        })
+        .await
     }
 
-    /// Clear the active session for a user, forcing a new one on next message.
-    pub fn clear_session(&self, user_id: u64) {
-        let conn = self.conn.lock().unwrap();
-        // Delete sessions and their messages for this user
-        let session_ids: Vec<String> = conn
-            .prepare("SELECT id FROM sessions WHERE user_id = ?1")
+    /// Summary of the session's compacted history, if it has been compacted.
+    pub async fn session_summary(&self, session_id: &str) -> Option<String> {
+        let session_id = session_id.to_string();
+        self.read(move |conn| {
+            conn.query_row(
+                "SELECT summary FROM sessions WHERE id = ?1",
+                params![session_id],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .ok()
+            .flatten()
+            .filter(|s| !s.is_empty())
+        })
+        .await
+    }
+
+    /// Messages not yet folded into the session summary, oldest first: (id, role, content).
+    pub async fn unsummarized_messages(&self, session_id: &str) -> Vec<(i64, String, String)> {
+        let session_id = session_id.to_string();
+        self.read(move |conn| {
+            conn.prepare(
+                "SELECT id, role, content FROM session_messages
+                 WHERE session_id = ?1
+                   AND id > COALESCE((SELECT summarized_up_to FROM sessions WHERE id = ?1), 0)
+                 ORDER BY id",
+            )
             .and_then(|mut stmt| {
-                let rows = stmt.query_map(params![user_id as i64], |row| row.get(0))?;
+                let rows = stmt.query_map(params![session_id], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?;
                 rows.collect()
             })
-            .unwrap_or_default();
+            .unwrap_or_default()
+        })
+        .await
+    }
+
+    /// Replace the session summary with one covering every message up to `up_to_id`.
+    /// A summary covering less than the stored one is ignored (concurrent compaction).
+    pub async fn save_session_summary(&self, session_id: &str, summary: &str, up_to_id: i64) -> Result<bool, String> {
+        let session_id = session_id.to_string();
+        let summary = summary.to_string();
+        self.write(move |conn| {
+            let affected = conn
+                .execute(
+                    "UPDATE sessions SET summary = ?2, summarized_up_to = ?3
+                     WHERE id = ?1 AND summarized_up_to < ?3",
+                    params![session_id, summary, up_to_id],
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
+    }
 
-        for sid in &session_ids {
+    /// Append a message to the session history.
+    pub async fn append_message(&self, session_id: &str, role: &str, content: &str) {
+        let session_id = session_id.to_string();
+        let role = role.to_string();
+        let content = content.to_string();
+        self.write(move |conn| {
             let _ = conn.execute(
-                "DELETE FROM session_messages WHERE session_id = ?1",
-                params![sid],
+                "INSERT INTO session_messages (session_id, role, content) VALUES (?1, ?2, ?3)",
+                params![session_id, role, content],
             );
-        }
-        let _ = conn.execute(
-            "DELETE FROM sessions WHERE user_id = ?1",
-            params![user_id as i64],
-        );
+        })
+        .await
     }
 
-    /// Append a message to the session history.
-    pub fn append_message(&self, session_id: &str, role: &str, content: &str) {
-        let conn = self.conn.lock().unwrap();
-        let _ = conn.execute(
-            "INSERT INTO session_messages (session_id, role, content) VALUES (?1, ?2, ?3)",
-            params![session_id, role, content],
-        );
+    /// Append tool calls and results to the session history. `content` gets a
+    /// readable rendering for summaries; the full message is kept as JSON.
+    pub async fn append_tool_messages(&self, session_id: &str, messages: &[Message]) {
+        if messages.is_empty() {
+            return;
+        }
+        let session_id = session_id.to_string();
+        let rows: Vec<(&'static str, String, String)> = messages
+            .iter()
+            .filter_map(|m| {
+                let json = serde_json::to_string(m).ok()?;
+                Some((role_name(&m.role), history_text(&m.content), json))
+            })
+            .collect();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            for (role, content, json) in &rows {
+                tx.execute(
+                    "INSERT INTO session_messages (session_id, role, content, message) VALUES (?1, ?2, ?3, ?4)",
+                    params![session_id, role, content, json],
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+        .unwrap_or_else(|e| warn!("Failed to save tool messages: {e}"));
     }
 
     // --- Plan ---
 
-    pub fn get_plan(&self, user_id: u64) -> String {
-        let conn = self.conn.lock().unwrap();
-        conn.query_row(
-            "SELECT content FROM plans WHERE user_id = ?1",
-            params![user_id as i64],
-            |row| row.get(0),
-        )
-        .unwrap_or_default()
+    pub async fn get_plan(&self, user_id: u64) -> String {
+        self.read(move |conn| {
+            conn.query_row(
+                "SELECT content FROM plans WHERE user_id = ?1",
+                params![user_id as i64],
+                |row| row.get(0),
+            )
+            .unwrap_or_default()
+        })
+        .await
     }
 
-    pub fn set_plan(&self, user_id: u64, content: &str) -> Result<(), String> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO plans (user_id, content, updated_at) VALUES (?1, ?2, datetime('now'))
-             ON CONFLICT(user_id) DO UPDATE SET content = excluded.content, updated_at = datetime('now')",
-            params![user_id as i64, content],
-        )
-        .map_err(|e| e.to_string())?;
-        Ok(())
+    pub async fn set_plan(&self, user_id: u64, content: &str) -> Result<(), String> {
+        let content = content.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO plans (user_id, content, updated_at) VALUES (?1, ?2, datetime('now'))
+                 ON CONFLICT(user_id) DO UPDATE SET content = excluded.content, updated_at = datetime('now')",
+                params![user_id as i64, content],
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(())
+        })
+        .await
     }
 
     // --- Todo ---
 
-    pub fn add_todo(&self, user_id: u64, content: &str) -> Result<i64, String> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO todos (user_id, content) VALUES (?1, ?2)",
-            params![user_id as i64, content],
-        )
-        .map_err(|e| e.to_string())?;
-        Ok(conn.last_insert_rowid())
+    pub async fn add_todo(&self, user_id: u64, content: &str) -> Result<i64, String> {
+        let content = content.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO todos (user_id, content) VALUES (?1, ?2)",
+                params![user_id as i64, content],
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(conn.last_insert_rowid())
+        })
+        .await
     }
 
-    pub fn list_todos(&self, user_id: u64) -> Result<Vec<(i64, String, String)>, String> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn
-            .prepare(
-                "SELECT id, content, status FROM todos WHERE user_id = ?1
-                 ORDER BY CASE status WHEN 'in_progress' THEN 0 WHEN 'pending' THEN 1 ELSE 2 END, id"
+    pub async fn list_todos(&self, user_id: u64) -> Result<Vec<(i64, String, String)>, String> {
+        self.read(move |conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT id, content, status FROM todos WHERE user_id = ?1
+                     ORDER BY CASE status WHEN 'in_progress' THEN 0 WHEN 'pending' THEN 1 ELSE 2 END, id"
+                )
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map(params![user_id as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })
+                .map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+        })
+        .await
+    }
+
+    pub async fn update_todo_status(&self, user_id: u64, todo_id: i64, status: &str) -> Result<bool, String> {
+        let status = status.to_string();
+        self.write(move |conn| {
+            let completed_at = if status == "completed" {
+                "datetime('now')"
+            } else {
+                "NULL"
+            };
+            let affected = conn
+                .execute(
+                    &format!(
+                        "UPDATE todos SET status = ?1, completed_at = {} WHERE id = ?2 AND user_id = ?3",
+                        completed_at
+                    ),
+                    params![status, todo_id, user_id as i64],
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
+    }
+
+    pub async fn delete_todo(&self, user_id: u64, todo_id: i64) -> Result<bool, String> {
+        self.write(move |conn| {
+            let affected = conn
+                .execute(
+                    "DELETE FROM todos WHERE id = ?1 AND user_id = ?2",
+                    params![todo_id, user_id as i64],
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(affected > 0)
+        })
+        .await
+    }
+
+    pub async fn clear_completed_todos(&self, user_id: u64) -> Result<usize, String> {
+        self.write(move |conn| {
+            conn.execute(
+                "DELETE FROM todos WHERE user_id = ?1 AND status = 'completed'",
+                params![user_id as i64],
             )
-            .map_err(|e| e.to_string())?;
-        let rows = stmt
-            .query_map(params![user_id as i64], |row| {
-                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-            })
-            .map_err(|e| e.to_string())?;
-        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+            .map_err(|e| e.to_string())
+        })
+        .await
     }
 
-    pub fn update_todo_status(&self, user_id: u64, todo_id: i64, status: &str) -> Result<bool, String> {
-        let conn = self.conn.lock().unwrap();
-        let completed_at = if status == "completed" {
-            "datetime('now')"
-        } else {
-            "NULL"
-        };
-        let affected = conn
-            .execute(
-                &format!(
-                    "UPDATE todos SET status = ?1, completed_at = {} WHERE id = ?2 AND user_id = ?3",
-                    completed_at
-                ),
-                params![status, todo_id, user_id as i64],
+    // --- User settings ---
+
+    /// Preferred `provider:model` spec chosen via /model, if any.
+    pub async fn get_model_preference(&self, user_id: u64) -> Option<String> {
+        self.read(move |conn| {
+            conn.query_row(
+                "SELECT model FROM user_settings WHERE user_id = ?1",
+                params![user_id as i64],
+                |row| row.get::<_, Option<String>>(0),
             )
-            .map_err(|e| e.to_string())?;
-        Ok(affected > 0)
+            .ok()
+            .flatten()
+        })
+        .await
     }
 
-    pub fn delete_todo(&self, user_id: u64, todo_id: i64) -> Result<bool, String> {
-        let conn = self.conn.lock().unwrap();
-        let affected = conn
-            .execute(
-                "DELETE FROM todos WHERE id = ?1 AND user_id = ?2",
-                params![todo_id, user_id as i64],
+    /// Set (or clear with `None`) the preferred `provider:model` spec.
+    pub async fn set_model_preference(&self, user_id: u64, model: Option<&str>) -> Result<(), String> {
+        let model = model.map(str::to_string);
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO user_settings (user_id, model) VALUES (?1, ?2)
+                 ON CONFLICT(user_id) DO UPDATE SET model = excluded.model",
+                params![user_id as i64, model],
             )
             .map_err(|e| e.to_string())?;
-        Ok(affected > 0)
+            Ok(())
+        })
+        .await
     }
 
-    pub fn clear_completed_todos(&self, user_id: u64) -> Result<usize, String> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "DELETE FROM todos WHERE user_id = ?1 AND status = 'completed'",
-            params![user_id as i64],
-        )
-        .map_err(|e| e.to_string())
+    // --- Provider key state ---
+
+    pub async fn load_key_states(&self) -> Vec<KeySnapshot> {
+        self.read(move |conn| {
+            conn.prepare("SELECT provider, key_id, cooldown_until_ms, failures, disabled FROM key_states")
+                .and_then(|mut stmt| {
+                    let rows = stmt.query_map([], |row| {
+                        Ok(KeySnapshot {
+                            provider: row.get(0)?,
+                            key_id: row.get(1)?,
+                            cooldown_until_ms: row.get(2)?,
+                            failures: row.get::<_, i64>(3)? as u32,
+                            disabled: row.get(4)?,
+                        })
+                    })?;
+                    rows.collect()
+                })
+                .unwrap_or_default()
+        })
+        .await
+    }
+
+    pub async fn save_key_states(&self, snapshots: &[KeySnapshot]) -> Result<(), String> {
+        let snapshots = snapshots.to_vec();
+        self.write(move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            for snap in snapshots {
+                tx.execute(
+                    "INSERT INTO key_states (provider, key_id, cooldown_until_ms, failures, disabled, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'))
+                     ON CONFLICT(provider, key_id) DO UPDATE SET
+                        cooldown_until_ms = excluded.cooldown_until_ms,
+                        failures = excluded.failures,
+                        disabled = excluded.disabled,
+                        updated_at = excluded.updated_at",
+                    params![snap.provider, snap.key_id, snap.cooldown_until_ms, snap.failures as i64, snap.disabled],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            tx.commit().map_err(|e| e.to_string())
+        })
+        .await
     }
 
     // --- Query logs ---
 
-    pub fn log_query(
+    /// Log a finished query with its total token usage. Returns the log row id.
+    pub async fn log_query(
         &self,
         user_id: u64,
         provider: &str,
+        model: &str,
         prompt_preview: &str,
         response_time_ms: u64,
-        tokens_in: u32,
-        tokens_out: u32,
+        usage: &Usage,
+    ) -> Option<i64> {
+        let provider = provider.to_string();
+        let model = model.to_string();
+        let prompt_preview = prompt_preview.to_string();
+        let usage = usage.clone();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO query_logs (user_id, provider, model, prompt_preview, response_time_ms, tokens_in, tokens_out)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    user_id as i64,
+                    provider,
+                    model,
+                    &prompt_preview[..prompt_preview.len().min(100)],
+                    response_time_ms as i64,
+                    usage.prompt_tokens as i64,
+                    usage.completion_tokens as i64
+                ],
+            )
+            .ok()
+            .map(|_| conn.last_insert_rowid())
+        })
+        .await
+    }
+
+    /// Record the share of a query's tokens (and estimated cost) consumed by one provider/model.
+    pub async fn log_query_usage(
+        &self,
+        query_id: i64,
+        provider: &str,
+        model: &str,
+        usage: &Usage,
+        cost_usd: f64,
     ) {
-        let conn = self.conn.lock().unwrap();
-        let _ = conn.execute(
-            "INSERT INTO query_logs (user_id, provider, prompt_preview, response_time_ms, tokens_in, tokens_out)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                user_id as i64,
-                provider,
-                &prompt_preview[..prompt_preview.len().min(100)],
-                response_time_ms as i64,
-                tokens_in as i64,
-                tokens_out as i64
-            ],
+        let provider = provider.to_string();
+        let model = model.to_string();
+        let usage = usage.clone();
+        self.write(move |conn| {
+            let _ = conn.execute(
+                "INSERT INTO query_usage (query_id, provider, model, tokens_in, tokens_out, cost_usd)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    query_id,
+                    provider,
+                    model,
+                    usage.prompt_tokens as i64,
+                    usage.completion_tokens as i64,
+                    cost_usd
+                ],
+            );
+        })
+        .await
+    }
+
+    /// Usage for the current UTC day and month, for one user or (with `None`) everyone.
+    pub async fn usage_totals(&self, user_id: Option<u64>) -> UsageTotals {
+        self.read(move |conn| {
+            conn.query_row(
+                "SELECT
+                    COALESCE(SUM(CASE WHEN l.created_at >= date('now') THEN u.tokens_in + u.tokens_out END), 0),
+                    COALESCE(SUM(CASE WHEN l.created_at >= date('now') THEN u.cost_usd END), 0.0),
+                    COALESCE(SUM(u.tokens_in + u.tokens_out), 0),
+                    COALESCE(SUM(u.cost_usd), 0.0)
+                 FROM query_usage u JOIN query_logs l ON l.id = u.query_id
+                 WHERE l.created_at >= date('now', 'start of month')
+                   AND (?1 IS NULL OR l.user_id = ?1)",
+                params![user_id.map(|id| id as i64)],
+                |row| {
+                    Ok(UsageTotals {
+                        day_tokens: row.get::<_, i64>(0)? as u64,
+                        day_usd: row.get(1)?,
+                        month_tokens: row.get::<_, i64>(2)? as u64,
+                        month_usd: row.get(3)?,
+                    })
+                },
+            )
+            .unwrap_or_default()
+        })
+        .await
+    }
+    // --- Retention ---
+
+    /// Delete expired facts, and history and query logs beyond the retention
+    /// policy. Query logs from the current month are always kept, since budgets
+    /// are checked against them.
+    pub async fn prune(&self, policy: RetentionPolicy) -> Result<PruneStats, String> {
+        self.write(move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let mut stats = PruneStats {
+                facts: tx
+                    .execute(
+                        "DELETE FROM memory_facts WHERE expires_at IS NOT NULL AND expires_at <= datetime('now')",
+                        [],
+                    )
+                    .map_err(|e| e.to_string())?,
+                ..Default::default()
+            };
+
+            if policy.session_days > 0 {
+                let age = format!("-{} days", policy.session_days);
+                stats.messages += tx
+                    .execute(
+                        "DELETE FROM session_messages WHERE session_id IN
+                           (SELECT id FROM sessions WHERE last_active_at < datetime('now', ?1))",
+                        params![age],
+                    )
+                    .map_err(|e| e.to_string())?;
+                stats.sessions += tx
+                    .execute("DELETE FROM sessions WHERE last_active_at < datetime('now', ?1)", params![age])
+                    .map_err(|e| e.to_string())?;
+            }
+            if policy.max_session_messages > 0 {
+                stats.messages += tx
+                    .execute(
+                        "DELETE FROM session_messages WHERE id NOT IN
+                           (SELECT id FROM session_messages ORDER BY id DESC LIMIT ?1)",
+                        params![policy.max_session_messages as i64],
+                    )
+                    .map_err(|e| e.to_string())?;
+                // Sessions emptied by the cap, except each user's current one
+                stats.sessions += tx
+                    .execute(
+                        "DELETE FROM sessions
+                         WHERE summary IS NULL
+                           AND NOT EXISTS (SELECT 1 FROM session_messages WHERE session_id = sessions.id)
+                           AND id != (SELECT s.id FROM sessions s WHERE s.user_id = sessions.user_id
+                                      ORDER BY s.last_active_at DESC, s.rowid DESC LIMIT 1)",
+                        [],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+
+            let mut old_logs = Vec::new();
+            if policy.query_log_days > 0 {
+                old_logs.push(format!("created_at < datetime('now', '-{} days')", policy.query_log_days));
+            }
+            if policy.max_query_logs > 0 {
+                old_logs.push(format!(
+                    "id NOT IN (SELECT id FROM query_logs ORDER BY id DESC LIMIT {})",
+                    policy.max_query_logs
+                ));
+            }
+            if !old_logs.is_empty() {
+                let condition = format!(
+                    "created_at < date('now', 'start of month') AND ({})",
+                    old_logs.join(" OR ")
+                );
+                tx.execute(
+                    &format!("DELETE FROM query_usage WHERE query_id IN (SELECT id FROM query_logs WHERE {condition})"),
+                    [],
+                )
+                .map_err(|e| e.to_string())?;
+                stats.query_logs = tx
+                    .execute(&format!("DELETE FROM query_logs WHERE {condition}"), [])
+                    .map_err(|e| e.to_string())?;
+            }
+
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(stats)
+        })
+        .await
+    }
+
+    /// Return free pages to the file system. Cheap: the database runs in
+    /// incremental auto-vacuum mode, so this never rewrites the whole file.
+    pub async fn vacuum(&self) -> Result<(), String> {
+        self.write(move |conn| conn.execute_batch("PRAGMA incremental_vacuum;").map_err(|e| e.to_string()))
+            .await
+    }
+
+    /// Delete everything stored about a user: memories, conversations, todos,
+    /// plan, settings and query logs. The current month's token counts and
+    /// costs are first folded into anonymous rows (`ANONYMOUS_USER`) per day
+    /// bucket and model, so global budgets still add up. Returns the number
+    /// of rows removed.
+    pub async fn forget_user(&self, user_id: u64) -> Result<usize, String> {
+        self.write(move |conn| {
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            let user = user_id as i64;
+            // Earlier this month and today, so daily totals stay right too
+            for (stamp, range) in [
+                (
+                    "datetime('now', 'start of month')",
+                    "created_at >= date('now', 'start of month') AND created_at < date('now')",
+                ),
+                ("datetime('now', 'start of day')", "created_at >= date('now')"),
+            ] {
+                let logged = tx
+                    .execute(
+                        &format!(
+                            "INSERT INTO query_logs (user_id, tokens_in, tokens_out, created_at)
+                             SELECT ?2, SUM(tokens_in), SUM(tokens_out), {stamp}
+                             FROM query_logs WHERE user_id = ?1 AND {range}
+                             HAVING COUNT(*) > 0"
+                        ),
+                        params![user, ANONYMOUS_USER as i64],
+                    )
+                    .map_err(|e| e.to_string())?;
+                if logged == 0 {
+                    continue;
+                }
+                tx.execute(
+                    &format!(
+                        "INSERT INTO query_usage (query_id, provider, model, tokens_in, tokens_out, cost_usd)
+                         SELECT ?2, provider, model, SUM(tokens_in), SUM(tokens_out), SUM(cost_usd)
+                         FROM query_usage
+                         WHERE query_id IN (SELECT id FROM query_logs WHERE user_id = ?1 AND {range})
+                         GROUP BY provider, model"
+                    ),
+                    params![user, tx.last_insert_rowid()],
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            let mut removed = 0;
+            for sql in [
+                "DELETE FROM memory_facts WHERE user_id = ?1",
+                "DELETE FROM memory_proposals WHERE user_id = ?1",
+                "DELETE FROM session_messages WHERE session_id IN (SELECT id FROM sessions WHERE user_id = ?1)",
+                "DELETE FROM sessions WHERE user_id = ?1",
+                "DELETE FROM todos WHERE user_id = ?1",
+                "DELETE FROM plans WHERE user_id = ?1",
+                "DELETE FROM user_settings WHERE user_id = ?1",
+                "DELETE FROM query_usage WHERE query_id IN (SELECT id FROM query_logs WHERE user_id = ?1)",
+                "DELETE FROM query_logs WHERE user_id = ?1",
+            ] {
+                removed += tx.execute(sql, params![user]).map_err(|e| e.to_string())?;
+            }
+            tx.commit().map_err(|e| e.to_string())?;
+            Ok(removed)
+        })
+        .await
+    }
+}
+
+/// Owner of the usage rows left behind by `/forget_me`. Telegram never hands
+/// out user id 0, so these rows count toward global budgets only.
+const ANONYMOUS_USER: u64 = 0;
+
+/// Current UTC time with milliseconds, so sessions switched within the same
+/// second still order correctly.
+const NOW_MS: &str = "strftime('%Y-%m-%d %H:%M:%f', 'now')";
+
+fn create_session(conn: &Connection, user_id: u64) -> String {
+    // Bump the timestamp past sessions created within the same millisecond
+    let mut stamp = chrono::Utc::now().timestamp_millis();
+    loop {
+        let id = format!("{user_id}-{stamp}");
+        let inserted = conn.execute(
+            &format!("INSERT OR IGNORE INTO sessions (id, user_id, last_active_at) VALUES (?1, ?2, {NOW_MS})"),
+            params![&id, user_id as i64],
         );
+        match inserted {
+            Ok(0) => stamp += 1,
+            _ => return id,
+        }
+    }
+}
+
+/// Tool arguments and results are cut to this many bytes in the readable
+/// `content` of history rows; the stored message keeps them in full.
+const HISTORY_TEXT_BYTES: usize = 500;
+
+fn role_name(role: &Role) -> &'static str {
+    match role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::Tool => "tool",
+    }
+}
+
+/// Plain-text rendering of a message for summaries and search.
+fn history_text(content: &MessageContent) -> String {
+    match content {
+        MessageContent::AssistantWithToolCalls { text, tool_calls } => {
+            let mut out = text.clone().unwrap_or_default();
+            for tc in tool_calls {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let args = clip_text(&tc.function.arguments, HISTORY_TEXT_BYTES);
+                out.push_str(&format!("→ {}({args})", tc.function.name));
+            }
+            out
+        }
+        MessageContent::ToolResult { name, content, .. } => {
+            format!("[{name}] {}", clip_text(content, HISTORY_TEXT_BYTES))
+        }
+        other => other.as_text().to_string(),
+    }
+}
+
+/// A stored history row as a message: the saved JSON when there is one,
+/// otherwise plain text. Rows that can't be restored are skipped.
+fn history_message(role: String, content: String, message: Option<String>) -> Option<Message> {
+    if let Some(json) = message {
+        return serde_json::from_str(&json).ok();
+    }
+    let role = match role.as_str() {
+        "user" => Role::User,
+        "assistant" => Role::Assistant,
+        _ => return None,
+    };
+    Some(Message {
+        role,
+        content: MessageContent::Text(content),
+    })
+}
+
+fn clip_text(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+/// An embedding (model, vector) the blocking pool can own.
+fn owned_embedding(embedding: Option<(&str, &[f32])>) -> Option<(String, Vec<f32>)> {
+    embedding.map(|(model, vector)| (model.to_string(), vector.to_vec()))
+}
+
+fn as_embedding(embedding: &Option<(String, Vec<f32>)>) -> Option<(&str, &[f32])> {
+    embedding.as_ref().map(|(model, vector)| (model.as_str(), vector.as_slice()))
+}
+
+/// Minimum cosine similarity for a fact to count as a semantic match.
+const MIN_SIMILARITY: f32 = 0.35;
+
+/// A user's facts embedded with `model`, most similar to `query` first.
+fn semantic_search(conn: &Connection, user_id: u64, model: &str, query: &[f32]) -> Vec<(i64, String, String)> {
+    let facts: Vec<(i64, String, String, Vec<u8>)> = conn
+        .prepare(
+            "SELECT id, fact, category, embedding FROM memory_facts
+             WHERE user_id = ?1 AND embedding_model = ?2 AND embedding IS NOT NULL
+               AND (expires_at IS NULL OR expires_at > datetime('now'))",
+        )
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![user_id as i64, model], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?;
+            rows.collect()
+        })
+        .unwrap_or_default();
+
+    let mut scored: Vec<(f32, (i64, String, String))> = facts
+        .into_iter()
+        .map(|(id, fact, category, blob)| (cosine_similarity(query, &decode_vector(&blob)), (id, fact, category)))
+        .filter(|(score, _)| *score >= MIN_SIMILARITY)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.into_iter().take(20).map(|(_, hit)| hit).collect()
+}
+
+/// Merge ranked result lists with reciprocal rank fusion: each hit scores
+/// 1 / (60 + rank) per list it appears in. Best first.
+fn fuse_ranks(lists: &[&[(i64, String, String)]]) -> Vec<(f64, (i64, String, String))> {
+    let mut scored: Vec<(f64, (i64, String, String))> = Vec::new();
+    for list in lists {
+        for (rank, hit) in list.iter().enumerate() {
+            let score = 1.0 / (60.0 + rank as f64);
+            match scored.iter_mut().find(|(_, h)| h.0 == hit.0) {
+                Some((total, _)) => *total += score,
+                None => scored.push((score, hit.clone())),
+            }
+        }
+    }
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored
+}
+
+/// Ranking boost from a fact's metadata: 0.7x to 1.3x for importance, up to
+/// ~1.5x for recent use (30-day decay) and a slowly growing factor for the
+/// access count.
+fn fact_weight(conn: &Connection, fact_id: i64) -> f64 {
+    let (importance, count, days): (i64, i64, Option<f64>) = conn
+        .query_row(
+            "SELECT importance, access_count, julianday('now') - julianday(last_accessed_at)
+             FROM memory_facts WHERE id = ?1",
+            params![fact_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap_or((3, 0, None));
+    let importance = 1.0 + 0.15 * (importance.clamp(1, 5) - 3) as f64;
+    let frequency = 1.0 + 0.2 * (count.max(0) as f64).ln_1p();
+    let recency = 1.0 + 0.5 * days.map_or(0.0, |d| (-d.max(0.0) / 30.0).exp());
+    importance * frequency * recency
+}
+
+/// Delete a user's facts whose expiry has passed.
+fn purge_expired_facts(conn: &Connection, user_id: u64) {
+    let _ = conn.execute(
+        "DELETE FROM memory_facts WHERE user_id = ?1 AND expires_at IS NOT NULL AND expires_at <= datetime('now')",
+        params![user_id as i64],
+    );
+}
+
+/// Facts at least this similar (cosine) to a new one count as the same fact.
+const DUPLICATE_SIMILARITY: f32 = 0.92;
+
+/// A fact of the user saying the same as `fact`: identical once normalized, or,
+/// given its embedding, the most similar fact above `DUPLICATE_SIMILARITY`.
+fn find_duplicate(
+    conn: &Connection,
+    user_id: u64,
+    fact: &str,
+    semantic: Option<(&str, &[f32])>,
+) -> Option<(i64, String)> {
+    // (id, fact, embedding model, embedding)
+    type Row = (i64, String, Option<String>, Option<Vec<u8>>);
+    let facts: Vec<Row> = conn
+        .prepare("SELECT id, fact, embedding_model, embedding FROM memory_facts WHERE user_id = ?1")
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map(params![user_id as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?;
+            rows.collect()
+        })
+        .unwrap_or_default();
+
+    let normalized = normalize_fact(fact);
+    let mut best: Option<(f32, i64, String)> = None;
+    for (id, existing, model, blob) in facts {
+        if normalize_fact(&existing) == normalized {
+            return Some((id, existing));
+        }
+        let similarity = semantic
+            .filter(|(m, _)| model.as_deref() == Some(*m))
+            .zip(blob)
+            .map_or(0.0, |((_, vector), blob)| cosine_similarity(vector, &decode_vector(&blob)));
+        if similarity >= DUPLICATE_SIMILARITY && best.as_ref().is_none_or(|b| similarity > b.0) {
+            best = Some((similarity, id, existing));
+        }
+    }
+    best.map(|(_, id, existing)| (id, existing))
+}
+
+/// Lowercased words only, so case, punctuation and spacing don't make a fact new.
+fn normalize_fact(fact: &str) -> String {
+    fact.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// FTS5 query matching any word of free text (quoted, so punctuation and
+/// operators in the message can't break the query).
+fn any_term_query(text: &str) -> Option<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        if word.chars().count() >= 2 && !terms.contains(&word) {
+            terms.push(word);
+        }
+    }
+    if terms.is_empty() {
+        return None;
+    }
+    Some(
+        terms
+            .iter()
+            .take(20)
+            .map(|t| format!("\"{t}\""))
+            .collect::<Vec<_>>()
+            .join(" OR "),
+    )
+}
+
+/// Vectors are stored as little-endian f32 bytes.
+fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+fn decode_vector(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn log(db: &Database, user_id: u64, model: &str, tokens: u32, cost_usd: f64) -> i64 {
+        let usage = Usage { prompt_tokens: tokens, completion_tokens: tokens, ..Default::default() };
+        let id = db.log_query(user_id, "test", model, "hello", 10, &usage).await.unwrap();
+        db.log_query_usage(id, "test", model, &usage, cost_usd).await;
+        id
+    }
+
+    #[tokio::test]
+    async fn forget_user_keeps_anonymous_totals() {
+        let db = Database::open(":memory:").unwrap();
+        log(&db, 7, "small", 100, 0.5).await;
+        log(&db, 7, "small", 50, 0.25).await;
+        let earlier = log(&db, 7, "large", 10, 1.0).await;
+        log(&db, 8, "small", 5, 0.0).await;
+        // Move one query to the first of the month; skip on the 1st itself
+        let past_the_first = chrono::Utc::now().format("%d").to_string() != "01";
+        if past_the_first {
+            db.write(move |conn| {
+                conn.execute(
+                    "UPDATE query_logs SET created_at = datetime('now', 'start of month') WHERE id = ?1",
+                    params![earlier],
+                )
+                .unwrap()
+            })
+            .await;
+        }
+        let before = db.usage_totals(None).await;
+
+        db.forget_user(7).await.unwrap();
+
+        let forgotten = db.usage_totals(Some(7)).await;
+        assert_eq!(forgotten.month_tokens, 0);
+        let after = db.usage_totals(None).await;
+        assert_eq!(after.day_tokens, before.day_tokens);
+        assert_eq!(after.month_tokens, before.month_tokens);
+        assert!((after.day_usd - before.day_usd).abs() < 1e-9);
+        assert!((after.month_usd - before.month_usd).abs() < 1e-9);
+        assert_eq!(db.usage_totals(Some(8)).await.month_tokens, 10);
+        let previews: i64 = db
+            .read(|conn| {
+                conn.query_row("SELECT COUNT(*) FROM query_logs WHERE prompt_preview IS NOT NULL", [], |r| r.get(0))
+                    .unwrap()
+            })
+            .await;
+        assert_eq!(previews, 1);
     }
 }
@@ -0,0 +1,25 @@
+//! Free Agent: a Telegram AI agent routing requests over a pool of LLM providers.
+//!
+//! The `free-agent` binary runs the bot from environment configuration. Code
+//! embedding it can add its own backends before starting:
+//!
+//! ```no_run
+//! use free_agent::config::Config;
+//! use free_agent::provider::ProviderPool;
+//!
+//! # async fn start(my_provider: Box<dyn free_agent::provider::LlmProvider>) {
+//! let config = Config::from_env();
+//! let mut pool = ProviderPool::new(&config);
+//! pool.register(my_provider, vec!["api-key".into()], vec!["my-model".into()])
+//!     .expect("provider names are unique");
+//! free_agent::telegram::run_bot_with_pool(config, pool).await;
+//! # }
+//! ```
+
+mod agent;
+pub mod config;
+mod db;
+pub mod provider;
+mod skills;
+pub mod telegram;
+mod tools;
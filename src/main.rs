@@ -1,12 +1,5 @@
-mod agent;
-mod db;
-mod provider;
-mod skills;
-mod tools;
-mod config;
-mod telegram;
-
-use config::Config;
+use free_agent::config::Config;
+use free_agent::telegram;
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
@@ -23,11 +16,17 @@ async fn main() {
 
     tracing::info!("Free Agent v{}", env!("CARGO_PKG_VERSION"));
     tracing::info!(
-        "Providers: claude={}, gemini={}, groq={}, mistral={}",
+        "Providers: claude={}, gemini={}, groq={}, mistral={}, openai_compat=[{}]",
         config.claude_keys.len(),
         config.gemini_keys.len(),
         config.groq_keys.len(),
-        config.mistral_keys.len()
+        config.mistral_keys.len(),
+        config
+            .openai_compat
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
     );
 
     // Start bot
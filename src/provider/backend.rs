@@ -0,0 +1,69 @@
+use futures::future::BoxFuture;
+
+use super::capabilities;
+use super::types::*;
+
+/// What a provider/model combination can handle.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Capabilities {
+    /// Native incremental streaming (otherwise the full response is emitted at once).
+    pub streaming: bool,
+    /// Native function/tool calling.
+    pub tools: bool,
+    /// Image inputs.
+    pub vision: bool,
+    /// Structured JSON output mode.
+    pub json_mode: bool,
+    /// Context window in tokens (prompt + completion).
+    pub max_context: u32,
+}
+
+/// An LLM backend that `ProviderPool` can route requests to.
+///
+/// Implementations are stateless with respect to credentials: the pool owns key
+/// rotation, cooldowns, retries and fallback, and passes the key and model to use
+/// on every call. Code embedding the agent adds its own backends with
+/// `ProviderPool::register` and starts the bot with `telegram::run_bot_with_pool`.
+pub trait LlmProvider: Send + Sync {
+    /// Unique provider name, used in specs like `name:model`, logs and the footer.
+    fn name(&self) -> &str;
+
+    fn capabilities(&self, model: &str) -> Capabilities;
+
+    /// Rough prompt size in tokens for `model`. The default assumes ~4 characters
+    /// per token; override it for tokenizers that differ noticeably.
+    fn estimate_tokens(&self, _model: &str, messages: &[Message], tools: &[ToolDef]) -> u32 {
+        capabilities::estimate_tokens(messages, tools)
+    }
+
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>>;
+
+    /// Streaming variant of `chat`: emits text deltas and completed tool calls
+    /// through `on_event` and returns the fully assembled response.
+    /// The default waits for `chat` and emits the whole response at once.
+    fn chat_stream<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+        on_event: &'a (dyn Fn(StreamEvent) + Send + Sync),
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(async move {
+            let response = self.chat(messages, tools, api_key, model).await?;
+            if let Some(text) = response.content.as_ref().filter(|t| !t.is_empty()) {
+                on_event(StreamEvent::TextDelta(text.clone()));
+            }
+            for tc in &response.tool_calls {
+                on_event(StreamEvent::ToolCall(tc.clone()));
+            }
+            Ok(response)
+        })
+    }
+}
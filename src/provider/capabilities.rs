@@ -0,0 +1,139 @@
+use super::backend::Capabilities;
+use super::types::*;
+
+/// What a chat request needs from the model that serves it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Requirements {
+    pub tools: bool,
+    pub vision: bool,
+    pub json_mode: bool,
+    /// Rough prompt size in tokens.
+    pub context: u32,
+}
+
+impl Requirements {
+    pub fn for_request(messages: &[Message], tools: &[ToolDef], json_mode: bool) -> Self {
+        Self {
+            tools: !tools.is_empty(),
+            vision: messages
+                .iter()
+                .any(|m| matches!(m.content, MessageContent::UserWithImage { .. })),
+            json_mode,
+            context: estimate_tokens(messages, tools),
+        }
+    }
+
+    /// Names of the requirements `caps` doesn't meet (empty when it qualifies).
+    pub fn missing(&self, caps: &Capabilities) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.tools && !caps.tools {
+            missing.push("tool calling");
+        }
+        if self.vision && !caps.vision {
+            missing.push("image input");
+        }
+        if self.json_mode && !caps.json_mode {
+            missing.push("JSON mode");
+        }
+        if self.context > caps.max_context {
+            missing.push("a large enough context window");
+        }
+        missing
+    }
+
+    /// The same requirements without tool calling, for models that can
+    /// still answer in plain text when nothing supports tools.
+    pub fn without_tools(self) -> Self {
+        Self { tools: false, ..self }
+    }
+}
+
+/// Rough token estimate (~4 characters per token, images at a flat ~1k tokens).
+pub fn estimate_tokens(messages: &[Message], tools: &[ToolDef]) -> u32 {
+    let mut chars = 0usize;
+    let mut images = 0usize;
+    for m in messages {
+        chars += m.content.as_text().len();
+        match &m.content {
+            MessageContent::UserWithImage { images: imgs, .. } => images += imgs.len(),
+            MessageContent::AssistantWithToolCalls { tool_calls, .. } => {
+                chars += tool_calls
+                    .iter()
+                    .map(|tc| tc.function.name.len() + tc.function.arguments.len())
+                    .sum::<usize>();
+            }
+            _ => {}
+        }
+    }
+    for t in tools {
+        chars += t.function.name.len() + t.function.description.len() + t.function.parameters.to_string().len();
+    }
+    (chars / 4 + images * 1000).min(u32::MAX as usize) as u32
+}
+
+/// User overrides for the built-in capability guesses, from `MODEL_CAPABILITIES`.
+/// Entries match a provider name exactly or a model name by substring; first match wins.
+#[derive(Debug, Clone, Default)]
+pub struct CapabilityOverrides {
+    entries: Vec<(String, Override)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Override {
+    tools: bool,
+    vision: bool,
+    json_mode: bool,
+    max_context: Option<u32>,
+}
+
+impl CapabilityOverrides {
+    /// Parse `pattern=flag,flag,...` entries separated by `;`, e.g.
+    /// `llava=vision,ctx=4096;my-llm=tools,json`. Listed flags (`tools`, `vision`,
+    /// `json`) are enabled and the rest disabled; `ctx=N` sets the context window.
+    pub fn parse(spec: &str) -> Self {
+        let entries = spec
+            .split(';')
+            .filter_map(|item| {
+                let (pattern, flags) = item.split_once('=')?;
+                let pattern = pattern.trim().to_lowercase();
+                if pattern.is_empty() {
+                    return None;
+                }
+                let mut o = Override {
+                    tools: false,
+                    vision: false,
+                    json_mode: false,
+                    max_context: None,
+                };
+                for flag in flags.split(',').map(|f| f.trim().to_lowercase()) {
+                    match flag.as_str() {
+                        "tools" => o.tools = true,
+                        "vision" => o.vision = true,
+                        "json" => o.json_mode = true,
+                        f => o.max_context = f.strip_prefix("ctx=").and_then(|n| n.parse().ok()).or(o.max_context),
+                    }
+                }
+                Some((pattern, o))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    pub fn apply(&self, provider: &str, model: &str, caps: Capabilities) -> Capabilities {
+        let model = model.to_lowercase();
+        match self
+            .entries
+            .iter()
+            .find(|(pattern, _)| model.contains(pattern.as_str()) || provider.eq_ignore_ascii_case(pattern))
+        {
+            Some((_, o)) => Capabilities {
+                tools: o.tools,
+                vision: o.vision,
+                json_mode: o.json_mode,
+                max_context: o.max_context.unwrap_or(caps.max_context),
+                ..caps
+            },
+            None => caps,
+        }
+    }
+}
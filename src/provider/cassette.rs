@@ -0,0 +1,295 @@
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::backend::{Capabilities, LlmProvider};
+use super::types::*;
+
+/// One recorded provider call: the normalized request and the response it produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub provider: String,
+    pub model: String,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub tools: Vec<ToolDef>,
+    pub response: LlmResponse,
+}
+
+/// Load a cassette written by `Recorder` (a JSON array of interactions).
+pub fn load_cassette(path: &str) -> Result<Vec<Interaction>, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    serde_json::from_str(&data).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Appends every interaction to a cassette file, rewriting it after each call
+/// so a crash never loses what was recorded so far.
+pub struct Recorder {
+    path: String,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Recorder {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            interactions: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn record(&self, interaction: Interaction) {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(interaction);
+        let result = serde_json::to_string_pretty(&*interactions)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&self.path, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("Failed to write cassette {}: {e}", self.path);
+        }
+    }
+}
+
+/// Wraps a live provider and records each successful call to a cassette.
+pub struct RecordingProvider {
+    inner: Box<dyn LlmProvider>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingProvider {
+    pub fn new(inner: Box<dyn LlmProvider>, recorder: Arc<Recorder>) -> Self {
+        Self { inner, recorder }
+    }
+
+    fn record(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        model: &str,
+        result: &Result<LlmResponse, ProviderError>,
+    ) {
+        if let Ok(response) = result {
+            self.recorder.record(Interaction {
+                provider: self.inner.name().to_string(),
+                model: model.to_string(),
+                messages: messages.to_vec(),
+                tools: tools.to_vec(),
+                response: response.clone(),
+            });
+        }
+    }
+}
+
+impl LlmProvider for RecordingProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn capabilities(&self, model: &str) -> Capabilities {
+        self.inner.capabilities(model)
+    }
+
+    fn estimate_tokens(&self, model: &str, messages: &[Message], tools: &[ToolDef]) -> u32 {
+        self.inner.estimate_tokens(model, messages, tools)
+    }
+
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(async move {
+            let result = self.inner.chat(messages, tools, api_key, model).await;
+            self.record(messages, tools, model, &result);
+            result
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+        on_event: &'a (dyn Fn(StreamEvent) + Send + Sync),
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(async move {
+            let result = self.inner.chat_stream(messages, tools, api_key, model, on_event).await;
+            self.record(messages, tools, model, &result);
+            result
+        })
+    }
+}
+
+/// Serves recorded interactions of one provider back in order, without network access.
+pub struct ReplayProvider {
+    name: String,
+    interactions: Vec<Interaction>,
+    cursor: AtomicUsize,
+}
+
+impl ReplayProvider {
+    pub fn new(name: &str, interactions: Vec<Interaction>) -> Self {
+        Self {
+            name: name.to_string(),
+            interactions,
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split a cassette into one replay provider per recorded provider name.
+    pub fn from_cassette(interactions: Vec<Interaction>) -> Vec<(Self, Vec<String>)> {
+        let mut names: Vec<String> = Vec::new();
+        for i in &interactions {
+            if !names.contains(&i.provider) {
+                names.push(i.provider.clone());
+            }
+        }
+        names
+            .into_iter()
+            .map(|name| {
+                let own: Vec<Interaction> =
+                    interactions.iter().filter(|i| i.provider == name).cloned().collect();
+                let mut models: Vec<String> = Vec::new();
+                for i in &own {
+                    if !models.contains(&i.model) {
+                        models.push(i.model.clone());
+                    }
+                }
+                (Self::new(&name, own), models)
+            })
+            .collect()
+    }
+}
+
+impl LlmProvider for ReplayProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: false,
+            tools: true,
+            vision: true,
+            json_mode: true,
+            max_context: u32::MAX,
+        }
+    }
+
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        _tools: &'a [ToolDef],
+        _api_key: &'a str,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(async move {
+            let idx = self.cursor.fetch_add(1, Ordering::Relaxed);
+            let recorded = self.interactions.get(idx).ok_or_else(|| {
+                ProviderError::RequestError(format!(
+                    "Cassette exhausted for {} after {} interactions",
+                    self.name,
+                    self.interactions.len()
+                ))
+            })?;
+
+            // Requests should line up with the recording; drift usually means the test changed
+            let last = |m: &[Message]| m.last().map(|m| m.content.as_text().to_string());
+            if recorded.model != model || last(&recorded.messages) != last(messages) {
+                warn!("{}: request #{idx} differs from the recording", self.name);
+            }
+            Ok(recorded.response.clone())
+        })
+    }
+}
+
+/// Mock provider returning a fixed script of responses, e.g. a tool call followed
+/// by a final answer, for exercising the agent loop without a backend.
+#[cfg(test)]
+pub struct ScriptedProvider {
+    name: String,
+    responses: Mutex<VecDeque<Result<LlmResponse, ProviderError>>>,
+}
+
+#[cfg(test)]
+impl ScriptedProvider {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            responses: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queue a response that calls `tool` with the given JSON arguments.
+    pub fn tool_call(self, tool: &str, arguments: serde_json::Value) -> Self {
+        let call_id = format!("call_{}", self.responses.lock().unwrap().len());
+        self.respond(LlmResponse {
+            content: None,
+            tool_calls: vec![ToolCall {
+                id: call_id,
+                function: ToolCallFunction {
+                    name: tool.to_string(),
+                    arguments: arguments.to_string(),
+                },
+            }],
+            usage: Usage::default(),
+        })
+    }
+
+    /// Queue a plain text response.
+    pub fn text(self, text: &str) -> Self {
+        self.respond(LlmResponse {
+            content: Some(text.to_string()),
+            tool_calls: Vec::new(),
+            usage: Usage::default(),
+        })
+    }
+
+    pub fn respond(self, response: LlmResponse) -> Self {
+        self.responses.lock().unwrap().push_back(Ok(response));
+        self
+    }
+
+    /// Queue a failed call, e.g. a transient error the pool should retry.
+    pub fn fail(self, error: ProviderError) -> Self {
+        self.responses.lock().unwrap().push_back(Err(error));
+        self
+    }
+}
+
+#[cfg(test)]
+impl LlmProvider for ScriptedProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: false,
+            tools: true,
+            vision: true,
+            json_mode: true,
+            max_context: u32::MAX,
+        }
+    }
+
+    fn chat<'a>(
+        &'a self,
+        _messages: &'a [Message],
+        _tools: &'a [ToolDef],
+        _api_key: &'a str,
+        _model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        let next = self.responses.lock().unwrap().pop_front();
+        Box::pin(async move {
+            next.unwrap_or_else(|| Err(ProviderError::RequestError(format!("{}: script exhausted", self.name))))
+        })
+    }
+}
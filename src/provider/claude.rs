@@ -1,53 +1,153 @@
+use futures::future::BoxFuture;
 use reqwest::Client;
 use serde_json::json;
 use tracing::debug;
 
+use super::backend::{Capabilities, LlmProvider};
+use super::keys::retry_after_from_headers;
+use super::stream::SseReader;
 use super::types::*;
 
 pub struct ClaudeProvider {
     client: Client,
-    model: String,
+}
+
+impl Default for ClaudeProvider {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ClaudeProvider {
+    pub const DEFAULT_MODEL: &'static str = "claude-sonnet-4-20250514";
+
     pub fn new() -> Self {
         Self {
             client: Client::new(),
-            model: "claude-sonnet-4-20250514".into(),
         }
     }
 
-    #[allow(dead_code)]
-    pub fn with_model(model: &str) -> Self {
-        Self {
-            client: Client::new(),
-            model: model.into(),
-        }
+    async fn complete(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        api_key: &str,
+        model: &str,
+    ) -> Result<LlmResponse, ProviderError> {
+        let body = build_body(messages, tools, model);
+        let resp = self.send(&body, api_key).await?;
+        parse_claude_response(resp).await
     }
 
-    pub async fn chat(
+    async fn complete_stream(
         &self,
         messages: &[Message],
         tools: &[ToolDef],
         api_key: &str,
+        model: &str,
+        on_event: &(dyn Fn(StreamEvent) + Send + Sync),
     ) -> Result<LlmResponse, ProviderError> {
-        let (system_prompt, api_messages) = build_claude_messages(messages);
+        let mut body = build_body(messages, tools, model);
+        body["stream"] = json!(true);
+        let resp = self.send(&body, api_key).await?;
 
-        let mut body = json!({
-            "model": self.model,
-            "max_tokens": 8192,
-            "messages": api_messages,
-        });
+        let mut reader = SseReader::new(resp);
+        let mut text = String::new();
+        let mut tool_calls: Vec<ToolCall> = Vec::new();
+        // (id, name, partial input JSON) of the tool_use block being streamed
+        let mut pending_tool: Option<(String, String, String)> = None;
+        let mut usage = Usage::default();
 
-        if !system_prompt.is_empty() {
-            body["system"] = json!(system_prompt);
-        }
+        while let Some(data) = reader.next_event().await? {
+            let event: serde_json::Value = match serde_json::from_str(&data) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
 
-        if !tools.is_empty() {
-            body["tools"] = build_claude_tools(tools);
+            match event["type"].as_str() {
+                Some("message_start") => {
+                    usage = parse_usage(&event["message"]["usage"]);
+                }
+                Some("content_block_start") => {
+                    let block = &event["content_block"];
+                    match block["type"].as_str() {
+                        Some("tool_use") => {
+                            pending_tool = Some((
+                                block["id"].as_str().unwrap_or("").to_string(),
+                                block["name"].as_str().unwrap_or("").to_string(),
+                                String::new(),
+                            ));
+                        }
+                        // Separate text blocks the same way the non-streaming parser does
+                        Some("text") if !text.is_empty() => {
+                            text.push('\n');
+                            on_event(StreamEvent::TextDelta("\n".into()));
+                        }
+                        _ => {}
+                    }
+                }
+                Some("content_block_delta") => {
+                    let delta = &event["delta"];
+                    match delta["type"].as_str() {
+                        Some("text_delta") => {
+                            if let Some(t) = delta["text"].as_str() {
+                                text.push_str(t);
+                                on_event(StreamEvent::TextDelta(t.to_string()));
+                            }
+                        }
+                        Some("input_json_delta") => {
+                            if let (Some((_, _, input)), Some(partial)) =
+                                (pending_tool.as_mut(), delta["partial_json"].as_str())
+                            {
+                                input.push_str(partial);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Some("content_block_stop") => {
+                    if let Some((id, name, input)) = pending_tool.take() {
+                        let tc = ToolCall {
+                            id,
+                            function: ToolCallFunction {
+                                name,
+                                arguments: if input.is_empty() { "{}".into() } else { input },
+                            },
+                        };
+                        on_event(StreamEvent::ToolCall(tc.clone()));
+                        tool_calls.push(tc);
+                    }
+                }
+                Some("message_delta") => {
+                    if let Some(out) = event["usage"]["output_tokens"].as_u64() {
+                        usage.completion_tokens = out as u32;
+                    }
+                }
+                Some("error") => {
+                    let msg = event["error"]["message"].as_str().unwrap_or("unknown error");
+                    let msg = format!("Stream error: {msg}");
+                    return Err(match event["error"]["type"].as_str() {
+                        Some("overloaded_error" | "api_error") => ProviderError::Transient(msg),
+                        _ => ProviderError::RequestError(msg),
+                    });
+                }
+                _ => {}
+            }
         }
 
-        debug!("Claude API request: model={}", self.model);
+        Ok(LlmResponse {
+            content: if text.is_empty() { None } else { Some(text) },
+            tool_calls,
+            usage,
+        })
+    }
+
+    async fn send(
+        &self,
+        body: &serde_json::Value,
+        api_key: &str,
+    ) -> Result<reqwest::Response, ProviderError> {
+        debug!("Claude API request: model={}", body["model"].as_str().unwrap_or(""));
 
         let resp = self
             .client
@@ -55,24 +155,108 @@ impl ClaudeProvider {
             .header("x-api-key", api_key)
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
-            .json(&body)
+            .json(body)
             .send()
             .await
-            .map_err(|e| ProviderError::RequestError(e.to_string()))?;
+            .map_err(ProviderError::from_reqwest)?;
 
         let status = resp.status();
         if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-            return Err(ProviderError::RateLimited);
+            return Err(ProviderError::RateLimited(retry_after_from_headers(resp.headers())));
         }
-        if status == reqwest::StatusCode::UNAUTHORIZED {
-            return Err(ProviderError::AuthError("Invalid API key".into()));
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(ProviderError::AuthError(format!("Invalid API key (HTTP {status})")));
         }
         if !status.is_success() {
             let text = resp.text().await.unwrap_or_default();
-            return Err(ProviderError::RequestError(format!("HTTP {status}: {text}")));
+            return Err(ProviderError::from_status(status, &text));
         }
 
-        parse_claude_response(resp).await
+        Ok(resp)
+    }
+}
+
+impl LlmProvider for ClaudeProvider {
+    fn name(&self) -> &str {
+        "claude"
+    }
+
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            tools: true,
+            vision: true,
+            json_mode: false,
+            max_context: 200_000,
+        }
+    }
+
+    fn estimate_tokens(&self, _model: &str, messages: &[Message], tools: &[ToolDef]) -> u32 {
+        // Claude's tokenizer averages closer to 3.5 characters per token
+        let base = super::capabilities::estimate_tokens(messages, tools) as u64;
+        (base * 8 / 7).min(u32::MAX as u64) as u32
+    }
+
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(self.complete(messages, tools, api_key, model))
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+        on_event: &'a (dyn Fn(StreamEvent) + Send + Sync),
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(self.complete_stream(messages, tools, api_key, model, on_event))
+    }
+}
+
+fn build_body(messages: &[Message], tools: &[ToolDef], model: &str) -> serde_json::Value {
+    let (system_prompt, api_messages) = build_claude_messages(messages);
+
+    let mut body = json!({
+        "model": model,
+        "max_tokens": 8192,
+        "messages": api_messages,
+    });
+
+    // Cache breakpoints: tools, then system, then the conversation so far. Tools and
+    // system are resent unchanged on every agent turn, and each turn only appends to
+    // the conversation, so the next turn reads everything up to its last message from cache.
+    if !system_prompt.is_empty() {
+        body["system"] = json!([{
+            "type": "text",
+            "text": system_prompt,
+            "cache_control": { "type": "ephemeral" },
+        }]);
+    }
+
+    if !tools.is_empty() {
+        body["tools"] = build_claude_tools(tools);
+    }
+
+    if let Some(last) = body["messages"].as_array_mut().and_then(|m| m.last_mut()) {
+        mark_cache_breakpoint(last);
+    }
+
+    body
+}
+
+/// Put a `cache_control` breakpoint on the last content block of a message.
+fn mark_cache_breakpoint(message: &mut serde_json::Value) {
+    if let Some(text) = message["content"].as_str() {
+        message["content"] = json!([{ "type": "text", "text": text }]);
+    }
+    if let Some(block) = message["content"].as_array_mut().and_then(|b| b.last_mut()) {
+        block["cache_control"] = json!({ "type": "ephemeral" });
     }
 }
 
@@ -200,7 +384,7 @@ fn build_claude_messages(messages: &[Message]) -> (String, Vec<serde_json::Value
 /// Convert our generic ToolDef format to Claude tool format.
 /// Claude uses { name, description, input_schema } instead of OpenAI's { type: "function", function: { ... } }
 fn build_claude_tools(tools: &[ToolDef]) -> serde_json::Value {
-    let claude_tools: Vec<serde_json::Value> = tools
+    let mut claude_tools: Vec<serde_json::Value> = tools
         .iter()
         .map(|t| {
             json!({
@@ -211,6 +395,11 @@ fn build_claude_tools(tools: &[ToolDef]) -> serde_json::Value {
         })
         .collect();
 
+    // Tool definitions rarely change, so they are cached as the first prefix
+    if let Some(last) = claude_tools.last_mut() {
+        last["cache_control"] = json!({ "type": "ephemeral" });
+    }
+
     json!(claude_tools)
 }
 
@@ -256,14 +445,25 @@ async fn parse_claude_response(resp: reqwest::Response) -> Result<LlmResponse, P
         Some(text_parts.join("\n"))
     };
 
-    let usage = Usage {
-        prompt_tokens: body["usage"]["input_tokens"].as_u64().unwrap_or(0) as u32,
-        completion_tokens: body["usage"]["output_tokens"].as_u64().unwrap_or(0) as u32,
-    };
-
     Ok(LlmResponse {
         content,
         tool_calls,
-        usage,
+        usage: parse_usage(&body["usage"]),
     })
 }
+
+/// Claude reports cached input separately from `input_tokens`; fold it into
+/// `prompt_tokens` so usage means the same across providers.
+fn parse_usage(usage: &serde_json::Value) -> Usage {
+    let field = |name: &str| usage[name].as_u64().unwrap_or(0) as u32;
+    let cache_read_tokens = field("cache_read_input_tokens");
+    let cache_write_tokens = field("cache_creation_input_tokens");
+    Usage {
+        prompt_tokens: field("input_tokens")
+            .saturating_add(cache_read_tokens)
+            .saturating_add(cache_write_tokens),
+        completion_tokens: field("output_tokens"),
+        cache_read_tokens,
+        cache_write_tokens,
+    }
+}
@@ -0,0 +1,268 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::config::Config;
+
+use super::keys::retry_after_from_headers;
+use super::types::ProviderError;
+
+/// Turns text into vectors for semantic search.
+pub trait Embedder: Send + Sync {
+    /// Identifies the vector space, e.g. `gemini/gemini-embedding-001`.
+    /// Vectors from different models are never compared.
+    fn model(&self) -> &str;
+
+    /// One vector per input text, in order.
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, ProviderError>>;
+}
+
+/// Build the embedder selected by `EMBEDDINGS_PROVIDER` (Gemini when unset and
+/// Gemini keys are configured), if any.
+pub fn embedder_from_config(config: &Config) -> Option<Box<dyn Embedder>> {
+    let provider = match config.embeddings_provider.as_deref() {
+        Some(p) => p,
+        // Gemini keys come with a free embeddings API, so use it unless told otherwise
+        None if !config.gemini_keys.is_empty() => "gemini",
+        None => return None,
+    };
+    let model = config.embeddings_model.clone();
+    let embedder: Box<dyn Embedder> = match provider {
+        "none" | "off" => return None,
+        "gemini" if !config.gemini_keys.is_empty() => Box::new(GeminiEmbedder::new(
+            model.unwrap_or_else(|| GeminiEmbedder::DEFAULT_MODEL.into()),
+            config.gemini_keys.clone(),
+        )),
+        "mistral" if !config.mistral_keys.is_empty() => Box::new(OpenAiEmbedder::new(
+            "mistral",
+            "https://api.mistral.ai/v1",
+            model.unwrap_or_else(|| "mistral-embed".into()),
+            config.mistral_keys.clone(),
+            Vec::new(),
+        )),
+        name => {
+            let Some(cfg) = config.openai_compat.iter().find(|c| c.name == name) else {
+                warn!("Embeddings provider '{name}' is not configured, semantic memory search disabled");
+                return None;
+            };
+            let Some(model) = model else {
+                warn!("EMBEDDINGS_MODEL is required for '{name}', semantic memory search disabled");
+                return None;
+            };
+            Box::new(OpenAiEmbedder::new(name, &cfg.base_url, model, cfg.keys.clone(), cfg.headers.clone()))
+        }
+    };
+    info!("Embeddings: {}", embedder.model());
+    Some(embedder)
+}
+
+/// Cosine similarity of two vectors (0 when either is empty or they differ in length).
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let (mut dot, mut na, mut nb) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        na += x * x;
+        nb += y * y;
+    }
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na.sqrt() * nb.sqrt())
+    }
+}
+
+/// Round-robin over API keys (a single empty key for keyless servers).
+struct KeyCycle {
+    keys: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl KeyCycle {
+    fn new(keys: Vec<String>) -> Self {
+        let keys = if keys.is_empty() { vec![String::new()] } else { keys };
+        Self {
+            keys,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn next(&self) -> &str {
+        &self.keys[self.next.fetch_add(1, Ordering::Relaxed) % self.keys.len()]
+    }
+}
+
+async fn check_status(resp: reqwest::Response) -> Result<reqwest::Response, ProviderError> {
+    let status = resp.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(ProviderError::RateLimited(retry_after_from_headers(resp.headers())));
+    }
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(ProviderError::AuthError(format!("HTTP {status}")));
+    }
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(ProviderError::from_status(status, &text));
+    }
+    Ok(resp)
+}
+
+fn parse_vector(value: &serde_json::Value) -> Option<Vec<f32>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| v.as_f64().map(|f| f as f32))
+        .collect()
+}
+
+/// Google's `batchEmbedContents` API.
+struct GeminiEmbedder {
+    client: Client,
+    /// Model name sent to the API.
+    api_model: String,
+    /// `gemini/<model>`
+    id: String,
+    keys: KeyCycle,
+}
+
+impl GeminiEmbedder {
+    const DEFAULT_MODEL: &'static str = "gemini-embedding-001";
+    /// Truncated output keeps stored vectors small; quality loss is minor.
+    const DIMENSIONS: u32 = 768;
+
+    fn new(model: String, keys: Vec<String>) -> Self {
+        Self {
+            client: Client::new(),
+            id: format!("gemini/{model}"),
+            api_model: model,
+            keys: KeyCycle::new(keys),
+        }
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        let model = &self.api_model;
+        let url = format!(
+            "https://generativelanguage.googleapis.com/v1beta/models/{model}:batchEmbedContents"
+        );
+        let requests: Vec<serde_json::Value> = texts
+            .iter()
+            .map(|text| {
+                json!({
+                    "model": format!("models/{model}"),
+                    "content": { "parts": [{ "text": text }] },
+                    "outputDimensionality": Self::DIMENSIONS,
+                })
+            })
+            .collect();
+        let resp = self
+            .client
+            .post(&url)
+            .header("x-goog-api-key", self.keys.next())
+            .json(&json!({ "requests": requests }))
+            .send()
+            .await
+            .map_err(ProviderError::from_reqwest)?;
+        let body: serde_json::Value = check_status(resp)
+            .await?
+            .json()
+            .await
+            .map_err(|e| ProviderError::ParseError(e.to_string()))?;
+
+        body["embeddings"]
+            .as_array()
+            .map(|items| items.iter().filter_map(|e| parse_vector(&e["values"])).collect::<Vec<_>>())
+            .filter(|vectors| vectors.len() == texts.len())
+            .ok_or_else(|| ProviderError::ParseError("Unexpected embeddings response".into()))
+    }
+}
+
+impl Embedder for GeminiEmbedder {
+    fn model(&self) -> &str {
+        &self.id
+    }
+
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, ProviderError>> {
+        Box::pin(self.embed_batch(texts))
+    }
+}
+
+/// OpenAI-style `/embeddings` endpoint (OpenAI, Mistral, Ollama, llama.cpp, vLLM, ...).
+struct OpenAiEmbedder {
+    client: Client,
+    base_url: String,
+    /// Model name sent to the API.
+    api_model: String,
+    /// `<provider>/<model>`
+    id: String,
+    keys: KeyCycle,
+    headers: Vec<(String, String)>,
+}
+
+impl OpenAiEmbedder {
+    fn new(
+        provider: &str,
+        base_url: &str,
+        model: String,
+        keys: Vec<String>,
+        headers: Vec<(String, String)>,
+    ) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            id: format!("{provider}/{model}"),
+            api_model: model,
+            keys: KeyCycle::new(keys),
+            headers,
+        }
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        let mut req = self
+            .client
+            .post(format!("{}/embeddings", self.base_url))
+            .json(&json!({ "model": self.api_model, "input": texts }));
+        let key = self.keys.next();
+        if !key.is_empty() {
+            req = req.bearer_auth(key);
+        }
+        for (name, value) in &self.headers {
+            req = req.header(name, value);
+        }
+        let resp = req.send().await.map_err(ProviderError::from_reqwest)?;
+        let body: serde_json::Value = check_status(resp)
+            .await?
+            .json()
+            .await
+            .map_err(|e| ProviderError::ParseError(e.to_string()))?;
+
+        let mut items: Vec<(u64, Vec<f32>)> = body["data"]
+            .as_array()
+            .map(|data| {
+                data.iter()
+                    .enumerate()
+                    .filter_map(|(i, d)| Some((d["index"].as_u64().unwrap_or(i as u64), parse_vector(&d["embedding"])?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if items.len() != texts.len() {
+            return Err(ProviderError::ParseError("Unexpected embeddings response".into()));
+        }
+        items.sort_by_key(|(i, _)| *i);
+        Ok(items.into_iter().map(|(_, v)| v).collect())
+    }
+}
+
+impl Embedder for OpenAiEmbedder {
+    fn model(&self) -> &str {
+        &self.id
+    }
+
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, ProviderError>> {
+        Box::pin(self.embed_batch(texts))
+    }
+}
@@ -0,0 +1,340 @@
+//! Prompt-based tool calling for models without native function calling.
+//!
+//! Tool schemas are described in the system prompt, the model replies with
+//! `<tool_call>{"name": ..., "arguments": {...}}</tool_call>` blocks which are parsed
+//! into real `ToolCall`s, and tool results are fed back as `<tool_result>` text.
+
+use std::sync::Mutex;
+
+use serde_json::json;
+
+use super::backend::LlmProvider;
+use super::types::*;
+
+const CALL_OPEN: &str = "<tool_call>";
+const CALL_CLOSE: &str = "</tool_call>";
+/// Llama-style calls some models produce on their own: `<function=name>{...}</function>`.
+const FUNCTION_OPEN: &str = "<function=";
+const FUNCTION_CLOSE: &str = "</function>";
+
+/// Run a chat request with emulated tools against a text-only model.
+pub async fn chat(
+    provider: &dyn LlmProvider,
+    messages: &[Message],
+    tools: &[ToolDef],
+    api_key: &str,
+    model: &str,
+    on_event: Option<&(dyn Fn(StreamEvent) + Send + Sync)>,
+) -> Result<LlmResponse, ProviderError> {
+    let messages = rewrite_messages(messages, tools);
+
+    let mut response = match on_event {
+        Some(f) => {
+            // Forward prose as it streams, but never the raw tool-call markup
+            let filter = DeltaFilter::default();
+            let on_text = |event: StreamEvent| match event {
+                StreamEvent::TextDelta(delta) => {
+                    if let Some(text) = filter.push(&delta) {
+                        f(StreamEvent::TextDelta(text));
+                    }
+                }
+                other => f(other),
+            };
+            provider.chat_stream(&messages, &[], api_key, model, &on_text).await?
+        }
+        None => provider.chat(&messages, &[], api_key, model).await?,
+    };
+
+    let (text, tool_calls) = parse_tool_calls(response.content.as_deref().unwrap_or(""));
+    if let Some(f) = on_event {
+        for tc in &tool_calls {
+            f(StreamEvent::ToolCall(tc.clone()));
+        }
+    }
+    response.content = Some(text).filter(|t| !t.is_empty());
+    response.tool_calls = tool_calls;
+    Ok(response)
+}
+
+/// Describe the tools in the system prompt and turn native tool-call/result
+/// messages into the text protocol.
+fn rewrite_messages(messages: &[Message], tools: &[ToolDef]) -> Vec<Message> {
+    let instructions = tool_instructions(tools);
+    let mut out: Vec<Message> = Vec::with_capacity(messages.len() + 1);
+    let mut has_system = false;
+
+    for m in messages {
+        match (&m.role, &m.content) {
+            (Role::System, _) if !has_system => {
+                has_system = true;
+                out.push(Message {
+                    role: Role::System,
+                    content: MessageContent::Text(format!("{}\n\n{instructions}", m.content.as_text())),
+                });
+            }
+            (_, MessageContent::AssistantWithToolCalls { text, tool_calls }) => {
+                let mut parts: Vec<String> = text.iter().filter(|t| !t.is_empty()).cloned().collect();
+                for tc in tool_calls {
+                    let arguments: serde_json::Value =
+                        serde_json::from_str(&tc.function.arguments).unwrap_or_else(|_| json!({}));
+                    let call = json!({ "name": tc.function.name, "arguments": arguments });
+                    parts.push(format!("{CALL_OPEN}\n{call}\n{CALL_CLOSE}"));
+                }
+                out.push(Message {
+                    role: Role::Assistant,
+                    content: MessageContent::Text(parts.join("\n")),
+                });
+            }
+            (_, MessageContent::ToolResult { name, content, .. }) => {
+                let block = format!("<tool_result name=\"{name}\">\n{content}\n</tool_result>");
+                // Keep roles alternating: results of one turn go into a single user message
+                match out.last_mut() {
+                    Some(Message {
+                        role: Role::User,
+                        content: MessageContent::Text(prev),
+                    }) if prev.starts_with("<tool_result") => {
+                        prev.push_str("\n\n");
+                        prev.push_str(&block);
+                    }
+                    _ => out.push(Message {
+                        role: Role::User,
+                        content: MessageContent::Text(block),
+                    }),
+                }
+            }
+            _ => out.push(m.clone()),
+        }
+    }
+
+    if !has_system {
+        out.insert(
+            0,
+            Message {
+                role: Role::System,
+                content: MessageContent::Text(instructions),
+            },
+        );
+    }
+    out
+}
+
+fn tool_instructions(tools: &[ToolDef]) -> String {
+    let mut text = format!(
+        "# Tools\n\
+         You can call tools. To call one, reply with a block exactly like this \
+         (one block per call, several blocks allowed):\n\
+         {CALL_OPEN}\n{{\"name\": \"tool_name\", \"arguments\": {{\"param\": \"value\"}}}}\n{CALL_CLOSE}\n\
+         Write nothing after your tool calls. Results come back in the next message \
+         as <tool_result> blocks. Never invent tool results. When you have what you need, \
+         answer normally without any {CALL_OPEN} block.\n\n\
+         Available tools:"
+    );
+    for t in tools {
+        text.push_str(&format!(
+            "\n- {}: {}\n  parameters: {}",
+            t.function.name, t.function.description, t.function.parameters
+        ));
+    }
+    text
+}
+
+/// Split a model reply into its prose and the tool calls it contains.
+fn parse_tool_calls(text: &str) -> (String, Vec<ToolCall>) {
+    let mut prose = String::new();
+    let mut calls = Vec::new();
+    let mut rest = text;
+
+    loop {
+        let next = [(CALL_OPEN, CALL_CLOSE), (FUNCTION_OPEN, FUNCTION_CLOSE)]
+            .into_iter()
+            .filter_map(|(open, close)| rest.find(open).map(|pos| (pos, open, close)))
+            .min_by_key(|(pos, _, _)| *pos);
+        let Some((pos, open, close)) = next else {
+            prose.push_str(rest);
+            break;
+        };
+        prose.push_str(&rest[..pos]);
+        let after = &rest[pos + open.len()..];
+        // A missing closing tag means the block runs to the end of the reply
+        let (inner, remaining) = match after.find(close) {
+            Some(end) => (&after[..end], &after[end + close.len()..]),
+            None => (after, ""),
+        };
+        let call = if open == FUNCTION_OPEN {
+            parse_function_block(inner)
+        } else {
+            parse_json_block(inner)
+        };
+        if let Some((name, arguments)) = call {
+            calls.push(ToolCall {
+                id: format!("call_{}", uuid::Uuid::new_v4().simple()),
+                function: ToolCallFunction { name, arguments },
+            });
+        }
+        rest = remaining;
+    }
+
+    (prose.trim().to_string(), calls)
+}
+
+/// `{"name": "...", "arguments": {...}}`, optionally inside a ```json fence.
+fn parse_json_block(inner: &str) -> Option<(String, String)> {
+    let body = inner
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    let name = value["name"].as_str()?.to_string();
+    let args = if value["arguments"].is_null() {
+        &value["parameters"]
+    } else {
+        &value["arguments"]
+    };
+    Some((name, normalize_arguments(args)))
+}
+
+/// `name>{...}` — the part after `<function=`.
+fn parse_function_block(inner: &str) -> Option<(String, String)> {
+    let (name, args) = inner.split_once('>')?;
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return None;
+    }
+    let args: serde_json::Value = serde_json::from_str(args.trim()).unwrap_or_else(|_| json!({}));
+    Some((name, normalize_arguments(&args)))
+}
+
+/// Arguments as a JSON object string; models sometimes send them pre-encoded.
+fn normalize_arguments(args: &serde_json::Value) -> String {
+    match args {
+        serde_json::Value::Object(_) => args.to_string(),
+        serde_json::Value::String(s) if serde_json::from_str::<serde_json::Value>(s).is_ok() => s.clone(),
+        _ => "{}".into(),
+    }
+}
+
+/// Passes streamed prose through while holding back anything that may be the
+/// start of a tool-call block.
+#[derive(Default)]
+struct DeltaFilter {
+    state: Mutex<FilterState>,
+}
+
+#[derive(Default)]
+struct FilterState {
+    buf: String,
+    forwarded: usize,
+    /// A tool-call block has started; nothing more is forwarded.
+    stopped: bool,
+}
+
+impl DeltaFilter {
+    fn push(&self, delta: &str) -> Option<String> {
+        let mut st = self.state.lock().unwrap();
+        if st.stopped {
+            return None;
+        }
+        st.buf.push_str(delta);
+
+        let pending = &st.buf[st.forwarded..];
+        let marker = [CALL_OPEN, FUNCTION_OPEN]
+            .iter()
+            .filter_map(|m| pending.find(m))
+            .min();
+        let safe = match marker {
+            Some(pos) => pos,
+            // Hold back a trailing '<...' that could still grow into a marker
+            None => match pending.rfind('<') {
+                Some(pos)
+                    if CALL_OPEN.starts_with(&pending[pos..]) || FUNCTION_OPEN.starts_with(&pending[pos..]) =>
+                {
+                    pos
+                }
+                _ => pending.len(),
+            },
+        };
+        let text = pending[..safe].to_string();
+        st.stopped = marker.is_some();
+        st.forwarded += safe;
+        Some(text).filter(|t| !t.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arguments: &str) -> String {
+        format!("{CALL_OPEN}{{\"name\": \"{name}\", \"arguments\": {arguments}}}{CALL_CLOSE}")
+    }
+
+    fn names(calls: &[ToolCall]) -> Vec<&str> {
+        calls.iter().map(|c| c.function.name.as_str()).collect()
+    }
+
+    #[test]
+    fn text_around_blocks_is_kept_as_prose() {
+        let reply = format!("Let me look.\n{}\nBack soon.", call("web_search", r#"{"query": "rust"}"#));
+
+        let (text, calls) = parse_tool_calls(&reply);
+
+        assert_eq!(text, "Let me look.\n\nBack soon.");
+        assert_eq!(names(&calls), ["web_search"]);
+        assert_eq!(calls[0].function.arguments, r#"{"query":"rust"}"#);
+    }
+
+    #[test]
+    fn several_blocks_in_one_reply() {
+        let reply = format!(
+            "{}\n{}\n<function=get_datetime>{{}}</function>",
+            call("memory_save", r#"{"fact": "Likes tea"}"#),
+            call("todo_add", r#""{\"item\": \"buy tea\"}""#),
+        );
+
+        let (text, calls) = parse_tool_calls(&reply);
+
+        assert_eq!(text, "");
+        assert_eq!(names(&calls), ["memory_save", "todo_add", "get_datetime"]);
+        // Pre-encoded arguments are passed through as the JSON string they contain
+        assert_eq!(calls[1].function.arguments, r#"{"item": "buy tea"}"#);
+        assert_ne!(calls[0].id, calls[1].id);
+    }
+
+    #[test]
+    fn malformed_blocks_are_dropped() {
+        let reply = format!(
+            "Checking.{CALL_OPEN}{{\"name\": \"web_search\", \"arguments\": {CALL_CLOSE}{}",
+            call("get_datetime", "{}"),
+        );
+
+        let (text, calls) = parse_tool_calls(&reply);
+
+        assert_eq!(text, "Checking.");
+        assert_eq!(names(&calls), ["get_datetime"]);
+        // An unclosed block still counts when its JSON is complete
+        let (_, calls) = parse_tool_calls(r#"<tool_call>{"name": "get_datetime", "arguments": {}}"#);
+        assert_eq!(names(&calls), ["get_datetime"]);
+    }
+
+    #[test]
+    fn filter_holds_back_a_tag_split_across_deltas() {
+        let filter = DeltaFilter::default();
+
+        assert_eq!(filter.push("Let me check.<to").as_deref(), Some("Let me check."));
+        assert_eq!(filter.push("ol_ca"), None);
+        assert_eq!(filter.push("ll>{\"name\": \"web_search\""), None);
+        assert_eq!(filter.push("}</tool_call>\nDone."), None);
+    }
+
+    #[test]
+    fn filter_releases_text_that_is_not_a_tag() {
+        let filter = DeltaFilter::default();
+
+        assert_eq!(filter.push("1 <").as_deref(), Some("1 "));
+        assert_eq!(filter.push(" 2, <b>ok</b>").as_deref(), Some("< 2, <b>ok</b>"));
+        assert_eq!(filter.push("<func"), None);
+        assert_eq!(filter.push("tion=get_datetime>{}"), None);
+    }
+}
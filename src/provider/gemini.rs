@@ -1,39 +1,130 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
 use reqwest::Client;
 use serde_json::json;
 
+use super::backend::{Capabilities, LlmProvider};
+use super::keys::{parse_secs, retry_after_from_headers};
+use super::stream::SseReader;
 use super::types::*;
 
 pub struct GeminiProvider {
     client: Client,
-    model: String,
 }
 
 impl GeminiProvider {
+    pub const DEFAULT_MODEL: &'static str = "gemma-4-31b-it";
+
     pub fn new() -> Self {
         Self {
             client: Client::new(),
-            model: "gemma-4-31b-it".into(),
         }
     }
+}
 
-    fn is_gemma(&self) -> bool {
-        self.model.starts_with("gemma")
-    }
+fn is_gemma(model: &str) -> bool {
+    model.starts_with("gemma")
 }
 
 impl GeminiProvider {
-    pub async fn chat(
+    async fn complete(
         &self,
         messages: &[Message],
         tools: &[ToolDef],
         api_key: &str,
+        model: &str,
     ) -> Result<LlmResponse, ProviderError> {
         let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent",
-            self.model
+            "https://generativelanguage.googleapis.com/v1beta/models/{model}:generateContent"
         );
+        let body = self.build_body(messages, tools, model);
+        let resp = self.send(&url, &body, api_key).await?;
+        parse_google_response(resp).await
+    }
+
+    /// Streaming variant of `chat` using `streamGenerateContent` over SSE.
+    /// Each chunk is a partial `GenerateContentResponse`; function calls arrive whole.
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        api_key: &str,
+        model: &str,
+        on_event: &(dyn Fn(StreamEvent) + Send + Sync),
+    ) -> Result<LlmResponse, ProviderError> {
+        let url = format!(
+            "https://generativelanguage.googleapis.com/v1beta/models/{model}:streamGenerateContent?alt=sse"
+        );
+        let body = self.build_body(messages, tools, model);
+        let resp = self.send(&url, &body, api_key).await?;
+
+        let mut reader = SseReader::new(resp);
+        let mut text = String::new();
+        let mut tool_calls: Vec<ToolCall> = Vec::new();
+        let mut usage = Usage::default();
+
+        while let Some(data) = reader.next_event().await? {
+            let chunk: serde_json::Value = match serde_json::from_str(&data) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            if let Some(err) = chunk.get("error") {
+                let msg = format!("Stream error: {}", err["message"].as_str().unwrap_or("unknown error"));
+                return Err(if err["code"].as_u64().is_some_and(|c| c >= 500) {
+                    ProviderError::Transient(msg)
+                } else {
+                    ProviderError::RequestError(msg)
+                });
+            }
+
+            let parts = chunk["candidates"][0]["content"]["parts"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+
+            for part in &parts {
+                if part["thought"].as_bool().unwrap_or(false) {
+                    continue;
+                }
+                if let Some(t) = part["text"].as_str() {
+                    if !t.is_empty() {
+                        text.push_str(t);
+                        on_event(StreamEvent::TextDelta(t.to_string()));
+                    }
+                } else if let Some(fc) = part.get("functionCall") {
+                    let name = fc["name"].as_str().unwrap_or("").to_string();
+                    let args = fc.get("args").cloned().unwrap_or(json!({}));
+                    let tc = ToolCall {
+                        id: format!("call_{}", tool_calls.len()),
+                        function: ToolCallFunction {
+                            name,
+                            arguments: serde_json::to_string(&args)
+                                .map_err(|e| ProviderError::ParseError(e.to_string()))?,
+                        },
+                    };
+                    on_event(StreamEvent::ToolCall(tc.clone()));
+                    tool_calls.push(tc);
+                }
+            }
+
+            // usageMetadata is cumulative — the last chunk carries the totals
+            if let Some(meta) = chunk.get("usageMetadata") {
+                usage.prompt_tokens = meta["promptTokenCount"].as_u64().unwrap_or(0) as u32;
+                usage.completion_tokens = meta["candidatesTokenCount"].as_u64().unwrap_or(0) as u32;
+            }
+        }
 
-        let is_gemma = self.is_gemma();
+        Ok(LlmResponse {
+            content: if text.is_empty() { None } else { Some(text) },
+            tool_calls,
+            usage,
+        })
+    }
+
+    fn build_body(&self, messages: &[Message], tools: &[ToolDef], model: &str) -> serde_json::Value {
+        let is_gemma = is_gemma(model);
         let (contents, system_instruction) = build_google_contents(messages, is_gemma);
 
         let mut body = json!({ "contents": contents });
@@ -70,31 +161,90 @@ impl GeminiProvider {
             });
         }
 
+        body
+    }
+
+    async fn send(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+        api_key: &str,
+    ) -> Result<reqwest::Response, ProviderError> {
         let resp = self
             .client
-            .post(&url)
+            .post(url)
             .header("x-goog-api-key", api_key)
-            .json(&body)
+            .json(body)
             .send()
             .await
-            .map_err(|e| ProviderError::RequestError(e.to_string()))?;
+            .map_err(ProviderError::from_reqwest)?;
 
         let status = resp.status();
         if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
-            return Err(ProviderError::RateLimited);
+            let from_headers = retry_after_from_headers(resp.headers());
+            let text = resp.text().await.unwrap_or_default();
+            return Err(ProviderError::RateLimited(from_headers.or_else(|| retry_delay_from_body(&text))));
         }
         if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
             return Err(ProviderError::AuthError(format!("HTTP {status}")));
         }
         if !status.is_success() {
             let text = resp.text().await.unwrap_or_default();
-            return Err(ProviderError::RequestError(format!("HTTP {status}: {text}")));
+            return Err(ProviderError::from_status(status, &text));
         }
 
-        parse_google_response(resp).await
+        Ok(resp)
     }
 }
 
+impl LlmProvider for GeminiProvider {
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    fn capabilities(&self, model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            // Gemma models have no native function calling
+            tools: !is_gemma(model),
+            vision: true,
+            json_mode: !is_gemma(model),
+            max_context: if is_gemma(model) { 128_000 } else { 1_000_000 },
+        }
+    }
+
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(self.complete(messages, tools, api_key, model))
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+        on_event: &'a (dyn Fn(StreamEvent) + Send + Sync),
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(self.complete_stream(messages, tools, api_key, model, on_event))
+    }
+}
+
+/// Gemini reports the wait in the error body: `error.details[].retryDelay` ("17s").
+fn retry_delay_from_body(text: &str) -> Option<Duration> {
+    let body: serde_json::Value = serde_json::from_str(text).ok()?;
+    body["error"]["details"]
+        .as_array()?
+        .iter()
+        .filter_map(|d| d["retryDelay"].as_str())
+        .find_map(|delay| parse_secs(delay.trim_end_matches('s')))
+}
+
 fn build_google_contents(
     messages: &[Message],
     is_gemma: bool,
@@ -258,6 +408,7 @@ async fn parse_google_response(resp: reqwest::Response) -> Result<LlmResponse, P
         completion_tokens: body["usageMetadata"]["candidatesTokenCount"]
             .as_u64()
             .unwrap_or(0) as u32,
+        ..Default::default()
     };
 
     Ok(LlmResponse {
@@ -267,98 +418,36 @@ async fn parse_google_response(resp: reqwest::Response) -> Result<LlmResponse, P
     })
 }
 
-// --- Shared OpenAI-compatible helpers (used by groq, mistral) ---
-
-pub fn build_oai_messages(messages: &[Message]) -> Vec<serde_json::Value> {
-    messages
-        .iter()
-        .map(|m| {
-            match &m.content {
-                MessageContent::Text(text) => {
-                    let role = match m.role {
-                        Role::System => "system",
-                        Role::User => "user",
-                        Role::Assistant => "assistant",
-                        Role::Tool => "tool",
-                    };
-                    json!({
-                        "role": role,
-                        "content": text,
-                    })
-                }
-                MessageContent::UserWithImage { text, images } => {
-                    let mut parts: Vec<serde_json::Value> = Vec::new();
-                    for img in images {
-                        parts.push(json!({
-                            "type": "image_url",
-                            "image_url": {
-                                "url": format!("data:{};base64,{}", img.media_type, img.base64_data)
-                            }
-                        }));
-                    }
-                    if !text.is_empty() {
-                        parts.push(json!({ "type": "text", "text": text }));
-                    }
-                    json!({ "role": "user", "content": parts })
-                }
-                MessageContent::ToolResult { tool_call_id, name, content } => json!({
-                    "role": "tool",
-                    "tool_call_id": tool_call_id,
-                    "name": name,
-                    "content": content,
-                }),
-                MessageContent::AssistantWithToolCalls { text, tool_calls } => {
-                    let mut msg = json!({
-                        "role": "assistant",
-                        "tool_calls": tool_calls.iter().map(|tc| json!({
-                            "id": tc.id,
-                            "type": "function",
-                            "function": {
-                                "name": tc.function.name,
-                                "arguments": tc.function.arguments,
-                            }
-                        })).collect::<Vec<_>>(),
-                    });
-                    if let Some(t) = text {
-                        msg["content"] = json!(t);
-                    }
-                    msg
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(delay: &str) -> String {
+        json!({
+            "error": {
+                "code": 429,
+                "details": [
+                    { "@type": "type.googleapis.com/google.rpc.QuotaFailure" },
+                    { "@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": delay },
+                ],
             }
         })
-        .collect()
-}
-
-pub async fn parse_oai_response(resp: reqwest::Response) -> Result<LlmResponse, ProviderError> {
-    let body: serde_json::Value = resp
-        .json()
-        .await
-        .map_err(|e| ProviderError::ParseError(e.to_string()))?;
-
-    let choice = body["choices"]
-        .get(0)
-        .ok_or_else(|| ProviderError::ParseError("No choices in response".into()))?;
-
-    let message = &choice["message"];
-
-    let content = message["content"].as_str().map(|s| s.to_string());
-
-    let tool_calls: Vec<ToolCall> = if let Some(tcs) = message["tool_calls"].as_array() {
-        tcs.iter()
-            .filter_map(|tc| serde_json::from_value(tc.clone()).ok())
-            .collect()
-    } else {
-        vec![]
-    };
+        .to_string()
+    }
 
-    let usage = Usage {
-        prompt_tokens: body["usage"]["prompt_tokens"].as_u64().unwrap_or(0) as u32,
-        completion_tokens: body["usage"]["completion_tokens"].as_u64().unwrap_or(0) as u32,
-    };
+    #[test]
+    fn retry_delay_in_seconds() {
+        assert_eq!(retry_delay_from_body(&body("17s")), Some(Duration::from_secs(17)));
+        assert_eq!(retry_delay_from_body(&body("17.5s")), Some(Duration::from_millis(17_500)));
+    }
 
-    Ok(LlmResponse {
-        content,
-        tool_calls,
-        usage,
-    })
+    #[test]
+    fn invalid_retry_delay_is_ignored() {
+        // None lets the key pool apply its default cooldown
+        for delay in ["soon", "NaNs", "-3s", ""] {
+            assert_eq!(retry_delay_from_body(&body(delay)), None, "{delay:?}");
+        }
+        assert_eq!(retry_delay_from_body("Too Many Requests"), None);
+        assert_eq!(retry_delay_from_body(r#"{"error": {"code": 429}}"#), None);
+    }
 }
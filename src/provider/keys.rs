@@ -0,0 +1,322 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+
+/// Cooldown applied on a 429 without a usable reset header (doubles per consecutive failure).
+const BASE_COOLDOWN_SECS: u64 = 30;
+const MAX_COOLDOWN_SECS: u64 = 3600;
+/// Non-rate-limit failures in a row before a key is benched.
+const FAILURES_BEFORE_COOLDOWN: u32 = 3;
+
+#[derive(Debug, Clone, Default)]
+struct KeyState {
+    /// Unix millis until which the key must not be used.
+    cooldown_until_ms: i64,
+    consecutive_failures: u32,
+    /// Set on 401/403; never retried for the lifetime of the process.
+    disabled: bool,
+}
+
+impl KeyState {
+    fn is_available(&self, now_ms: i64) -> bool {
+        !self.disabled && self.cooldown_until_ms <= now_ms
+    }
+
+    fn start_cooldown(&mut self, duration: Duration) {
+        let until = now_ms() + duration.as_millis().min(i64::MAX as u128) as i64;
+        self.cooldown_until_ms = self.cooldown_until_ms.max(until);
+    }
+
+    fn backoff(&self) -> Duration {
+        let exp = self.consecutive_failures.saturating_sub(1).min(16);
+        Duration::from_secs((BASE_COOLDOWN_SECS << exp).min(MAX_COOLDOWN_SECS))
+    }
+}
+
+/// Persistable cooldown state of one key. Keys are identified by a hash, never stored raw.
+#[derive(Debug, Clone)]
+pub struct KeySnapshot {
+    pub provider: String,
+    pub key_id: String,
+    pub cooldown_until_ms: i64,
+    pub failures: u32,
+    pub disabled: bool,
+}
+
+/// Round-robin key pool that skips keys which are cooling down or disabled.
+pub struct KeyPool {
+    keys: Vec<String>,
+    states: Mutex<Vec<KeyState>>,
+    index: AtomicUsize,
+    dirty: AtomicBool,
+}
+
+impl KeyPool {
+    pub fn new(keys: Vec<String>) -> Self {
+        let states = vec![KeyState::default(); keys.len()];
+        Self {
+            keys,
+            states: Mutex::new(states),
+            index: AtomicUsize::new(0),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Next usable key as (slot, key), or `None` if every key is cooling down or disabled.
+    pub fn next_key(&self) -> Option<(usize, &str)> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        let states = self.states.lock().unwrap();
+        let now = now_ms();
+        let start = self.index.fetch_add(1, Ordering::Relaxed);
+        (0..self.keys.len())
+            .map(|offset| (start + offset) % self.keys.len())
+            .find(|&idx| states[idx].is_available(now))
+            .map(|idx| (idx, self.keys[idx].as_str()))
+    }
+
+    pub fn report_success(&self, slot: usize) {
+        let mut states = self.states.lock().unwrap();
+        if states[slot].consecutive_failures > 0 {
+            states[slot].consecutive_failures = 0;
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Bench the key until the provider's reset time, or for an exponential backoff.
+    pub fn report_rate_limited(&self, slot: usize, retry_after: Option<Duration>) -> Duration {
+        let mut states = self.states.lock().unwrap();
+        let state = &mut states[slot];
+        state.consecutive_failures += 1;
+        let cooldown = retry_after
+            .map(|d| d.min(Duration::from_secs(MAX_COOLDOWN_SECS)))
+            .unwrap_or_else(|| state.backoff());
+        state.start_cooldown(cooldown);
+        self.dirty.store(true, Ordering::Relaxed);
+        cooldown
+    }
+
+    /// Count a generic failure; repeated failures bench the key with backoff.
+    pub fn report_failure(&self, slot: usize) {
+        let mut states = self.states.lock().unwrap();
+        let state = &mut states[slot];
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= FAILURES_BEFORE_COOLDOWN {
+            let cooldown = state.backoff();
+            state.start_cooldown(cooldown);
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Permanently disable a key (invalid or revoked credentials).
+    pub fn disable(&self, slot: usize) {
+        self.states.lock().unwrap()[slot].disabled = true;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Number of keys currently usable.
+    pub fn available(&self) -> usize {
+        let now = now_ms();
+        self.states
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.is_available(now))
+            .count()
+    }
+
+    /// Snapshot key states for persistence and clear the dirty flag.
+    pub fn snapshot(&self, provider: &str) -> Vec<KeySnapshot> {
+        self.dirty.store(false, Ordering::Relaxed);
+        let states = self.states.lock().unwrap();
+        self.keys
+            .iter()
+            .zip(states.iter())
+            .map(|(key, state)| KeySnapshot {
+                provider: provider.to_string(),
+                key_id: key_id(key),
+                cooldown_until_ms: state.cooldown_until_ms,
+                failures: state.consecutive_failures,
+                disabled: state.disabled,
+            })
+            .collect()
+    }
+
+    /// Restore cooldowns and disabled keys saved by a previous run. Unknown keys are ignored.
+    pub fn restore(&self, snapshots: &[KeySnapshot]) {
+        let mut states = self.states.lock().unwrap();
+        for (key, state) in self.keys.iter().zip(states.iter_mut()) {
+            let id = key_id(key);
+            if let Some(snap) = snapshots.iter().find(|s| s.key_id == id) {
+                state.cooldown_until_ms = snap.cooldown_until_ms;
+                state.consecutive_failures = snap.failures;
+                state.disabled = snap.disabled;
+            }
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// Stable, non-reversible identifier for a key (FNV-1a 64).
+fn key_id(key: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in key.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+/// Extract how long to wait before retrying from rate-limit response headers.
+/// Understands `Retry-After` (seconds or HTTP date), `x-ratelimit-reset*`
+/// (seconds, Go-style durations like "2m59.5s", or unix timestamps) and
+/// Anthropic's RFC 3339 `anthropic-ratelimit-*-reset`.
+pub fn retry_after_from_headers(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(value) = header_str(headers, "retry-after") {
+        if let Some(delay) = parse_secs(value) {
+            return Some(delay);
+        }
+        if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+            return until(date.with_timezone(&chrono::Utc));
+        }
+    }
+
+    // No Retry-After: be conservative and wait for the latest advertised reset
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            name.starts_with("x-ratelimit-reset")
+                || (name.starts_with("anthropic-ratelimit-") && name.ends_with("-reset"))
+        })
+        .filter_map(|(_, value)| value.to_str().ok().and_then(parse_reset))
+        .max()
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(|s| s.trim())
+}
+
+fn parse_reset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(value) {
+        return until(date.with_timezone(&chrono::Utc));
+    }
+    if let Ok(num) = value.parse::<f64>() {
+        // Large values are unix timestamps, small ones are relative seconds
+        if num > 1_000_000_000.0 {
+            return Some(secs_to_duration(num - chrono::Utc::now().timestamp() as f64));
+        }
+        return Some(secs_to_duration(num));
+    }
+    parse_go_duration(value)
+}
+
+/// Parse Go-style durations such as "1h2m3.5s", "6m0s" or "250ms".
+fn parse_go_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let num_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let num: f64 = rest[..num_end].parse().ok()?;
+        rest = &rest[num_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let factor = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += num * factor;
+        rest = &rest[unit_end..];
+    }
+    Some(secs_to_duration(total))
+}
+
+/// A plain number of seconds such as "17" or "17.5"; NaN, infinite and
+/// negative values are rejected so the caller's default cooldown applies.
+pub(super) fn parse_secs(value: &str) -> Option<Duration> {
+    let secs = value.trim().parse::<f64>().ok()?;
+    (secs.is_finite() && secs >= 0.0).then(|| secs_to_duration(secs))
+}
+
+/// Convert seconds from an untrusted header into a bounded `Duration`.
+fn secs_to_duration(secs: f64) -> Duration {
+    if secs.is_finite() {
+        Duration::from_secs_f64(secs.clamp(0.0, MAX_COOLDOWN_SECS as f64))
+    } else {
+        Duration::ZERO
+    }
+}
+
+fn until(at: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    (at - chrono::Utc::now()).to_std().ok().or(Some(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(retry_after_from_headers(&headers(&[("retry-after", "17")])), Some(Duration::from_secs(17)));
+        assert_eq!(
+            retry_after_from_headers(&headers(&[("retry-after", "1.5")])),
+            Some(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let at = chrono::Utc::now() + chrono::Duration::seconds(120);
+        let date = at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+
+        let delay = retry_after_from_headers(&headers(&[("retry-after", &date)])).unwrap();
+
+        assert!(delay > Duration::from_secs(100) && delay <= Duration::from_secs(120), "{delay:?}");
+        // A date in the past means the key can be retried right away
+        let past = headers(&[("retry-after", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(retry_after_from_headers(&past), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn invalid_retry_after_falls_back_to_the_default_cooldown() {
+        for value in ["soon", "NaN", "inf", "-5", ""] {
+            let retry_after = retry_after_from_headers(&headers(&[("retry-after", value)]));
+            assert_eq!(retry_after, None, "{value:?}");
+            let pool = KeyPool::new(vec!["key".into()]);
+            assert_eq!(pool.report_rate_limited(0, retry_after), Duration::from_secs(BASE_COOLDOWN_SECS));
+        }
+    }
+}
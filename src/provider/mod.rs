@@ -1,9 +1,24 @@
+mod capabilities;
+mod embeddings;
+mod emulation;
+mod keys;
 mod pool;
+mod pricing;
+mod retry;
+mod stream;
 mod types;
 mod gemini;
-mod groq;
-mod mistral;
+mod openai_compat;
+pub mod backend;
+pub mod cassette;
 pub mod claude;
 
-pub use pool::ProviderPool;
+pub use backend::{Capabilities, LlmProvider};
+pub use capabilities::CapabilityOverrides;
+pub use embeddings::{Embedder, cosine_similarity, embedder_from_config};
+pub use keys::KeySnapshot;
+pub use openai_compat::OpenAiCompatConfig;
+pub use pool::{ChatOptions, ProviderPool};
+pub use pricing::PriceTable;
+pub use retry::RetryPolicy;
 pub use types::*;
@@ -0,0 +1,394 @@
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde_json::json;
+use tracing::debug;
+
+use super::backend::{Capabilities, LlmProvider};
+use super::keys::retry_after_from_headers;
+use super::stream::SseReader;
+use super::types::*;
+
+/// Settings for one OpenAI-compatible endpoint (Groq, Mistral, Ollama, llama.cpp server,
+/// vLLM, OpenRouter, DeepSeek, ...).
+#[derive(Debug, Clone)]
+pub struct OpenAiCompatConfig {
+    /// Provider name used for routing, overrides and logs (e.g. "ollama").
+    pub name: String,
+    /// API root without the trailing `/chat/completions` (e.g. "http://localhost:11434/v1").
+    pub base_url: String,
+    /// Model catalog; the first entry is the default.
+    pub models: Vec<String>,
+    /// API keys for round-robin. Empty for local servers that need no auth.
+    pub keys: Vec<String>,
+    /// Extra headers sent with every request (e.g. OpenRouter's `HTTP-Referer`).
+    pub headers: Vec<(String, String)>,
+}
+
+impl OpenAiCompatConfig {
+    pub fn groq(keys: Vec<String>, models: Vec<String>) -> Self {
+        Self {
+            name: "groq".into(),
+            base_url: "https://api.groq.com/openai/v1".into(),
+            models: models_or_default(&models, "openai/gpt-oss-120b"),
+            keys,
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn mistral(keys: Vec<String>, models: Vec<String>) -> Self {
+        Self {
+            name: "mistral".into(),
+            base_url: "https://api.mistral.ai/v1".into(),
+            models: models_or_default(&models, "mistral-small-latest"),
+            keys,
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// Use the configured model list, or a single default model when none is configured.
+pub(super) fn models_or_default(models: &[String], default: &str) -> Vec<String> {
+    if models.is_empty() {
+        vec![default.to_string()]
+    } else {
+        models.to_vec()
+    }
+}
+
+/// Generic provider for any server speaking the OpenAI chat completions API.
+pub struct OpenAiCompatProvider {
+    client: Client,
+    name: String,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl OpenAiCompatProvider {
+    pub fn new(config: &OpenAiCompatConfig) -> Self {
+        Self {
+            client: Client::new(),
+            name: config.name.clone(),
+            url: format!("{}/chat/completions", config.base_url.trim_end_matches('/')),
+            headers: config.headers.clone(),
+        }
+    }
+
+    async fn complete(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        api_key: &str,
+        model: &str,
+    ) -> Result<LlmResponse, ProviderError> {
+        let body = self.build_body(messages, tools, model)?;
+        let resp = self.send(&body, api_key).await?;
+        parse_oai_response(resp).await
+    }
+
+    async fn complete_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        api_key: &str,
+        model: &str,
+        on_event: &(dyn Fn(StreamEvent) + Send + Sync),
+    ) -> Result<LlmResponse, ProviderError> {
+        let mut body = self.build_body(messages, tools, model)?;
+        body["stream"] = json!(true);
+        // Ask for token usage on the final chunk (OpenAI convention; ignored where unsupported)
+        body["stream_options"] = json!({ "include_usage": true });
+        let resp = self.send(&body, api_key).await?;
+        stream_oai_response(resp, on_event).await
+    }
+
+    fn build_body(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        model: &str,
+    ) -> Result<serde_json::Value, ProviderError> {
+        let mut body = json!({
+            "model": model,
+            "messages": build_oai_messages(messages),
+        });
+
+        if !tools.is_empty() {
+            body["tools"] = serde_json::to_value(tools)
+                .map_err(|e| ProviderError::ParseError(e.to_string()))?;
+            body["tool_choice"] = json!("auto");
+        }
+
+        Ok(body)
+    }
+
+    async fn send(
+        &self,
+        body: &serde_json::Value,
+        api_key: &str,
+    ) -> Result<reqwest::Response, ProviderError> {
+        debug!(
+            "{} API request: model={}",
+            self.name,
+            body["model"].as_str().unwrap_or("")
+        );
+
+        let mut req = self.client.post(&self.url).json(body);
+        // Local servers (llama.cpp, Ollama) usually run without auth
+        if !api_key.is_empty() {
+            req = req.bearer_auth(api_key);
+        }
+        for (name, value) in &self.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+
+        let resp = req
+            .send()
+            .await
+            .map_err(ProviderError::from_reqwest)?;
+
+        let status = resp.status();
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(ProviderError::RateLimited(retry_after_from_headers(resp.headers())));
+        }
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(ProviderError::AuthError(format!("HTTP {status}")));
+        }
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            return Err(ProviderError::from_status(status, &text));
+        }
+
+        Ok(resp)
+    }
+}
+
+impl LlmProvider for OpenAiCompatProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self, model: &str) -> Capabilities {
+        // Conservative guesses from the model name; MODEL_CAPABILITIES overrides them
+        let model = model.to_lowercase();
+        let vision = [
+            "vision", "-vl", "llava", "pixtral", "llama-4", "gpt-4o", "gpt-4.1", "gemma-3",
+            "mistral-small", "mistral-medium",
+        ]
+        .iter()
+        .any(|hint| model.contains(hint));
+        let long_context = ["gpt-oss", "gpt-4", "llama-3", "llama-4", "mistral", "qwen", "deepseek"]
+            .iter()
+            .any(|hint| model.contains(hint));
+        Capabilities {
+            streaming: true,
+            tools: true,
+            vision,
+            json_mode: true,
+            max_context: if long_context { 128_000 } else { 32_768 },
+        }
+    }
+
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(self.complete(messages, tools, api_key, model))
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        api_key: &'a str,
+        model: &'a str,
+        on_event: &'a (dyn Fn(StreamEvent) + Send + Sync),
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        Box::pin(self.complete_stream(messages, tools, api_key, model, on_event))
+    }
+}
+
+fn build_oai_messages(messages: &[Message]) -> Vec<serde_json::Value> {
+    messages
+        .iter()
+        .map(|m| {
+            match &m.content {
+                MessageContent::Text(text) => {
+                    let role = match m.role {
+                        Role::System => "system",
+                        Role::User => "user",
+                        Role::Assistant => "assistant",
+                        Role::Tool => "tool",
+                    };
+                    json!({
+                        "role": role,
+                        "content": text,
+                    })
+                }
+                MessageContent::UserWithImage { text, images } => {
+                    let mut parts: Vec<serde_json::Value> = Vec::new();
+                    for img in images {
+                        parts.push(json!({
+                            "type": "image_url",
+                            "image_url": {
+                                "url": format!("data:{};base64,{}", img.media_type, img.base64_data)
+                            }
+                        }));
+                    }
+                    if !text.is_empty() {
+                        parts.push(json!({ "type": "text", "text": text }));
+                    }
+                    json!({ "role": "user", "content": parts })
+                }
+                MessageContent::ToolResult { tool_call_id, name, content } => json!({
+                    "role": "tool",
+                    "tool_call_id": tool_call_id,
+                    "name": name,
+                    "content": content,
+                }),
+                MessageContent::AssistantWithToolCalls { text, tool_calls } => {
+                    let mut msg = json!({
+                        "role": "assistant",
+                        "tool_calls": tool_calls.iter().map(|tc| json!({
+                            "id": tc.id,
+                            "type": "function",
+                            "function": {
+                                "name": tc.function.name,
+                                "arguments": tc.function.arguments,
+                            }
+                        })).collect::<Vec<_>>(),
+                    });
+                    if let Some(t) = text {
+                        msg["content"] = json!(t);
+                    }
+                    msg
+                }
+            }
+        })
+        .collect()
+}
+
+async fn parse_oai_response(resp: reqwest::Response) -> Result<LlmResponse, ProviderError> {
+    let body: serde_json::Value = resp
+        .json()
+        .await
+        .map_err(|e| ProviderError::ParseError(e.to_string()))?;
+
+    let choice = body["choices"]
+        .get(0)
+        .ok_or_else(|| ProviderError::ParseError("No choices in response".into()))?;
+
+    let message = &choice["message"];
+
+    let content = message["content"].as_str().map(|s| s.to_string());
+
+    let tool_calls: Vec<ToolCall> = if let Some(tcs) = message["tool_calls"].as_array() {
+        tcs.iter()
+            .filter_map(|tc| serde_json::from_value(tc.clone()).ok())
+            .collect()
+    } else {
+        vec![]
+    };
+
+    let usage = Usage {
+        prompt_tokens: body["usage"]["prompt_tokens"].as_u64().unwrap_or(0) as u32,
+        completion_tokens: body["usage"]["completion_tokens"].as_u64().unwrap_or(0) as u32,
+        ..Default::default()
+    };
+
+    Ok(LlmResponse {
+        content,
+        tool_calls,
+        usage,
+    })
+}
+
+/// Consume an OpenAI-style SSE stream (`choices[].delta`), emitting text deltas as they
+/// arrive. Tool call fragments are keyed by `index` and emitted once the stream ends.
+async fn stream_oai_response(
+    resp: reqwest::Response,
+    on_event: &(dyn Fn(StreamEvent) + Send + Sync),
+) -> Result<LlmResponse, ProviderError> {
+    let mut reader = SseReader::new(resp);
+    let mut text = String::new();
+    // (id, name, arguments) per tool call index
+    let mut partial_calls: Vec<(String, String, String)> = Vec::new();
+    let mut usage = Usage::default();
+
+    while let Some(data) = reader.next_event().await? {
+        if data.trim() == "[DONE]" {
+            break;
+        }
+        let chunk: serde_json::Value = match serde_json::from_str(&data) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        if let Some(err) = chunk.get("error") {
+            let msg = err["message"].as_str().unwrap_or("unknown error");
+            return Err(ProviderError::RequestError(format!("Stream error: {msg}")));
+        }
+
+        let delta = &chunk["choices"][0]["delta"];
+
+        if let Some(t) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            text.push_str(t);
+            on_event(StreamEvent::TextDelta(t.to_string()));
+        }
+
+        if let Some(tcs) = delta["tool_calls"].as_array() {
+            for tc in tcs {
+                let idx = tc["index"].as_u64().unwrap_or(0) as usize;
+                if partial_calls.len() <= idx {
+                    partial_calls.resize(idx + 1, Default::default());
+                }
+                let entry = &mut partial_calls[idx];
+                if let Some(id) = tc["id"].as_str() {
+                    entry.0 = id.to_string();
+                }
+                if let Some(name) = tc["function"]["name"].as_str() {
+                    entry.1.push_str(name);
+                }
+                if let Some(args) = tc["function"]["arguments"].as_str() {
+                    entry.2.push_str(args);
+                }
+            }
+        }
+
+        // Usage arrives on the final chunk (Groq nests it under `x_groq`)
+        let chunk_usage = if chunk["usage"].is_object() {
+            &chunk["usage"]
+        } else {
+            &chunk["x_groq"]["usage"]
+        };
+        if let Some(p) = chunk_usage["prompt_tokens"].as_u64() {
+            usage.prompt_tokens = p as u32;
+            usage.completion_tokens = chunk_usage["completion_tokens"].as_u64().unwrap_or(0) as u32;
+        }
+    }
+
+    let tool_calls: Vec<ToolCall> = partial_calls
+        .into_iter()
+        .filter(|(_, name, _)| !name.is_empty())
+        .enumerate()
+        .map(|(i, (id, name, arguments))| ToolCall {
+            id: if id.is_empty() { format!("call_{i}") } else { id },
+            function: ToolCallFunction {
+                name,
+                arguments: if arguments.is_empty() { "{}".into() } else { arguments },
+            },
+        })
+        .collect();
+
+    for tc in &tool_calls {
+        on_event(StreamEvent::ToolCall(tc.clone()));
+    }
+
+    Ok(LlmResponse {
+        content: if text.is_empty() { None } else { Some(text) },
+        tool_calls,
+        usage,
+    })
+}
@@ -1,178 +1,358 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
 use tracing::{info, warn};
 
+use crate::config::Config;
+
+use super::backend::{Capabilities, LlmProvider};
+use super::capabilities::{CapabilityOverrides, Requirements};
+use super::cassette::{Recorder, RecordingProvider, ReplayProvider, load_cassette};
 use super::claude::ClaudeProvider;
+use super::emulation;
 use super::gemini::GeminiProvider;
-use super::groq::GroqProvider;
-use super::mistral::MistralProvider;
+use super::keys::{KeyPool, KeySnapshot};
+use super::openai_compat::{OpenAiCompatConfig, OpenAiCompatProvider, models_or_default};
+use super::pricing::PriceTable;
+use super::retry::RetryPolicy;
 use super::types::*;
 
-struct KeyPool {
-    keys: Vec<String>,
-    index: AtomicUsize,
+struct ProviderEntry {
+    provider: Box<dyn LlmProvider>,
+    keys: KeyPool,
+    /// Model catalog; the first entry is the default.
+    models: Vec<String>,
 }
 
-impl KeyPool {
-    fn new(keys: Vec<String>) -> Self {
-        Self {
-            keys,
-            index: AtomicUsize::new(0),
+impl ProviderEntry {
+    /// Call the provider, streaming when `on_event` is set. With `emulate_tools`
+    /// the tools are described in the prompt instead of sent natively.
+    async fn chat(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        api_key: &str,
+        model: &str,
+        on_event: Option<&(dyn Fn(StreamEvent) + Send + Sync)>,
+        emulate_tools: bool,
+    ) -> Result<LlmResponse, ProviderError> {
+        if emulate_tools {
+            return emulation::chat(self.provider.as_ref(), messages, tools, api_key, model, on_event).await;
         }
-    }
-
-    fn next_key(&self) -> Option<&str> {
-        if self.keys.is_empty() {
-            return None;
+        match on_event {
+            Some(f) => self.provider.chat_stream(messages, tools, api_key, model, f).await,
+            None => self.provider.chat(messages, tools, api_key, model).await,
         }
-        let idx = self.index.fetch_add(1, Ordering::Relaxed) % self.keys.len();
-        Some(&self.keys[idx])
     }
 
-    fn is_empty(&self) -> bool {
-        self.keys.is_empty()
+    fn default_model(&self) -> &str {
+        &self.models[0]
     }
 
-    fn len(&self) -> usize {
-        self.keys.len()
+    /// Find a model in the catalog: exact name first, then substring ("haiku").
+    fn find_model(&self, hint: &str) -> Option<&str> {
+        let hint = hint.to_lowercase();
+        self.models
+            .iter()
+            .find(|m| m.to_lowercase() == hint)
+            .or_else(|| self.models.iter().find(|m| m.to_lowercase().contains(&hint)))
+            .map(|m| m.as_str())
     }
 }
 
-/// Enum-based provider dispatch (no dyn trait needed)
-enum Provider {
-    Claude(ClaudeProvider),
-    Gemini(GeminiProvider),
-    Groq(GroqProvider),
-    Mistral(MistralProvider),
+/// Per-request options for `ProviderPool::chat`.
+#[derive(Clone, Copy, Default)]
+pub struct ChatOptions<'a> {
+    /// Stream the response, forwarding deltas here.
+    pub on_event: Option<&'a (dyn Fn(StreamEvent) + Send + Sync)>,
+    /// Only route to models that are free according to the price table.
+    pub free_only: bool,
+    /// Only route to models with a structured JSON output mode.
+    pub json_mode: bool,
 }
 
-impl Provider {
-    fn name(&self) -> &str {
-        match self {
-            Provider::Claude(_) => "claude",
-            Provider::Gemini(_) => "gemini",
-            Provider::Groq(_) => "groq",
-            Provider::Mistral(_) => "mistral",
-        }
-    }
+/// State shared by every attempt made for one chat request.
+struct ChatRequest<'a> {
+    messages: &'a [Message],
+    tools: &'a [ToolDef],
+    opts: ChatOptions<'a>,
+    needs: Requirements,
+    /// Retrying stops once this is reached; fallback to other providers still happens.
+    deadline: Instant,
+    /// Whether any attempt has been made (and may have streamed output).
+    started: AtomicBool,
+}
 
-    async fn chat(
-        &self,
-        messages: &[Message],
-        tools: &[ToolDef],
-        api_key: &str,
-    ) -> Result<LlmResponse, ProviderError> {
-        match self {
-            Provider::Claude(p) => p.chat(messages, tools, api_key).await,
-            Provider::Gemini(p) => p.chat(messages, tools, api_key).await,
-            Provider::Groq(p) => p.chat(messages, tools, api_key).await,
-            Provider::Mistral(p) => p.chat(messages, tools, api_key).await,
+impl<'a> ChatRequest<'a> {
+    fn new(
+        messages: &'a [Message],
+        tools: &'a [ToolDef],
+        opts: ChatOptions<'a>,
+        retry: &RetryPolicy,
+    ) -> Self {
+        Self {
+            messages,
+            tools,
+            opts,
+            needs: Requirements::for_request(messages, tools, opts.json_mode),
+            deadline: Instant::now() + retry.deadline,
+            started: AtomicBool::new(false),
         }
     }
 }
 
-struct ProviderEntry {
-    provider: Provider,
-    keys: KeyPool,
+/// Prompt budget of the model a request would go to first, for trimming the
+/// conversation before the call.
+pub struct ContextBudget<'a> {
+    provider: &'a dyn LlmProvider,
+    model: &'a str,
+    /// Prompt tokens that fit once room for the reply is set aside.
+    pub max_tokens: u32,
+}
+
+impl ContextBudget<'_> {
+    /// Token estimate for this model, using its provider's estimator.
+    pub fn estimate(&self, messages: &[Message], tools: &[ToolDef]) -> u32 {
+        self.provider.estimate_tokens(self.model, messages, tools)
+    }
 }
 
 /// Round-robin provider pool with automatic fallback
 pub struct ProviderPool {
     providers: Vec<ProviderEntry>,
-    default_idx: usize,
+    /// Name of the provider tried first; falls back to the first registered one.
+    default_provider: String,
+    retry: RetryPolicy,
+    prices: PriceTable,
+    capability_overrides: CapabilityOverrides,
+    /// Prompt-based tool calling for models without native support.
+    tool_emulation: bool,
+    /// When set, every registered provider is wrapped to record its calls.
+    recorder: Option<Arc<Recorder>>,
 }
 
 impl ProviderPool {
-    pub fn new(
-        claude_keys: Vec<String>,
-        gemini_keys: Vec<String>,
-        groq_keys: Vec<String>,
-        mistral_keys: Vec<String>,
-        default_provider: &str,
-    ) -> Self {
-        let mut providers = Vec::new();
-
-        if !claude_keys.is_empty() {
-            providers.push(ProviderEntry {
-                provider: Provider::Claude(ClaudeProvider::new()),
-                keys: KeyPool::new(claude_keys),
-            });
-        }
-        if !gemini_keys.is_empty() {
-            providers.push(ProviderEntry {
-                provider: Provider::Gemini(GeminiProvider::new()),
-                keys: KeyPool::new(gemini_keys),
-            });
-        }
-        if !groq_keys.is_empty() {
-            providers.push(ProviderEntry {
-                provider: Provider::Groq(GroqProvider::new()),
-                keys: KeyPool::new(groq_keys),
-            });
-        }
-        if !mistral_keys.is_empty() {
-            providers.push(ProviderEntry {
-                provider: Provider::Mistral(MistralProvider::new()),
-                keys: KeyPool::new(mistral_keys),
-            });
-        }
-
-        let default_idx = providers
-            .iter()
-            .position(|p| p.provider.name() == default_provider)
-            .unwrap_or(0);
+    /// Build the pool from the providers configured in `config`.
+    /// Further providers, such as in-house or mock backends, can be added with `register`.
+    pub fn new(config: &Config) -> Self {
+        let mut pool = Self {
+            providers: Vec::new(),
+            default_provider: config.default_provider.clone(),
+            retry: config.retry.clone(),
+            prices: config.prices.clone(),
+            capability_overrides: config.capabilities.clone(),
+            tool_emulation: config.tool_emulation,
+            recorder: config.provider_record.as_deref().map(|path| {
+                info!("Recording provider calls to {path}");
+                Arc::new(Recorder::new(path))
+            }),
+        };
+
+        // Offline mode: serve recorded responses instead of calling any live API
+        if let Some(path) = &config.provider_replay {
+            match load_cassette(path) {
+                Ok(interactions) => {
+                    for (provider, models) in ReplayProvider::from_cassette(interactions) {
+                        pool.register_or_warn(Box::new(provider), vec![String::new()], models);
+                    }
+                    info!("Replaying provider calls from {path}");
+                }
+                Err(e) => warn!("Failed to load cassette {e}"),
+            }
+            return pool;
+        }
+
+        if !config.claude_keys.is_empty() {
+            pool.register_or_warn(
+                Box::new(ClaudeProvider::new()),
+                config.claude_keys.clone(),
+                models_or_default(&config.claude_models, ClaudeProvider::DEFAULT_MODEL),
+            );
+        }
+        if !config.gemini_keys.is_empty() {
+            pool.register_or_warn(
+                Box::new(GeminiProvider::new()),
+                config.gemini_keys.clone(),
+                models_or_default(&config.gemini_models, GeminiProvider::DEFAULT_MODEL),
+            );
+        }
+        if !config.groq_keys.is_empty() {
+            let cfg = OpenAiCompatConfig::groq(config.groq_keys.clone(), config.groq_models.clone());
+            pool.register_or_warn(Box::new(OpenAiCompatProvider::new(&cfg)), cfg.keys, cfg.models);
+        }
+        if !config.mistral_keys.is_empty() {
+            let cfg = OpenAiCompatConfig::mistral(
+                config.mistral_keys.clone(),
+                config.mistral_models.clone(),
+            );
+            pool.register_or_warn(Box::new(OpenAiCompatProvider::new(&cfg)), cfg.keys, cfg.models);
+        }
+        for cfg in &config.openai_compat {
+            // Keyless endpoints (local servers) still need one slot in the key pool
+            let keys = if cfg.keys.is_empty() {
+                vec![String::new()]
+            } else {
+                cfg.keys.clone()
+            };
+            pool.register_or_warn(Box::new(OpenAiCompatProvider::new(cfg)), keys, cfg.models.clone());
+        }
 
         info!(
             "Provider pool: {} providers, default={}",
-            providers.len(),
-            providers.get(default_idx).map(|p| p.provider.name()).unwrap_or("none")
+            pool.providers.len(),
+            pool.providers
+                .get(pool.default_idx())
+                .map(|p| p.provider.name())
+                .unwrap_or("none")
         );
 
+        pool
+    }
+
+    /// A pool with no providers and default settings, for setups that only use
+    /// their own backends (e.g. mocks in tests). Add providers with `register`.
+    pub fn empty(default_provider: &str) -> Self {
         Self {
-            providers,
-            default_idx,
+            providers: Vec::new(),
+            default_provider: default_provider.to_string(),
+            retry: RetryPolicy::default(),
+            prices: PriceTable::default(),
+            capability_overrides: CapabilityOverrides::default(),
+            tool_emulation: true,
+            recorder: None,
+        }
+    }
+
+    fn register_or_warn(
+        &mut self,
+        provider: Box<dyn LlmProvider>,
+        keys: Vec<String>,
+        models: Vec<String>,
+    ) {
+        if let Err(e) = self.register(provider, keys, models) {
+            warn!("{e}, skipping");
+        }
+    }
+
+    /// Add a provider with its API keys and model catalog (first model is the default).
+    /// Keyless backends should pass a single empty key. Provider names must be unique.
+    pub fn register(
+        &mut self,
+        provider: Box<dyn LlmProvider>,
+        keys: Vec<String>,
+        models: Vec<String>,
+    ) -> Result<(), String> {
+        let name = provider.name();
+        if self.providers.iter().any(|p| p.provider.name() == name) {
+            return Err(format!("Duplicate provider name '{name}'"));
+        }
+        if models.is_empty() {
+            return Err(format!("Provider '{name}' has no models"));
         }
+        if keys.is_empty() {
+            return Err(format!("Provider '{name}' has no keys"));
+        }
+        let provider: Box<dyn LlmProvider> = match &self.recorder {
+            Some(recorder) => Box::new(RecordingProvider::new(provider, recorder.clone())),
+            None => provider,
+        };
+        self.providers.push(ProviderEntry {
+            provider,
+            keys: KeyPool::new(keys),
+            models,
+        });
+        Ok(())
     }
 
-    /// Send a chat request, trying providers in order with fallback
+    /// Send a chat request, trying providers in order with fallback.
+    /// When `opts.on_event` is set the response is streamed and deltas are forwarded to it.
+    /// Returns the response with the provider and model that produced it.
     pub async fn chat(
         &self,
         messages: &[Message],
         tools: &[ToolDef],
-    ) -> Result<(LlmResponse, String), ProviderError> {
+        opts: ChatOptions<'_>,
+    ) -> Result<(LlmResponse, String, String), ProviderError> {
+        let req = ChatRequest::new(messages, tools, opts, &self.retry);
+        self.chat_pool(&req).await
+    }
+
+    async fn chat_pool(
+        &self,
+        req: &ChatRequest<'_>,
+    ) -> Result<(LlmResponse, String, String), ProviderError> {
         if self.providers.is_empty() {
             return Err(ProviderError::NoKeys);
         }
 
-        let order = self.provider_order();
+        let needs = self.routing_needs(req.needs);
+        let mut routes = self.plan_routes(needs, req.opts.free_only);
+        if needs.tools {
+            // Models without tool calling come last: a plain-text answer still beats none
+            for route in self.plan_routes(needs.without_tools(), req.opts.free_only) {
+                if !routes.iter().any(|(idx, _)| *idx == route.0) {
+                    routes.push(route);
+                }
+            }
+        }
+        if routes.is_empty() {
+            return Err(ProviderError::Unsupported(self.unsupported_reason(req)));
+        }
 
-        for idx in order {
+        for (idx, model) in routes {
             let entry = &self.providers[idx];
             let provider_name = entry.provider.name().to_string();
             let num_keys = entry.keys.len();
 
-            // Try all keys for this provider before moving to next provider
+            if req.needs.tools && !self.capabilities_of(entry, model).tools {
+                if self.tool_emulation {
+                    info!("{provider_name}/{model} has no tool calling, emulating it via the prompt");
+                } else {
+                    warn!("{provider_name}/{model} has no tool calling, continuing without tools");
+                }
+            }
+
+            // Try all usable keys for this provider before moving to next provider.
+            // Cooling or disabled keys are skipped by `next_key`.
             for _attempt in 0..num_keys {
-                let key = match entry.keys.next_key() {
+                let (slot, key) = match entry.keys.next_key() {
                     Some(k) => k,
-                    None => break,
+                    None => {
+                        info!("{provider_name}: no usable keys (all cooling down or disabled)");
+                        break;
+                    }
                 };
 
-                info!("Trying provider: {provider_name} (key: {}...)", &key[..key.len().min(10)]);
-                match entry.provider.chat(messages, tools, key).await {
+                info!("Trying provider: {provider_name}/{model} (key: {}...)", &key[..key.len().min(10)]);
+                match self.call_with_retry(entry, key, model, req).await {
                     Ok(response) => {
                         info!("Provider {provider_name} succeeded");
-                        return Ok((response, provider_name));
+                        entry.keys.report_success(slot);
+                        return Ok((response, provider_name, model.to_string()));
                     }
-                    Err(ProviderError::RateLimited) => {
-                        warn!("{provider_name} RATE LIMITED (key: {}...), trying next key", &key[..key.len().min(10)]);
+                    Err(ProviderError::RateLimited(retry_after)) => {
+                        let cooldown = entry.keys.report_rate_limited(slot, retry_after);
+                        warn!(
+                            "{provider_name} RATE LIMITED (key: {}...), cooling down {}s, trying next key",
+                            &key[..key.len().min(10)],
+                            cooldown.as_secs()
+                        );
                         continue; // try next key of same provider
                     }
                     Err(ProviderError::AuthError(e)) => {
-                        warn!("{provider_name} AUTH ERROR (key: {}...): {e}", &key[..key.len().min(10)]);
-                        break; // auth error = skip this provider entirely
+                        warn!("{provider_name} AUTH ERROR (key: {}...): {e}, disabling key", &key[..key.len().min(10)]);
+                        entry.keys.disable(slot);
+                        continue; // other keys of this provider may still be valid
+                    }
+                    Err(e) if e.is_transient() => {
+                        // Retries exhausted; the provider is struggling, not the key
+                        warn!("{provider_name} still failing after retries: {e}");
+                        break;
                     }
                     Err(e) => {
                         warn!("{provider_name} FAILED (key: {}...): {e}", &key[..key.len().min(10)]);
+                        entry.keys.report_failure(slot);
                         break; // other errors = skip this provider
                     }
                 }
@@ -182,38 +362,210 @@ impl ProviderPool {
         Err(ProviderError::RequestError("All providers failed".into()))
     }
 
+    /// Capabilities of a provider/model after applying `MODEL_CAPABILITIES` overrides.
+    fn capabilities_of(&self, entry: &ProviderEntry, model: &str) -> Capabilities {
+        let name = entry.provider.name();
+        self.capability_overrides
+            .apply(name, model, entry.provider.capabilities(model))
+    }
+
+    /// What a model must support to be routed `needs`. With tool emulation any
+    /// model can take tools, so tool calling doesn't reorder providers.
+    fn routing_needs(&self, needs: Requirements) -> Requirements {
+        if self.tool_emulation {
+            needs.without_tools()
+        } else {
+            needs
+        }
+    }
+
+    /// Whether tool calls to this model go through prompt-based emulation.
+    fn emulates_tools(&self, entry: &ProviderEntry, model: &str, req: &ChatRequest<'_>) -> bool {
+        self.tool_emulation && req.needs.tools && !self.capabilities_of(entry, model).tools
+    }
+
+    fn qualifies(&self, entry: &ProviderEntry, model: &str, needs: Requirements, free_only: bool) -> bool {
+        (!free_only || self.prices.is_free(entry.provider.name(), model))
+            && needs.missing(&self.capabilities_of(entry, model)).is_empty()
+    }
+
+    /// Providers to try, in fallback order, each with the first model in its catalog
+    /// (default first) that meets `needs`. Providers with no such model are left out.
+    fn plan_routes(&self, needs: Requirements, free_only: bool) -> Vec<(usize, &str)> {
+        self.provider_order()
+            .into_iter()
+            .filter_map(|idx| {
+                let entry = &self.providers[idx];
+                let model = entry
+                    .models
+                    .iter()
+                    .find(|m| self.qualifies(entry, m, needs, free_only))?;
+                Some((idx, model.as_str()))
+            })
+            .collect()
+    }
+
+    /// Explain why no provider can serve a request.
+    fn unsupported_reason(&self, req: &ChatRequest<'_>) -> String {
+        let all_caps: Vec<Capabilities> = self
+            .providers
+            .iter()
+            .flat_map(|entry| entry.models.iter().map(|m| self.capabilities_of(entry, m)))
+            .collect();
+        let needs = req.needs.without_tools();
+        let unmet: Vec<&str> = needs
+            .missing(&Capabilities::default())
+            .into_iter()
+            .filter(|what| all_caps.iter().all(|caps| needs.missing(caps).contains(what)))
+            .collect();
+        if !unmet.is_empty() {
+            format!("No configured model supports {}.", unmet.join(" or "))
+        } else if req.opts.free_only {
+            "The paid budget is used up and no free model can handle this request.".into()
+        } else {
+            "No configured model supports everything this request needs.".into()
+        }
+    }
+
+    /// Call a provider with one key, retrying transient failures per the retry policy.
+    async fn call_with_retry(
+        &self,
+        entry: &ProviderEntry,
+        key: &str,
+        model: &str,
+        req: &ChatRequest<'_>,
+    ) -> Result<LlmResponse, ProviderError> {
+        let emulate_tools = self.emulates_tools(entry, model, req);
+        let mut attempts = 0;
+        loop {
+            // A previous attempt may have streamed partial output
+            let restarted = req.started.swap(true, Ordering::Relaxed);
+            if let Some(f) = req.opts.on_event.filter(|_| restarted) {
+                f(StreamEvent::Restart);
+            }
+            attempts += 1;
+            match entry
+                .chat(req.messages, req.tools, key, model, req.opts.on_event, emulate_tools)
+                .await
+            {
+                Err(e) if e.is_transient() => match self.retry.next_delay(attempts, req.deadline) {
+                    Some(delay) => {
+                        warn!(
+                            "{}/{model} transient error (attempt {attempts}/{}): {e}, retrying in {}ms",
+                            entry.provider.name(),
+                            self.retry.max_attempts,
+                            delay.as_millis()
+                        );
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => return Err(e),
+                },
+                result => return result,
+            }
+        }
+    }
+
+    fn default_idx(&self) -> usize {
+        self.providers
+            .iter()
+            .position(|p| p.provider.name() == self.default_provider)
+            .unwrap_or(0)
+    }
+
     fn provider_order(&self) -> Vec<usize> {
-        let mut order = vec![self.default_idx];
+        let default_idx = self.default_idx();
+        let mut order = vec![default_idx];
         for i in 0..self.providers.len() {
-            if i != self.default_idx {
+            if i != default_idx {
                 order.push(i);
             }
         }
         order
     }
 
-    /// Send a chat request to a specific provider by name.
-    /// Falls back to default pool behavior if the named provider is unavailable.
+    /// Resolve a `provider[:model]` spec (e.g. "claude", "claude:haiku") to a provider
+    /// index and full model name. A missing model part selects the provider's default.
+    fn resolve(&self, spec: &str) -> Option<(usize, &str)> {
+        let (name, hint) = match spec.split_once(':') {
+            Some((name, hint)) => (name, Some(hint.trim())),
+            None => (spec, None),
+        };
+        let idx = self
+            .providers
+            .iter()
+            .position(|p| p.provider.name().eq_ignore_ascii_case(name.trim()))?;
+        let entry = &self.providers[idx];
+        let model = match hint {
+            None | Some("") => entry.default_model(),
+            Some(hint) => entry.find_model(hint)?,
+        };
+        Some((idx, model))
+    }
+
+    /// Normalize a `provider[:model]` spec to `provider:full-model-name`,
+    /// or `None` if the provider or model is unknown.
+    pub fn resolve_model(&self, spec: &str) -> Option<String> {
+        self.resolve(spec)
+            .map(|(idx, model)| format!("{}:{model}", self.providers[idx].provider.name()))
+    }
+
+    /// Send a chat request to a specific provider (and optionally model) given as a
+    /// `provider[:model]` spec. Falls back to default pool behavior if it is unavailable.
     pub async fn chat_with_provider(
         &self,
         messages: &[Message],
         tools: &[ToolDef],
-        provider_name: &str,
-    ) -> Result<(LlmResponse, String), ProviderError> {
+        spec: &str,
+        opts: ChatOptions<'_>,
+    ) -> Result<(LlmResponse, String, String), ProviderError> {
+        let req = ChatRequest::new(messages, tools, opts, &self.retry);
+        // A pinned text-only model still serves tool requests when they can be emulated
+        let pinned_needs = self.routing_needs(req.needs);
+
         // Try the requested provider first
-        if let Some(entry) = self.providers.iter().find(|p| p.provider.name() == provider_name) {
-            if let Some(key) = entry.keys.next_key() {
-                match entry.provider.chat(messages, tools, key).await {
-                    Ok(response) => return Ok((response, provider_name.to_string())),
-                    Err(e) => {
-                        warn!("{provider_name} failed: {e}, falling back to pool");
+        match self.resolve(spec) {
+            Some((idx, model))
+                if opts.free_only
+                    && !self.prices.is_free(self.providers[idx].provider.name(), model) =>
+            {
+                warn!("{spec} is a paid model and the budget is exhausted, using free providers");
+            }
+            Some((idx, model))
+                if !self.qualifies(&self.providers[idx], model, pinned_needs, false) =>
+            {
+                let missing = pinned_needs.missing(&self.capabilities_of(&self.providers[idx], model));
+                warn!("{spec} lacks {}, using another provider", missing.join(", "));
+            }
+            Some((idx, model)) => {
+                let entry = &self.providers[idx];
+                let provider_name = entry.provider.name();
+                if let Some((slot, key)) = entry.keys.next_key() {
+                    match self.call_with_retry(entry, key, model, &req).await {
+                        Ok(response) => {
+                            entry.keys.report_success(slot);
+                            return Ok((response, provider_name.to_string(), model.to_string()));
+                        }
+                        Err(e) => {
+                            match &e {
+                                ProviderError::RateLimited(retry_after) => {
+                                    entry.keys.report_rate_limited(slot, *retry_after);
+                                }
+                                ProviderError::AuthError(_) => entry.keys.disable(slot),
+                                e if e.is_transient() => {}
+                                _ => entry.keys.report_failure(slot),
+                            }
+                            warn!("{provider_name}/{model} failed: {e}, falling back to pool");
+                        }
                     }
+                } else {
+                    warn!("{provider_name}: no usable keys, falling back to pool");
                 }
             }
+            None => warn!("Unknown provider/model '{spec}', falling back to pool"),
         }
 
         // Fallback to round-robin
-        self.chat(messages, tools).await
+        self.chat_pool(&req).await
     }
 
     pub fn available_providers(&self) -> Vec<&str> {
@@ -223,4 +575,147 @@ impl ProviderPool {
             .map(|p| p.provider.name())
             .collect()
     }
+
+    /// Key health per provider: (provider, usable keys, total keys).
+    pub fn key_health(&self) -> Vec<(&str, usize, usize)> {
+        self.providers
+            .iter()
+            .map(|p| (p.provider.name(), p.keys.available(), p.keys.len()))
+            .collect()
+    }
+
+    /// Whether any key state changed since the last `key_snapshot`.
+    pub fn key_states_dirty(&self) -> bool {
+        self.providers.iter().any(|p| p.keys.is_dirty())
+    }
+
+    /// Snapshot of all key cooldowns for persistence.
+    pub fn key_snapshot(&self) -> Vec<KeySnapshot> {
+        self.providers
+            .iter()
+            .flat_map(|p| p.keys.snapshot(p.provider.name()))
+            .collect()
+    }
+
+    /// Restore key cooldowns persisted by a previous run.
+    pub fn restore_key_states(&self, snapshots: &[KeySnapshot]) {
+        for entry in &self.providers {
+            let name = entry.provider.name();
+            let own: Vec<KeySnapshot> = snapshots
+                .iter()
+                .filter(|s| s.provider == name)
+                .cloned()
+                .collect();
+            entry.keys.restore(&own);
+        }
+    }
+
+    /// Capabilities of a provider's default model.
+    pub fn capabilities(&self, provider: &str) -> Option<Capabilities> {
+        self.providers
+            .iter()
+            .find(|p| p.provider.name() == provider)
+            .map(|p| self.capabilities_of(p, p.default_model()))
+    }
+
+    /// Context budget of the model `chat`/`chat_with_provider` would try first for
+    /// this request, ignoring its current size. `None` when no model qualifies.
+    pub fn context_budget(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDef],
+        spec: Option<&str>,
+        opts: ChatOptions<'_>,
+    ) -> Option<ContextBudget<'_>> {
+        let needs = self.routing_needs(Requirements {
+            context: 0,
+            ..Requirements::for_request(messages, tools, opts.json_mode)
+        });
+        let (idx, model) = spec
+            .and_then(|spec| self.resolve(spec))
+            .filter(|(idx, model)| self.qualifies(&self.providers[*idx], model, needs, opts.free_only))
+            .or_else(|| self.plan_routes(needs, opts.free_only).into_iter().next())
+            .or_else(|| self.plan_routes(needs.without_tools(), opts.free_only).into_iter().next())?;
+
+        let provider = self.providers[idx].provider.as_ref();
+        let max_context = self.capabilities_of(&self.providers[idx], model).max_context;
+        // Leave room for the reply: up to 8k tokens, less on small models
+        let reply_reserve = (max_context / 4).min(8192);
+        Some(ContextBudget {
+            provider,
+            model,
+            max_tokens: max_context.saturating_sub(reply_reserve),
+        })
+    }
+
+    pub fn prices(&self) -> &PriceTable {
+        &self.prices
+    }
+
+    /// Model catalog per available provider: (provider, models), default model first.
+    pub fn model_catalog(&self) -> Vec<(&str, &[String])> {
+        self.providers
+            .iter()
+            .filter(|p| !p.keys.is_empty())
+            .map(|p| (p.provider.name(), p.models.as_slice()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl ProviderPool {
+    /// A pool with default settings and no providers; add them with `register`.
+    pub(crate) fn for_tests(default_provider: &str, tool_emulation: bool) -> Self {
+        Self {
+            // Retry immediately so tests don't wait on backoff
+            retry: RetryPolicy {
+                base_delay: std::time::Duration::ZERO,
+                ..RetryPolicy::default()
+            },
+            tool_emulation,
+            ..Self::empty(default_provider)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gemma (no native tools) as the default, Claude (tools) as a fallback.
+    fn gemma_and_claude(tool_emulation: bool) -> ProviderPool {
+        let mut pool = ProviderPool::for_tests("gemini", tool_emulation);
+        pool.register(Box::new(ClaudeProvider::new()), vec!["k".into()], vec![ClaudeProvider::DEFAULT_MODEL.into()])
+            .unwrap();
+        pool.register(Box::new(GeminiProvider::new()), vec!["k".into()], vec![GeminiProvider::DEFAULT_MODEL.into()])
+            .unwrap();
+        pool
+    }
+
+    fn route_names(pool: &ProviderPool, needs: Requirements) -> Vec<&str> {
+        let needs = pool.routing_needs(needs);
+        pool.plan_routes(needs, false)
+            .into_iter()
+            .map(|(idx, _)| pool.providers[idx].provider.name())
+            .collect()
+    }
+
+    fn tool_request() -> Requirements {
+        Requirements {
+            tools: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_provider_stays_first_with_tool_emulation() {
+        let pool = gemma_and_claude(true);
+        assert_eq!(route_names(&pool, tool_request()), ["gemini", "claude"]);
+    }
+
+    #[test]
+    fn tool_capable_providers_come_first_without_emulation() {
+        let pool = gemma_and_claude(false);
+        assert_eq!(route_names(&pool, tool_request()), ["claude"]);
+    }
 }
@@ -0,0 +1,91 @@
+use super::types::Usage;
+
+/// Price of one model in USD per million tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ModelPrice {
+    pub input_per_m: f64,
+    pub output_per_m: f64,
+}
+
+impl ModelPrice {
+    pub fn is_free(&self) -> bool {
+        self.input_per_m <= 0.0 && self.output_per_m <= 0.0
+    }
+}
+
+/// Per-model price table used for cost estimates and budget enforcement.
+/// Entries match a provider name exactly or a model name by substring; first match wins.
+#[derive(Debug, Clone)]
+pub struct PriceTable {
+    entries: Vec<(String, ModelPrice)>,
+}
+
+impl Default for PriceTable {
+    /// Anthropic list prices. Gemini, Groq, Mistral and custom endpoints are
+    /// used on free tiers here, so anything unlisted costs nothing.
+    fn default() -> Self {
+        let entry = |pattern: &str, input_per_m, output_per_m| {
+            (pattern.to_string(), ModelPrice { input_per_m, output_per_m })
+        };
+        Self {
+            entries: vec![
+                entry("opus", 15.0, 75.0),
+                entry("sonnet", 3.0, 15.0),
+                entry("3-5-haiku", 0.8, 4.0),
+                entry("haiku", 1.0, 5.0),
+                // Unknown Claude models: assume Sonnet pricing rather than free
+                entry("claude", 3.0, 15.0),
+            ],
+        }
+    }
+}
+
+impl PriceTable {
+    /// Parse overrides like `gpt-4o=2.5/10,my-llm=0/0`; they take precedence over
+    /// the built-in prices. Malformed entries are skipped.
+    pub fn with_overrides(spec: &str) -> Self {
+        let mut table = Self::default();
+        let overrides: Vec<(String, ModelPrice)> = spec
+            .split(',')
+            .filter_map(|item| {
+                let (pattern, prices) = item.split_once('=')?;
+                let (input, output) = prices.split_once('/')?;
+                let price = ModelPrice {
+                    input_per_m: input.trim().parse().ok()?,
+                    output_per_m: output.trim().parse().ok()?,
+                };
+                let pattern = pattern.trim().to_lowercase();
+                (!pattern.is_empty()).then_some((pattern, price))
+            })
+            .collect();
+        table.entries.splice(0..0, overrides);
+        table
+    }
+
+    pub fn price(&self, provider: &str, model: &str) -> ModelPrice {
+        let model = model.to_lowercase();
+        self.entries
+            .iter()
+            .find(|(pattern, _)| model.contains(pattern.as_str()) || provider.eq_ignore_ascii_case(pattern))
+            .map(|(_, price)| *price)
+            .unwrap_or_default()
+    }
+
+    pub fn is_free(&self, provider: &str, model: &str) -> bool {
+        self.price(provider, model).is_free()
+    }
+
+    /// Estimated cost of `usage` in USD. Cache writes cost 1.25x the input
+    /// price and cache reads 0.1x, as on Anthropic.
+    pub fn cost(&self, provider: &str, model: &str, usage: &Usage) -> f64 {
+        let price = self.price(provider, model);
+        let uncached = usage
+            .prompt_tokens
+            .saturating_sub(usage.cache_read_tokens)
+            .saturating_sub(usage.cache_write_tokens);
+        let input = uncached as f64
+            + usage.cache_write_tokens as f64 * 1.25
+            + usage.cache_read_tokens as f64 * 0.1;
+        (input * price.input_per_m + usage.completion_tokens as f64 * price.output_per_m) / 1_000_000.0
+    }
+}
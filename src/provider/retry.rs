@@ -0,0 +1,54 @@
+use std::time::{Duration, Instant};
+
+/// Retry policy for transient provider failures (5xx, overloaded, connection errors),
+/// applied to the same key before falling back to another key or provider.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts per key, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles on every further retry.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Random spread applied to each delay, as a fraction (0.2 = ±20%).
+    pub jitter: f64,
+    /// Overall time budget for retrying within one request.
+    pub deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.2,
+            deadline: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (1-based), with jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exp = retry.saturating_sub(1).min(16);
+        let delay = self.base_delay.saturating_mul(1 << exp).min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (2.0 * random_unit() - 1.0);
+        delay.mul_f64(factor)
+    }
+
+    /// Delay before retrying after `attempts` failed attempts, or `None` when the
+    /// attempt limit is reached or waiting would overrun `deadline`.
+    pub fn next_delay(&self, attempts: u32, deadline: Instant) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let delay = self.backoff(attempts);
+        (Instant::now() + delay < deadline).then_some(delay)
+    }
+}
+
+/// Uniform value in [0, 1). Jitter only needs to decorrelate clients, not be secure.
+fn random_unit() -> f64 {
+    (uuid::Uuid::new_v4().as_u128() >> 75) as f64 / (1u64 << 53) as f64
+}
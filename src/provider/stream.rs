@@ -0,0 +1,72 @@
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+
+use super::types::ProviderError;
+
+type ByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<Vec<u8>>> + Send>>;
+
+/// Minimal Server-Sent Events reader over a streaming HTTP response.
+/// Yields the (joined) `data:` payload of each event; `event:`/`id:` lines and comments are ignored.
+pub struct SseReader {
+    stream: ByteStream,
+    buf: Vec<u8>,
+    data: String,
+    eof: bool,
+}
+
+impl SseReader {
+    pub fn new(resp: reqwest::Response) -> Self {
+        Self {
+            stream: Box::pin(resp.bytes_stream().map(|r| r.map(|b| b.to_vec()))),
+            buf: Vec::new(),
+            data: String::new(),
+            eof: false,
+        }
+    }
+
+    /// Read the next event's data payload. Returns `None` once the stream is exhausted.
+    pub async fn next_event(&mut self) -> Result<Option<String>, ProviderError> {
+        loop {
+            if let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+                let raw: Vec<u8> = self.buf.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&raw);
+                let line = line.trim_end_matches(['\n', '\r']);
+
+                if line.is_empty() {
+                    // Blank line terminates an event
+                    if !self.data.is_empty() {
+                        return Ok(Some(std::mem::take(&mut self.data)));
+                    }
+                    continue;
+                }
+
+                if let Some(value) = line.strip_prefix("data:") {
+                    if !self.data.is_empty() {
+                        self.data.push('\n');
+                    }
+                    self.data.push_str(value.strip_prefix(' ').unwrap_or(value));
+                }
+                continue;
+            }
+
+            if self.eof {
+                // Flush a trailing event without the final blank line
+                if !self.buf.is_empty() {
+                    self.buf.push(b'\n');
+                    continue;
+                }
+                if !self.data.is_empty() {
+                    return Ok(Some(std::mem::take(&mut self.data)));
+                }
+                return Ok(None);
+            }
+
+            match self.stream.next().await {
+                Some(Ok(chunk)) => self.buf.extend_from_slice(&chunk),
+                Some(Err(e)) => return Err(ProviderError::from_reqwest(e)),
+                None => self.eof = true,
+            }
+        }
+    }
+}
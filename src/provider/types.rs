@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 /// A message in the conversation
@@ -55,14 +57,14 @@ impl MessageContent {
 }
 
 /// Tool definition sent to the LLM
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolDef {
     #[serde(rename = "type")]
     pub tool_type: String,
     pub function: FunctionDef,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionDef {
     pub name: String,
     pub description: String,
@@ -83,29 +85,102 @@ pub struct ToolCallFunction {
 }
 
 /// LLM response (either text content or tool calls)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LlmResponse {
     pub content: Option<String>,
+    #[serde(default)]
     pub tool_calls: Vec<ToolCall>,
+    #[serde(default)]
     pub usage: Usage,
 }
 
-#[derive(Debug, Clone, Default)]
+/// Incremental event emitted while a streamed response is being received.
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// A chunk of assistant text.
+    TextDelta(String),
+    /// A tool call whose name and arguments have been fully assembled.
+    ToolCall(ToolCall),
+    /// The request is being retried or sent elsewhere; discard anything streamed so far.
+    Restart,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Usage {
+    /// All input tokens, including those read from or written to the prompt cache.
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
+    /// Input tokens served from the provider's prompt cache.
+    pub cache_read_tokens: u32,
+    /// Input tokens written to the provider's prompt cache.
+    pub cache_write_tokens: u32,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens = self.prompt_tokens.saturating_add(other.prompt_tokens);
+        self.completion_tokens = self.completion_tokens.saturating_add(other.completion_tokens);
+        self.cache_read_tokens = self.cache_read_tokens.saturating_add(other.cache_read_tokens);
+        self.cache_write_tokens = self.cache_write_tokens.saturating_add(other.cache_write_tokens);
+    }
+
+    /// Share of input tokens served from cache, or `None` when caching wasn't used.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        if self.cache_read_tokens == 0 && self.cache_write_tokens == 0 {
+            return None;
+        }
+        Some(self.cache_read_tokens as f64 / self.prompt_tokens.max(1) as f64)
+    }
+
+    pub fn total(&self) -> u32 {
+        self.prompt_tokens.saturating_add(self.completion_tokens)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ProviderError {
+    /// HTTP 429, with the provider's advertised reset delay when available.
     #[error("Rate limited")]
-    RateLimited,
+    RateLimited(Option<Duration>),
     #[error("Auth error: {0}")]
     AuthError(String),
+    /// Server-side or network failure (5xx, overloaded, connection reset) worth retrying.
+    #[error("Transient error: {0}")]
+    Transient(String),
+    /// Permanent request failure; retrying the same request won't help.
     #[error("Request error: {0}")]
     RequestError(String),
     #[error("Parse error: {0}")]
     ParseError(String),
     #[error("No available keys")]
     NoKeys,
+    /// No registered provider/model has the capabilities the request needs.
+    #[error("{0}")]
+    Unsupported(String),
+}
+
+impl ProviderError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ProviderError::Transient(_))
+    }
+
+    /// Classify a failed HTTP status: 5xx and 529 (overloaded) are transient.
+    pub fn from_status(status: reqwest::StatusCode, body: &str) -> Self {
+        let msg = format!("HTTP {status}: {body}");
+        if status.is_server_error() || status.as_u16() == 529 {
+            ProviderError::Transient(msg)
+        } else {
+            ProviderError::RequestError(msg)
+        }
+    }
+
+    /// Classify a transport error: connection failures and timeouts are transient.
+    pub fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_connect() || e.is_timeout() || e.is_request() || e.is_body() {
+            ProviderError::Transient(e.to_string())
+        } else {
+            ProviderError::RequestError(e.to_string())
+        }
+    }
 }
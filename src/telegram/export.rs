@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+
+use crate::db::HistoryRow;
+use crate::provider::{Message, MessageContent};
+
+/// Identifies files written by `/export`.
+const FORMAT: &str = "free-agent-session";
+const VERSION: u32 = 1;
+/// At most this many conversations are taken from one import file.
+const MAX_IMPORT_SESSIONS: usize = 100;
+
+/// A session as written by `/export` and read back by `/import`.
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    format: String,
+    version: u32,
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(default)]
+    exported_at: String,
+    messages: Vec<FileMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileMessage {
+    role: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    /// The structured tool call or result, as kept in history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<serde_json::Value>,
+}
+
+/// A conversation read from an import file.
+pub struct ImportedSession {
+    pub title: String,
+    pub messages: Vec<HistoryRow>,
+}
+
+/// The session as JSON that `/import` accepts.
+pub fn to_json(title: &str, summary: Option<&str>, messages: &[HistoryRow]) -> String {
+    let file = SessionFile {
+        format: FORMAT.into(),
+        version: VERSION,
+        title: title.to_string(),
+        summary: summary.map(str::to_string),
+        exported_at: now(),
+        messages: messages
+            .iter()
+            .map(|m| FileMessage {
+                role: m.role.clone(),
+                content: m.content.clone(),
+                created_at: Some(m.created_at.clone()),
+                message: m.message.as_deref().and_then(|json| serde_json::from_str(json).ok()),
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&file).unwrap_or_default()
+}
+
+/// The session as a readable Markdown transcript.
+pub fn to_markdown(title: &str, summary: Option<&str>, messages: &[HistoryRow]) -> String {
+    let mut out = format!("# {title}\n\n_Exported {} UTC, {} messages_\n\n", now(), messages.len());
+    if let Some(summary) = summary {
+        out.push_str("> **Summary of earlier messages**\n");
+        for line in summary.lines() {
+            out.push_str(&format!("> {line}\n"));
+        }
+        out.push('\n');
+    }
+    for m in messages {
+        let speaker = match m.role.as_str() {
+            "user" => "User",
+            "tool" => "Tool result",
+            _ => "Assistant",
+        };
+        out.push_str(&format!("## {speaker} · {}\n\n", m.created_at));
+        if m.message.is_some() {
+            // Tool calls and results are shown verbatim
+            out.push_str(&format!("```\n{}\n```\n\n", m.content.replace("```", "'''")));
+        } else {
+            out.push_str(&format!("{}\n\n", m.content.trim_end()));
+        }
+    }
+    out
+}
+
+/// Conversations in a file written by `/export`, or in a ChatGPT export
+/// (`conversations.json`, or a single conversation from it).
+pub fn parse_import(bytes: &[u8]) -> Result<Vec<ImportedSession>, String> {
+    let value: serde_json::Value =
+        serde_json::from_slice(bytes).map_err(|e| format!("Not a JSON file: {e}"))?;
+
+    if value["format"] == FORMAT {
+        let file: SessionFile =
+            serde_json::from_value(value).map_err(|e| format!("Invalid session file: {e}"))?;
+        if file.version > VERSION {
+            return Err(format!("Session file version {} is newer than this bot supports", file.version));
+        }
+        return Ok(vec![from_session_file(file)]);
+    }
+
+    let conversations = match &value {
+        serde_json::Value::Array(items) => items.iter().collect(),
+        serde_json::Value::Object(_) => vec![&value],
+        _ => Vec::new(),
+    };
+    let mut sessions: Vec<ImportedSession> = conversations
+        .into_iter()
+        .filter(|c| c["mapping"].is_object())
+        .filter_map(from_chatgpt)
+        .collect();
+    if sessions.is_empty() {
+        return Err("No conversations found: expected a file from /export or a ChatGPT export".into());
+    }
+    // Keep the most recent ones, oldest first so the newest ends up active
+    let last_active = |s: &ImportedSession| s.messages.last().map(|m| m.created_at.clone()).unwrap_or_default();
+    sessions.sort_by_key(|s| std::cmp::Reverse(last_active(s)));
+    sessions.truncate(MAX_IMPORT_SESSIONS);
+    sessions.reverse();
+    Ok(sessions)
+}
+
+fn from_session_file(file: SessionFile) -> ImportedSession {
+    let messages = file
+        .messages
+        .into_iter()
+        .filter(|m| matches!(m.role.as_str(), "user" | "assistant" | "tool"))
+        .map(|m| HistoryRow {
+            role: m.role,
+            content: m.content,
+            // Keep only structured messages this build can read back
+            message: m
+                .message
+                .filter(|v| serde_json::from_value::<Message>(v.clone()).is_ok())
+                .map(|v| v.to_string()),
+            created_at: m.created_at.unwrap_or_else(now),
+        })
+        .collect();
+    ImportedSession {
+        title: file.title,
+        messages: keep_complete_tool_turns(messages),
+    }
+}
+
+/// A ChatGPT conversation: the branch ending at `current_node`, walked back
+/// through `parent` links, with only user and assistant text kept.
+fn from_chatgpt(conversation: &serde_json::Value) -> Option<ImportedSession> {
+    let mapping = conversation["mapping"].as_object()?;
+    let mut node = conversation["current_node"].as_str().or_else(|| {
+        // No current node recorded: use the latest leaf
+        mapping
+            .iter()
+            .filter(|(_, n)| n["children"].as_array().is_none_or(|c| c.is_empty()))
+            .max_by(|(_, a), (_, b)| {
+                let time = |n: &serde_json::Value| n["message"]["create_time"].as_f64().unwrap_or(0.0);
+                time(a).total_cmp(&time(b))
+            })
+            .map(|(id, _)| id.as_str())
+    });
+
+    let mut messages = Vec::new();
+    // Bounded by the mapping size, so a parent cycle can't loop forever
+    for _ in 0..mapping.len() {
+        let Some(current) = node.and_then(|id| mapping.get(id)) else {
+            break;
+        };
+        let message = &current["message"];
+        let role = message["author"]["role"].as_str().unwrap_or("");
+        let text = message["content"]["parts"]
+            .as_array()
+            .map(|parts| parts.iter().filter_map(|p| p.as_str()).collect::<Vec<_>>().join("\n"))
+            .unwrap_or_default();
+        let hidden = message["metadata"]["is_visually_hidden_from_conversation"].as_bool() == Some(true);
+        if matches!(role, "user" | "assistant") && !text.trim().is_empty() && !hidden {
+            messages.push(HistoryRow {
+                role: role.to_string(),
+                content: text,
+                message: None,
+                created_at: message["create_time"].as_f64().map_or_else(now, format_timestamp),
+            });
+        }
+        node = current["parent"].as_str();
+    }
+    if messages.is_empty() {
+        return None;
+    }
+    messages.reverse();
+    let title = conversation["title"].as_str().unwrap_or("").trim().to_string();
+    Some(ImportedSession { title, messages })
+}
+
+/// Tool calls must be followed by exactly their results. Keep complete turns
+/// structured; anything else falls back to plain text (calls) or is dropped
+/// (orphan results), so a hand-edited file can't break later requests.
+fn keep_complete_tool_turns(rows: Vec<HistoryRow>) -> Vec<HistoryRow> {
+    let parse = |row: &HistoryRow| row.message.as_deref().and_then(|json| serde_json::from_str::<Message>(json).ok());
+    let mut out = Vec::with_capacity(rows.len());
+    let mut i = 0;
+    while i < rows.len() {
+        let call_ids: Option<Vec<String>> = match parse(&rows[i]).map(|m| m.content) {
+            Some(MessageContent::AssistantWithToolCalls { tool_calls, .. }) => {
+                Some(tool_calls.into_iter().map(|tc| tc.id).collect())
+            }
+            _ => None,
+        };
+        let Some(call_ids) = call_ids else {
+            if rows[i].role != "tool" {
+                out.push(HistoryRow {
+                    message: None,
+                    ..rows[i].clone()
+                });
+            }
+            i += 1;
+            continue;
+        };
+
+        let results_end = rows[i + 1..]
+            .iter()
+            .position(|r| r.role != "tool")
+            .map_or(rows.len(), |p| i + 1 + p);
+        let result_ids: Vec<String> = rows[i + 1..results_end]
+            .iter()
+            .filter_map(|r| match parse(r).map(|m| m.content) {
+                Some(MessageContent::ToolResult { tool_call_id, .. }) => Some(tool_call_id),
+                _ => None,
+            })
+            .collect();
+        if result_ids == call_ids && results_end - i - 1 == call_ids.len() {
+            out.extend_from_slice(&rows[i..results_end]);
+        } else {
+            out.push(HistoryRow {
+                message: None,
+                ..rows[i].clone()
+            });
+        }
+        i = results_end;
+    }
+    out
+}
+
+fn format_timestamp(secs: f64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(now)
+}
+
+fn now() -> String {
+    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
@@ -1,3 +1,6 @@
+use crate::provider::Usage;
+use crate::provider::backend::Capabilities;
+
 /// Tool icons and message formatting for Telegram output.
 
 /// Strip raw function/tool call syntax that some LLMs leak into text responses.
@@ -228,7 +231,8 @@ pub fn tool_icon(name: &str) -> &str {
     match name {
         "web_search" => "🌐",
         "web_fetch" => "📥",
-        "memory_save" | "memory_search" | "memory_list" | "memory_delete" => "🧠",
+        "memory_save" | "memory_search" | "memory_update" | "memory_merge" | "memory_list" | "memory_delete" => "🧠",
+        "conversation_search" => "💬",
         "bash" => "⚡",
         "read" => "📖",
         "write" => "✏️",
@@ -248,7 +252,9 @@ pub fn format_tools_footer(
     tools_count: &[usize],
     elapsed_secs: f64,
     provider: &str,
+    model: &str,
     turns: usize,
+    usage: &Usage,
 ) -> String {
     let mut parts = Vec::new();
 
@@ -269,20 +275,81 @@ pub fn format_tools_footer(
 
     parts.push(format!("⏱ {elapsed_secs:.1}s"));
 
+    let source = if model.is_empty() {
+        provider.to_string()
+    } else {
+        format!("{provider}/{model}")
+    };
     if turns > 1 {
-        parts.push(format!("{provider} ({turns} turns)"));
+        parts.push(format!("{source} ({turns} turns)"));
     } else {
-        parts.push(provider.to_string());
+        parts.push(source);
+    }
+
+    if usage.total() > 0 {
+        let mut tokens = format!(
+            "{} in / {} out tok",
+            format_tokens(usage.prompt_tokens),
+            format_tokens(usage.completion_tokens)
+        );
+        if let Some(rate) = usage.cache_hit_rate() {
+            tokens.push_str(&format!(" ({:.0}% cached)", rate * 100.0));
+        }
+        parts.push(tokens);
     }
 
     format!("\n\n---\n{}", parts.join("  |  "))
 }
 
+/// Short capability tags for `/providers`, e.g. "tools, vision, streaming, 200k ctx".
+pub fn format_capabilities(caps: &Capabilities) -> String {
+    let mut tags: Vec<String> = [
+        (caps.tools, "tools"),
+        (caps.vision, "vision"),
+        (caps.json_mode, "json"),
+        (caps.streaming, "streaming"),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, tag)| tag.to_string())
+    .collect();
+    if tags.is_empty() {
+        tags.push("text only".into());
+    }
+    if caps.max_context > 0 && caps.max_context < u32::MAX {
+        tags.push(format!("{} ctx", format_tokens(caps.max_context)));
+    }
+    tags.join(", ")
+}
+
+/// Compact token count: 950, 12.3k, 128k, 1.2M.
+fn format_tokens(n: u32) -> String {
+    let text = match n {
+        0..1_000 => return n.to_string(),
+        1_000..1_000_000 => format!("{:.1}k", n as f64 / 1_000.0),
+        _ => format!("{:.1}M", n as f64 / 1_000_000.0),
+    };
+    text.replace(".0", "")
+}
+
 pub fn format_progress(current_tool: &str) -> String {
     let icon = tool_icon(current_tool);
     format!("⏳ {icon} Đang dùng {current_tool}...")
 }
 
+/// Render partially streamed text for the progress message, keeping it under
+/// Telegram's 4096-char edit limit.
+pub fn format_streaming(text: &str) -> String {
+    const MAX_PREVIEW: usize = 4000;
+    let text = text.trim_start();
+    if text.len() > MAX_PREVIEW {
+        let end = floor_char_boundary(text, MAX_PREVIEW);
+        format!("{}… ▌", &text[..end])
+    } else {
+        format!("{text} ▌")
+    }
+}
+
 /// Find the largest char-boundary index <= `pos` in `s`.
 fn floor_char_boundary(s: &str, pos: usize) -> usize {
     if pos >= s.len() {
@@ -5,22 +5,27 @@ use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 use std::time::Duration;
 use base64::Engine;
 use teloxide::prelude::*;
-use teloxide::types::{BotCommand, ChatAction, ParseMode};
+use teloxide::types::{BotCommand, ChatAction, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode};
 use teloxide::update_listeners::Polling;
 use tracing::{error, info, warn};
 
-use crate::agent::{AgentLoop, AgentProgress};
+use crate::agent::extraction::{self, ExtractionMode};
+use crate::agent::{budget, compaction, titles};
+use crate::agent::{AgentContext, AgentLoop, AgentProgress, ToolContext};
 use crate::config::Config;
-use crate::db::Database;
-use crate::provider::{ImageData, Message, MessageContent, ProviderPool, Role};
+use crate::db::{Database, FactMeta};
+use crate::provider::{Embedder, ImageData, MessageContent, ProviderPool, embedder_from_config};
 use crate::skills;
+use crate::tools;
 use crate::tools::claude_code::ClaudeCodeManager;
 
-use super::formatter;
+use super::{export, formatter};
 
 struct AppState {
     pool: ProviderPool,
     db: Database,
+    /// Embeddings for semantic memory search, when configured.
+    embedder: Option<Box<dyn Embedder>>,
     config: Config,
     skills_content: String,
     base_prompt: String,
@@ -29,19 +34,33 @@ struct AppState {
     cancel_flags: std::sync::Mutex<HashMap<i64, Arc<AtomicBool>>>,
 }
 
+impl AppState {
+    /// Budget owner for background calls made on behalf of `user_id`.
+    fn payer(&self, user_id: u64) -> budget::Payer<'_> {
+        budget::Payer {
+            user_id,
+            limits: &self.config.budget,
+        }
+    }
+}
+
+/// Start the bot with the providers configured in `config`.
 pub async fn run_bot(config: Config) {
-    let bot = Bot::new(&config.telegram_bot_token);
+    let pool = ProviderPool::new(&config);
+    run_bot_with_pool(config, pool).await;
+}
 
-    let pool = ProviderPool::new(
-        config.claude_keys.clone(),
-        config.gemini_keys.clone(),
-        config.groq_keys.clone(),
-        config.mistral_keys.clone(),
-        &config.default_provider,
-    );
+/// Start the bot with a prepared provider pool, e.g. one with extra providers
+/// added through `ProviderPool::register`.
+pub async fn run_bot_with_pool(config: Config, pool: ProviderPool) {
+    let bot = Bot::new(&config.telegram_bot_token);
 
     let db = Database::open("free-agent.db").expect("Failed to open database");
 
+    if config.persist_key_state {
+        pool.restore_key_states(&db.load_key_states().await);
+    }
+
     let skills_content = skills::load_skills("skills");
 
     // Create ClaudeCodeManager if enabled
@@ -57,7 +76,7 @@ pub async fn run_bot(config: Config) {
     let sys_ok = config.enable_system_tools;
     let mut tool_list = vec![
         "web_search", "web_fetch", "memory_save", "memory_search",
-        "memory_list", "memory_delete", "get_datetime",
+        "memory_update", "memory_merge", "memory_list", "memory_delete", "conversation_search", "get_datetime",
         "plan_read", "plan_write",
         "todo_add", "todo_list", "todo_update", "todo_delete", "todo_clear_completed",
     ];
@@ -102,7 +121,9 @@ pub async fn run_bot(config: Config) {
         ## Memory Management\n\
         Bạn có hệ thống memory dài hạn.\n\
         - Dùng memory_save khi user chia sẻ thông tin quan trọng (preferences, decisions, projects, personal info)\n\
+        - Nếu thông tin đã có hoặc thay đổi: dùng memory_update thay vì lưu mới; gộp các memory trùng lặp bằng memory_merge\n\
         - Dùng memory_search khi cần nhớ lại context cũ hoặc khi user hỏi về điều đã nói trước đó\n\
+        - Dùng conversation_search để tìm lại nội dung các hội thoại trước (đã bàn gì, quyết định gì, kết quả nào) khi memory không có\n\
         - KHÔNG gọi memory_search cho mọi tin nhắn — chỉ search khi thực sự cần context\n\
         - KHÔNG search keyword vô nghĩa (ví dụ: không search \"hello\", \"hi\", \"heloo\")\n\n\
         ## Tools\n\
@@ -140,6 +161,7 @@ pub async fn run_bot(config: Config) {
     let state = Arc::new(AppState {
         pool,
         db,
+        embedder: embedder_from_config(&config),
         config: config.clone(),
         skills_content,
         base_prompt,
@@ -147,6 +169,44 @@ pub async fn run_bot(config: Config) {
         cancel_flags: std::sync::Mutex::new(HashMap::new()),
     });
 
+    // Prune expired facts and history and logs past their retention, then reclaim the space
+    if config.prune_interval_hours > 0 {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(state.config.prune_interval_hours * 3600));
+            loop {
+                interval.tick().await;
+                match state.db.prune(state.config.retention).await {
+                    Ok(stats) if stats.facts + stats.sessions + stats.messages + stats.query_logs > 0 => info!(
+                        "Pruned {} expired facts, {} sessions, {} messages and {} query logs",
+                        stats.facts, stats.sessions, stats.messages, stats.query_logs
+                    ),
+                    Ok(_) => {}
+                    Err(e) => warn!("Pruning failed: {e}"),
+                }
+                if let Err(e) = state.db.vacuum().await {
+                    warn!("Vacuum failed: {e}");
+                }
+            }
+        });
+    }
+
+    // Periodically persist key cooldowns so a restart doesn't hammer rate-limited keys
+    if config.persist_key_state {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(30));
+            loop {
+                interval.tick().await;
+                if state.pool.key_states_dirty()
+                    && let Err(e) = state.db.save_key_states(&state.pool.key_snapshot()).await
+                {
+                    warn!("Failed to persist key states: {e}");
+                }
+            }
+        });
+    }
+
     info!(
         "Bot started. Providers: {:?}, Tools: {}, SystemTools: {}, Gmail: {}, ClaudeCode: {}, Allowed users: {:?}",
         state.pool.available_providers(),
@@ -162,10 +222,23 @@ pub async fn run_bot(config: Config) {
         BotCommand::new("start", "Bot info & status"),
         BotCommand::new("help", "Show available commands"),
         BotCommand::new("new", "Start new conversation"),
+        BotCommand::new("sessions", "List conversations"),
+        BotCommand::new("resume", "Switch to an earlier conversation"),
+        BotCommand::new("rename", "Rename the current conversation"),
+        BotCommand::new("delete", "Delete a conversation"),
+        BotCommand::new("search", "Search past conversations"),
+        BotCommand::new("forget_me", "Delete all your data"),
+        BotCommand::new("export", "Export a conversation as Markdown + JSON"),
+        BotCommand::new("import", "Import a conversation (caption of a JSON file)"),
+        BotCommand::new("compact", "Summarize older conversation history"),
         BotCommand::new("stop", "Stop current query"),
         BotCommand::new("tools", "List available tools"),
         BotCommand::new("memory", "View saved memories"),
+        BotCommand::new("pin", "Pin/unpin a memory in every prompt"),
+        BotCommand::new("reindex", "Embed memories for semantic search"),
         BotCommand::new("providers", "Show LLM providers"),
+        BotCommand::new("model", "Pick provider/model"),
+        BotCommand::new("usage", "Token usage and remaining budget"),
     ];
     if let Err(e) = bot.set_my_commands(commands).await {
         error!("Failed to set bot commands: {e}");
@@ -173,7 +246,9 @@ pub async fn run_bot(config: Config) {
         info!("Bot commands menu registered");
     }
 
-    let handler = Update::filter_message().endpoint(handle_message);
+    let handler = dptree::entry()
+        .branch(Update::filter_message().endpoint(handle_message))
+        .branch(Update::filter_callback_query().endpoint(handle_callback));
 
     // Use custom polling listener: delete stale webhook + drop pending updates
     // to prevent TerminatedByOtherGetUpdates on restart
@@ -520,6 +595,11 @@ async fn handle_message(
         .unwrap_or("")
         .to_string();
 
+    // An import file is restored as a session, not read as an attachment
+    if raw_text.split_whitespace().next() == Some("/import") {
+        return handle_import(&msg, &bot, &state, user_id).await;
+    }
+
     // Process file attachments
     let (images, file_text) = process_attachments(
         &msg,
@@ -539,8 +619,13 @@ async fn handle_message(
         return handle_command(&msg, &bot, &state, &raw_text, user_id).await;
     }
 
-    // Parse inline provider override
-    let (preferred_provider, user_text_parsed) = parse_provider_override(&raw_text);
+    // Parse inline provider override, else use the model picked via /model
+    let (inline_provider, user_text_parsed) =
+        parse_provider_override(&raw_text, &state.pool.available_providers());
+    let preferred_provider = match inline_provider {
+        Some(provider) => Some(provider),
+        None => state.db.get_model_preference(user_id).await,
+    };
 
     // Combine text content with file info
     let combined_text = if file_text.is_empty() {
@@ -578,12 +663,29 @@ async fn handle_message(
         }
     });
 
-    // Progress callback: edit the progress message when tools are used
+    // Progress edits go through a single task so they apply in order and never
+    // land after the final response edit.
+    let (edit_tx, mut edit_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
     let bot_progress = bot.clone();
     let progress_chat_id = msg.chat.id;
+    let editor_handle = tokio::spawn(async move {
+        while let Some(text) = edit_rx.recv().await {
+            safe_edit(&bot_progress, progress_chat_id, progress_msg_id, &text).await;
+        }
+    });
+
+    // Progress callback: edit the progress message when tools are used or text streams in
     let last_edit = Arc::new(AtomicI64::new(0));
+    // Text streamed in the current turn, rebuilt from the chunks
+    let streamed = std::sync::Mutex::new(String::new());
 
     let on_progress = move |progress: AgentProgress| {
+        match &progress {
+            AgentProgress::Streaming(delta) => streamed.lock().unwrap().push_str(delta),
+            AgentProgress::Thinking => streamed.lock().unwrap().clear(),
+            AgentProgress::ToolUse(_) => {}
+        }
+
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or_default()
@@ -598,40 +700,38 @@ async fn handle_message(
         let display_text = match &progress {
             AgentProgress::ToolUse(name) => formatter::format_progress(name),
             AgentProgress::Thinking => "⏳ Đang suy nghĩ...".to_string(),
+            AgentProgress::Streaming(_) => formatter::format_streaming(&streamed.lock().unwrap()),
         };
 
         last_edit.store(now, Ordering::Relaxed);
-        let bot_inner = bot_progress.clone();
-        let _ = tokio::task::spawn(async move {
-            safe_edit(&bot_inner, progress_chat_id, progress_msg_id, &display_text).await;
-        });
+        let _ = edit_tx.send(display_text);
     };
 
     // Build system prompt with memory
-    let memory_ctx = state.db.build_memory_context(user_id);
-    let system_prompt = skills::build_system_prompt(
+    let session_id = state.db.get_or_create_session(user_id).await;
+    let memory_ctx = tools::memory_context(
+        &state.db,
+        state.embedder.as_deref(),
+        user_id,
+        &combined_text,
+        state.config.memory_context,
+    )
+    .await;
+    let mut system_prompt = skills::build_system_prompt(
         &state.base_prompt,
         &state.skills_content,
         &memory_ctx,
     );
+    // Older turns live on as a summary once the session has been compacted
+    if let Some(summary) = state.db.session_summary(&session_id).await {
+        system_prompt.push_str(&format!("\n\n## Earlier in this conversation\n\n{summary}"));
+    }
 
     // Load conversation history
-    let session_id = state.db.get_or_create_session(user_id);
-    let raw_history = state.db.load_history(&session_id, 10);
-    let history: Vec<Message> = raw_history
-        .into_iter()
-        .filter_map(|(role, content)| {
-            let r = match role.as_str() {
-                "user" => Role::User,
-                "assistant" => Role::Assistant,
-                _ => return None,
-            };
-            Some(Message { role: r, content: MessageContent::Text(content) })
-        })
-        .collect();
+    let history = state.db.load_history(&session_id, 10).await;
 
     // Save user message to history (text-only for DB)
-    state.db.append_message(&session_id, "user", &combined_text);
+    state.db.append_message(&session_id, "user", &combined_text).await;
 
     // Set up cancel flag for this chat
     let cancel_flag = Arc::new(AtomicBool::new(false));
@@ -642,24 +742,24 @@ async fn handle_message(
 
     // Run agent loop
     let start = std::time::Instant::now();
-    let result = AgentLoop::run(
-        &state.pool,
-        &system_prompt,
-        user_content,
-        user_id,
-        &state.db,
-        &state.config.gmail_creds,
-        state.config.enable_system_tools,
-        &state.config.working_dir,
-        state.config.bash_timeout,
-        state.config.max_agent_turns,
-        history,
-        preferred_provider.as_deref(),
-        state.cc_manager.as_ref(),
-        &cancel_flag,
-        on_progress,
-    )
-    .await;
+    let agent_ctx = AgentContext {
+        pool: &state.pool,
+        tools: ToolContext {
+            user_id,
+            db: &state.db,
+            embedder: state.embedder.as_deref(),
+            gmail_creds: &state.config.gmail_creds,
+            system_tools_enabled: state.config.enable_system_tools,
+            working_dir: &state.config.working_dir,
+            bash_timeout: state.config.bash_timeout,
+            cc_manager: state.cc_manager.as_ref(),
+        },
+        max_turns: state.config.max_agent_turns,
+        preferred_provider: preferred_provider.as_deref(),
+        budget_limits: &state.config.budget,
+        cancel_flag: &cancel_flag,
+    };
+    let result = AgentLoop::run(&agent_ctx, &system_prompt, user_content, history, on_progress).await;
 
     let elapsed_secs = start.elapsed().as_secs_f64();
 
@@ -673,14 +773,69 @@ async fn handle_message(
     typing_active.store(false, Ordering::Relaxed);
     typing_handle.abort();
 
+    // The progress sender was dropped with the agent loop; wait for pending edits
+    let _ = editor_handle.await;
+
     match result {
         Ok(agent_result) => {
             // Clean raw function call syntax and detect hallucinated output
             let cleaned = formatter::clean_response(&agent_result.response, &agent_result.tools_used);
 
-            // Save assistant response to history
-            state.db.append_message(&session_id, "assistant", &cleaned);
-            state.db.log_query(user_id, &agent_result.provider, &raw_text, start.elapsed().as_millis() as u64, 0, 0);
+            // Save tool calls, results and the reply to history
+            state.db.append_tool_messages(&session_id, &agent_result.transcript).await;
+            state.db.append_message(&session_id, "assistant", &cleaned).await;
+            if state.config.compact_threshold > 0 {
+                // Summarize in the background so the reply isn't delayed
+                let state = state.clone();
+                let session_id = session_id.clone();
+                tokio::spawn(async move {
+                    let result = compaction::compact_session(
+                        &state.pool,
+                        &state.db,
+                        state.payer(user_id),
+                        &session_id,
+                        state.config.compact_threshold,
+                        state.config.compact_provider.as_deref(),
+                    )
+                    .await;
+                    if let Err(e) = result {
+                        warn!("Auto-compaction of {session_id} failed: {e}");
+                    }
+                });
+            }
+            if state.db.session_title(&session_id).await.is_none() {
+                // Name the conversation after its first exchange, in the background
+                let state = state.clone();
+                let session_id = session_id.clone();
+                let (user_text, reply) = (combined_text.clone(), cleaned.clone());
+                tokio::spawn(async move {
+                    let title = titles::session_title(&state.pool, &state.db, state.payer(user_id), &user_text, &reply).await;
+                    if let Err(e) = state.db.set_session_title(&session_id, &title, true).await {
+                        warn!("Failed to title session {session_id}: {e}");
+                    }
+                });
+            }
+            if state.config.memory_extraction != ExtractionMode::Off {
+                tokio::spawn(extract_memories(
+                    bot.clone(),
+                    state.clone(),
+                    msg.chat.id,
+                    user_id,
+                    user_text_parsed.clone(),
+                    cleaned.clone(),
+                ));
+            }
+            let usage = agent_result.total_usage();
+            budget::record(
+                &state.db,
+                state.pool.prices(),
+                user_id,
+                &raw_text,
+                start.elapsed().as_millis() as u64,
+                (&agent_result.provider, &agent_result.model),
+                &agent_result.usage,
+            )
+            .await;
 
             // Build final response with footer
             let footer = formatter::format_tools_footer(
@@ -688,7 +843,9 @@ async fn handle_message(
                 &agent_result.tools_count,
                 elapsed_secs,
                 &agent_result.provider,
+                &agent_result.model,
                 agent_result.turns,
+                &usage,
             );
             let full_response = format!("{cleaned}{footer}");
 
@@ -713,6 +870,19 @@ async fn handle_message(
         }
         Err(err) => {
             error!("Agent error: {err}");
+            // Turns completed before the failure were paid for and count toward budgets
+            if let Some(last) = err.usage.last() {
+                budget::record(
+                    &state.db,
+                    state.pool.prices(),
+                    user_id,
+                    &raw_text,
+                    start.elapsed().as_millis() as u64,
+                    (&last.provider, &last.model),
+                    &err.usage,
+                )
+                .await;
+            }
             safe_edit(&bot, msg.chat.id, progress_msg_id, &format!("❌ Error: {err}")).await;
         }
     }
@@ -720,28 +890,153 @@ async fn handle_message(
     Ok(())
 }
 
+/// Look for durable facts in a finished exchange, then save them or ask the
+/// user to confirm each one, depending on `MEMORY_EXTRACTION`.
+async fn extract_memories(bot: Bot, state: Arc<AppState>, chat_id: ChatId, user_id: u64, user_text: String, reply: String) {
+    let embedder = state.embedder.as_deref();
+    let facts = match extraction::extract_facts(
+        &state.pool,
+        &state.db,
+        embedder,
+        state.payer(user_id),
+        &user_text,
+        &reply,
+        state.config.extraction_provider.as_deref(),
+    )
+    .await
+    {
+        Ok(facts) => facts,
+        Err(e) => {
+            warn!("{e}");
+            return;
+        }
+    };
+
+    if state.config.memory_extraction == ExtractionMode::Auto {
+        let saved = extraction::save_facts(&state.db, embedder, user_id, &facts).await;
+        if saved > 0 {
+            info!("Auto-saved {saved} memories for user {user_id}");
+        }
+        return;
+    }
+
+    for fact in &facts {
+        let id = match state.db.add_memory_proposal(user_id, &fact.fact, &fact.category, fact.importance).await {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Failed to store memory proposal: {e}");
+                continue;
+            }
+        };
+        let keyboard = InlineKeyboardMarkup::new(vec![vec![
+            InlineKeyboardButton::callback("✅ Save", format!("mem:save:{id}")),
+            InlineKeyboardButton::callback("✖️ Skip", format!("mem:skip:{id}")),
+        ]]);
+        let _ = bot
+            .send_message(chat_id, format!("💡 Remember this?\n{}", fact.fact))
+            .reply_markup(keyboard)
+            .await;
+    }
+}
+
+/// Inline button presses: answers to "Remember this?" suggestions and the
+/// /forget_me confirmation.
+async fn handle_callback(bot: Bot, q: CallbackQuery, state: Arc<AppState>) -> ResponseResult<()> {
+    let user_id = q.from.id.0;
+    if !state.config.allowed_users.is_empty() && !state.config.allowed_users.contains(&user_id) {
+        bot.answer_callback_query(q.id).text("Unauthorized.").await?;
+        return Ok(());
+    }
+    if let Some(answer) = q.data.as_deref().and_then(|d| d.strip_prefix("forget:")) {
+        let text = if answer == "yes" {
+            match state.db.forget_user(user_id).await {
+                Ok(removed) => {
+                    info!("Deleted all data of user {user_id} ({removed} rows)");
+                    if let Err(e) = state.db.vacuum().await {
+                        warn!("Vacuum after /forget_me failed: {e}");
+                    }
+                    "🗑 Your data has been deleted. Only this month's token and cost totals are kept, \
+                     without your id, so global budgets still add up.".to_string()
+                }
+                Err(e) => format!("❌ {e}"),
+            }
+        } else {
+            "Cancelled, nothing was deleted.".to_string()
+        };
+        bot.answer_callback_query(q.id).await?;
+        if let Some(message) = &q.message {
+            let _ = bot.edit_message_text(message.chat().id, message.id(), text).await;
+        }
+        return Ok(());
+    }
+
+    let Some((action, id)) = q
+        .data
+        .as_deref()
+        .and_then(|d| d.strip_prefix("mem:"))
+        .and_then(|d| d.split_once(':'))
+    else {
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    let proposal = match id.parse() {
+        Ok(id) => state.db.take_memory_proposal(user_id, id).await,
+        Err(_) => None,
+    };
+    let text = match (action, proposal) {
+        (_, None) => "This suggestion was already answered or has expired.".to_string(),
+        ("save", Some((fact, category, importance))) => {
+            let meta = FactMeta {
+                category,
+                pinned: false,
+                importance,
+                expires_in_days: None,
+            };
+            let result = tools::memory_save(&state.db, state.embedder.as_deref(), user_id, &fact, meta).await;
+            format!("🧠 {result}")
+        }
+        (_, Some((fact, _, _))) => format!("✖️ Not saved: {fact}"),
+    };
+
+    bot.answer_callback_query(q.id).await?;
+    // Replacing the text also removes the buttons
+    if let Some(message) = &q.message {
+        let _ = bot.edit_message_text(message.chat().id, message.id(), text).await;
+    }
+    Ok(())
+}
+
 /// Parse inline provider override from user message.
 /// Examples: "use claude tell me a joke" → (Some("claude"), "tell me a joke")
+///           "use claude:haiku tell me a joke" → (Some("claude:haiku"), "tell me a joke")
 ///           "dùng gemini xin chào" → (Some("gemini"), "xin chào")
 ///           "normal message" → (None, "normal message")
-fn parse_provider_override(text: &str) -> (Option<String>, String) {
+fn parse_provider_override(text: &str, providers: &[&str]) -> (Option<String>, String) {
     let lower = text.to_lowercase();
-    let prefixes = [
-        ("use claude ", "claude"),
-        ("dùng claude ", "claude"),
-        ("use gemini ", "gemini"),
-        ("dùng gemini ", "gemini"),
-        ("use groq ", "groq"),
-        ("dùng groq ", "groq"),
-        ("use mistral ", "mistral"),
-        ("dùng mistral ", "mistral"),
-    ];
 
-    for (prefix, provider) in &prefixes {
-        if lower.starts_with(prefix) {
-            let remaining = text[prefix.len()..].to_string();
+    for verb in ["use ", "dùng "] {
+        let Some(rest) = lower.strip_prefix(verb) else {
+            continue;
+        };
+        for provider in providers {
+            let Some(after) = rest.strip_prefix(provider) else {
+                continue;
+            };
+            // Optional ":model" suffix, e.g. "claude:haiku"
+            let model_len = if after.starts_with(':') {
+                after.find(' ').unwrap_or(after.len())
+            } else {
+                0
+            };
+            if !after[model_len..].starts_with(' ') {
+                continue;
+            }
+            // Slice the original to keep the user's casing; `get` guards odd char boundaries
+            let spec_len = verb.len() + provider.len() + model_len;
+            let spec = text.get(verb.len()..spec_len).unwrap_or(provider).to_lowercase();
+            let remaining = text.get(spec_len + 1..).unwrap_or("").to_string();
             if !remaining.is_empty() {
-                return (Some(provider.to_string()), remaining);
+                return (Some(spec), remaining);
             }
         }
     }
@@ -749,6 +1044,71 @@ fn parse_provider_override(text: &str) -> (Option<String>, String) {
     (None, text.to_string())
 }
 
+/// Largest import file accepted (Telegram bots can't download more anyway).
+const MAX_IMPORT_BYTES: u32 = 20 * 1024 * 1024;
+
+/// `/import`: recreate sessions from a JSON file attached to the message or
+/// to the message it replies to.
+async fn handle_import(
+    msg: &teloxide::types::Message,
+    bot: &Bot,
+    state: &AppState,
+    user_id: u64,
+) -> ResponseResult<()> {
+    let Some(doc) = msg.document().or_else(|| msg.reply_to_message().and_then(|m| m.document())) else {
+        bot.send_message(
+            msg.chat.id,
+            "Send a file from /export (or ChatGPT's conversations.json) with the caption /import, \
+             or reply to one with /import.",
+        )
+        .await?;
+        return Ok(());
+    };
+    if doc.file.size > MAX_IMPORT_BYTES {
+        bot.send_message(msg.chat.id, "❌ File too large to import (max 20 MB).").await?;
+        return Ok(());
+    }
+
+    let bytes = match download_telegram_file(bot, &doc.file.id, &state.config.telegram_bot_token).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            bot.send_message(msg.chat.id, format!("❌ Download failed: {e}")).await?;
+            return Ok(());
+        }
+    };
+    let sessions = match export::parse_import(&bytes) {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            bot.send_message(msg.chat.id, format!("❌ {e}")).await?;
+            return Ok(());
+        }
+    };
+
+    let mut imported = Vec::new();
+    for session in sessions {
+        let count = session.messages.len();
+        match state.db.import_session(user_id, &session.title, session.messages).await {
+            Ok(number) => imported.push((number, session.title, count)),
+            Err(e) => {
+                error!("Failed to import session: {e}");
+                bot.send_message(msg.chat.id, format!("❌ Import failed: {e}")).await?;
+                return Ok(());
+            }
+        }
+    }
+    let reply = match imported.as_slice() {
+        [(number, title, count)] => {
+            format!("Imported \"{title}\" ({count} messages) as conversation {number}; it is now active.")
+        }
+        _ => format!(
+            "Imported {} conversations; the most recent is now active. /sessions to list them.",
+            imported.len()
+        ),
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+    Ok(())
+}
+
 async fn handle_command(
     msg: &teloxide::types::Message,
     bot: &Bot,
@@ -783,12 +1143,25 @@ async fn handle_command(
                 msg.chat.id,
                 "/start — Bot info\n\
                  /help — Show commands\n\
-                 /new — Start new conversation\n\
+                 /new — Start new conversation (earlier ones are kept)\n\
+                 /sessions — List conversations\n\
+                 /resume <n> — Switch to conversation n\n\
+                 /rename <title> — Rename the current conversation\n\
+                 /delete <n> — Delete conversation n\n\
+                 /search <words> — Search past conversations\n\
+                 /export [n] — Export the current (or nth) conversation as Markdown + JSON\n\
+                 /import — Caption of (or reply to) an exported or ChatGPT JSON file\n\
+                 /compact — Summarize older history to free up context\n\
                  /stop — Stop current query\n\
                  /memory — List saved facts\n\
+                 /pin <id> — Always include a memory in context (again to unpin)\n\
+                 /reindex — Embed memories missing a vector (semantic search)\n\
                  /providers — Show available providers\n\
-                 /tools — List available tools\n\n\
-                 Tip: Prefix \"use claude\"/\"dùng gemini\" to pick a provider for one message.",
+                 /model — Show or pick provider:model (/model claude:haiku, /model reset)\n\
+                 /usage — Token usage and remaining budget\n\
+                 /tools — List available tools\n\
+                 /forget_me — Delete all your memories, conversations, todos, plan and logs\n\n\
+                 Tip: Prefix \"use claude\"/\"dùng gemini:flash\" to pick a provider/model for one message.",
             )
             .await?;
         }
@@ -812,12 +1185,145 @@ async fn handle_command(
             }
         }
         "/new" => {
-            state.db.clear_session(user_id);
-            bot.send_message(msg.chat.id, "Session cleared. Starting fresh conversation.")
+            state.db.new_session(user_id).await;
+            bot.send_message(
+                msg.chat.id,
+                "Started a new conversation. Earlier ones are kept: /sessions to list them, /resume <n> to go back.",
+            )
+            .await?;
+        }
+        "/sessions" => {
+            let sessions = state.db.list_sessions(user_id).await;
+            if sessions.is_empty() {
+                bot.send_message(msg.chat.id, "No conversations yet.").await?;
+            } else {
+                let output: String = sessions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| {
+                        let marker = if i == 0 { "▶" } else { " " };
+                        format!(
+                            "{marker} [{}] {} — {} UTC, {} messages",
+                            s.number, s.title, s.last_active_at, s.messages
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let output = format!("{output}\n\n/resume <n> to switch, /delete <n> to delete");
+                for chunk in formatter::split_message(&output, 4096) {
+                    bot.send_message(msg.chat.id, &chunk).await?;
+                }
+            }
+        }
+        "/resume" => {
+            let reply = match text.split_whitespace().nth(1).and_then(|a| a.parse::<i64>().ok()) {
+                Some(n) => match state.db.resume_session(user_id, n).await {
+                    Ok(true) => {
+                        let session_id = state.db.get_or_create_session(user_id).await;
+                        let title = state.db.session_title(&session_id).await.unwrap_or_default();
+                        format!("Resumed conversation {n}. {title}").trim_end().to_string()
+                    }
+                    Ok(false) => format!("Conversation {n} not found."),
+                    Err(e) => format!("❌ {e}"),
+                },
+                None => "Usage: /resume <n> (numbers are shown by /sessions)".into(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        "/rename" => {
+            let title = text.split_once(char::is_whitespace).map_or("", |(_, t)| t.trim());
+            let reply = if title.is_empty() {
+                "Usage: /rename <title>".to_string()
+            } else {
+                let session_id = state.db.get_or_create_session(user_id).await;
+                match state.db.set_session_title(&session_id, title, false).await {
+                    Ok(_) => format!("Renamed the current conversation to \"{title}\"."),
+                    Err(e) => format!("❌ {e}"),
+                }
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        "/delete" => {
+            let reply = match text.split_whitespace().nth(1).and_then(|a| a.parse::<i64>().ok()) {
+                Some(n) => match state.db.delete_session(user_id, n).await {
+                    Ok(true) => format!("🗑 Deleted conversation {n}."),
+                    Ok(false) => format!("Conversation {n} not found."),
+                    Err(e) => format!("❌ {e}"),
+                },
+                None => "Usage: /delete <n> (numbers are shown by /sessions)".into(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        "/search" => {
+            let query = text.split_once(char::is_whitespace).map_or("", |(_, q)| q.trim());
+            let reply = if query.is_empty() {
+                "Usage: /search <words>".to_string()
+            } else {
+                tools::conversation_search(&state.db, user_id, query, None).await
+            };
+            for chunk in formatter::split_message(&reply, 4096) {
+                bot.send_message(msg.chat.id, &chunk).await?;
+            }
+        }
+        "/forget_me" => {
+            let keyboard = InlineKeyboardMarkup::new(vec![vec![
+                InlineKeyboardButton::callback("🗑 Delete everything", "forget:yes"),
+                InlineKeyboardButton::callback("Cancel", "forget:no"),
+            ]]);
+            bot.send_message(
+                msg.chat.id,
+                "This deletes all your memories, conversations, todos, plan, settings and query \
+                 logs. Only this month's token and cost totals are kept, without your id, so global \
+                 budgets still add up. It cannot be undone (/export first to keep a copy).",
+            )
+            .reply_markup(keyboard)
+            .await?;
+        }
+        "/export" => {
+            let sessions = state.db.list_sessions(user_id).await;
+            let session = match text.split_whitespace().nth(1) {
+                None => sessions.first(),
+                Some(arg) => arg.parse::<i64>().ok().and_then(|n| sessions.iter().find(|s| s.number == n)),
+            };
+            let Some(session) = session.filter(|s| s.messages > 0) else {
+                bot.send_message(msg.chat.id, "Nothing to export. Usage: /export [n] (numbers are shown by /sessions)")
+                    .await?;
+                return Ok(());
+            };
+            let messages = state.db.session_messages(&session.id).await;
+            let summary = state.db.session_summary(&session.id).await;
+            let name = format!("session-{}", session.number);
+            let markdown = export::to_markdown(&session.title, summary.as_deref(), &messages);
+            let json = export::to_json(&session.title, summary.as_deref(), &messages);
+            bot.send_document(msg.chat.id, InputFile::memory(markdown).file_name(format!("{name}.md")))
+                .await?;
+            bot.send_document(msg.chat.id, InputFile::memory(json).file_name(format!("{name}.json")))
+                .caption("Send this file with the caption /import to restore the conversation.")
                 .await?;
         }
+        "/compact" => {
+            let session_id = state.db.get_or_create_session(user_id).await;
+            let result = compaction::compact_session(
+                &state.pool,
+                &state.db,
+                state.payer(user_id),
+                &session_id,
+                0,
+                state.config.compact_provider.as_deref(),
+            )
+            .await;
+            let reply = match result {
+                Ok(0) => format!(
+                    "Nothing to compact: the last {} exchanges are always kept as-is.",
+                    compaction::KEEP_RECENT_PAIRS
+                ),
+                Ok(n) => format!("Compacted {n} older messages into the session summary."),
+                Err(e) => format!("❌ {e}"),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
         "/memory" => {
-            let facts = state.db.list_facts(user_id, None).unwrap_or_default();
+            let facts = state.db.list_facts(user_id, None).await.unwrap_or_default();
             if facts.is_empty() {
                 bot.send_message(msg.chat.id, "No facts saved yet.").await?;
             } else {
@@ -831,12 +1337,94 @@ async fn handle_command(
                 }
             }
         }
+        "/pin" => {
+            let reply = match text.split_whitespace().nth(1).and_then(|a| a.parse::<i64>().ok()) {
+                Some(id) => match state.db.is_fact_pinned(user_id, id).await {
+                    Some(pinned) => match state.db.set_fact_pinned(user_id, id, !pinned).await {
+                        Ok(_) if pinned => format!("Unpinned memory {id}: included only when relevant."),
+                        Ok(_) => format!("📌 Pinned memory {id}: included in every prompt."),
+                        Err(e) => format!("❌ {e}"),
+                    },
+                    None => format!("Memory ID {id} not found."),
+                },
+                None => "Usage: /pin <id> (IDs are shown by /memory)".into(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        "/reindex" => {
+            let reply = match state.embedder.as_deref() {
+                Some(embedder) => match tools::backfill_embeddings(&state.db, embedder).await {
+                    Ok(0) => format!("All memories are already embedded ({}).", embedder.model()),
+                    Ok(n) => format!("Embedded {n} memories with {}.", embedder.model()),
+                    Err(e) => format!("❌ Backfill failed: {e}"),
+                },
+                None => "Semantic search is off: set EMBEDDINGS_PROVIDER (or GEMINI_API_KEYS).".into(),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
         "/providers" => {
-            bot.send_message(
-                msg.chat.id,
-                format!("Available: {}", state.pool.available_providers().join(", ")),
-            )
-            .await?;
+            let health = state.pool.key_health();
+            let lines: Vec<String> = state
+                .pool
+                .model_catalog()
+                .iter()
+                .map(|(provider, models)| {
+                    let keys = health
+                        .iter()
+                        .find(|(name, _, _)| name == provider)
+                        .map(|(_, usable, total)| format!(" [{usable}/{total} keys ready]"))
+                        .unwrap_or_default();
+                    let caps = state
+                        .pool
+                        .capabilities(provider)
+                        .map(|c| format!(" ({})", formatter::format_capabilities(&c)))
+                        .unwrap_or_default();
+                    format!("{provider}: {}{keys}{caps}", models.join(", "))
+                })
+                .collect();
+            bot.send_message(msg.chat.id, format!("Available:\n{}", lines.join("\n")))
+                .await?;
+        }
+        "/model" => {
+            let arg = text.split_whitespace().nth(1).unwrap_or("");
+            let reply = match arg {
+                "" => {
+                    let current = state
+                        .db
+                        .get_model_preference(user_id)
+                        .await
+                        .unwrap_or_else(|| "default (auto)".into());
+                    let catalog: Vec<String> = state
+                        .pool
+                        .model_catalog()
+                        .iter()
+                        .flat_map(|(provider, models)| {
+                            models.iter().map(move |m| format!("{provider}:{m}"))
+                        })
+                        .collect();
+                    format!(
+                        "Current: {current}\n\nModels:\n{}\n\n\
+                         /model <provider[:model]> to pick, /model reset for default",
+                        catalog.join("\n")
+                    )
+                }
+                "reset" | "default" | "auto" => match state.db.set_model_preference(user_id, None).await {
+                    Ok(()) => "Model reset to default.".to_string(),
+                    Err(e) => format!("Error: {e}"),
+                },
+                spec => match state.pool.resolve_model(spec) {
+                    Some(resolved) => match state.db.set_model_preference(user_id, Some(&resolved)).await {
+                        Ok(()) => format!("Model set: {resolved}"),
+                        Err(e) => format!("Error: {e}"),
+                    },
+                    None => format!("Unknown provider/model: {spec}. /model to list."),
+                },
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+        }
+        "/usage" => {
+            let report = budget::usage_report(&state.config.budget, &state.db, user_id).await;
+            bot.send_message(msg.chat.id, report).await?;
         }
         "/tools" => {
             let gmail_ok = state.config.gmail_creds.is_configured();
@@ -846,8 +1434,11 @@ async fn handle_command(
                 "web_fetch — Fetch URL content",
                 "memory_save — Save a fact",
                 "memory_search — Search memory",
+                "memory_update — Edit a fact",
+                "memory_merge — Merge duplicate facts",
                 "memory_list — List all facts",
                 "memory_delete — Delete a fact",
+                "conversation_search — Search past conversations",
                 "get_datetime — Current date/time",
                 "plan_read — Read current plan",
                 "plan_write — Write/update plan",
@@ -1,4 +1,5 @@
+mod export;
 mod formatter;
 mod handler;
 
-pub use handler::run_bot;
+pub use handler::{run_bot, run_bot_with_pool};
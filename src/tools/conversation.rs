@@ -0,0 +1,28 @@
+use crate::db::Database;
+
+/// Matches returned per search.
+const MAX_HITS: usize = 10;
+
+pub async fn conversation_search(db: &Database, user_id: u64, query: &str, days: Option<u32>) -> String {
+    if query.trim().is_empty() {
+        return "Error: query cannot be empty".into();
+    }
+    match db.search_conversations(user_id, query, days, MAX_HITS).await {
+        Ok(hits) if hits.is_empty() => "No matching messages found.".into(),
+        Ok(hits) => hits
+            .iter()
+            .map(|h| {
+                let title = h.session_title.as_deref().map(|t| format!(" \"{t}\"")).unwrap_or_default();
+                let time = h.created_at.get(..16).unwrap_or(&h.created_at);
+                format!(
+                    "[session {}{title}] {time} UTC {}: {}",
+                    h.session_number,
+                    h.role,
+                    h.snippet.replace('\n', " ")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(e) => format!("Error searching: {e}"),
+    }
+}
@@ -1,20 +1,153 @@
-use crate::db::Database;
+use tracing::{info, warn};
 
-pub async fn memory_save(db: &Database, user_id: u64, fact: &str, category: &str) -> String {
+use crate::db::{Database, FactMeta, FactUpdate, MemoryContextLimits, SaveOutcome};
+use crate::provider::Embedder;
+
+/// Facts embedded per request when backfilling.
+const BACKFILL_BATCH: usize = 32;
+/// Only this much of an incoming message is used to pick memories for it.
+const MAX_QUERY_BYTES: usize = 2000;
+
+pub async fn memory_save(
+    db: &Database,
+    embedder: Option<&dyn Embedder>,
+    user_id: u64,
+    fact: &str,
+    meta: FactMeta,
+) -> String {
     if fact.is_empty() {
         return "Error: fact cannot be empty".into();
     }
-    match db.save_fact(user_id, fact, category) {
-        Ok(id) => format!("Saved (ID: {id}): \"{fact}\" [{category}]"),
+    // Embedding first lets the duplicate check compare meaning; a failure only
+    // delays semantic search for this fact until the next backfill
+    let vector = match embedder {
+        Some(embedder) => match embedder.embed(&[fact.to_string()]).await {
+            Ok(mut vectors) => vectors.pop(),
+            Err(e) => {
+                warn!("Failed to embed new memory: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+    let embedding = embedder.zip(vector.as_deref()).map(|(e, v)| (e.model(), v));
+    let pin = if meta.pinned { " (pinned)" } else { "" };
+    let category = meta.category.clone();
+    match db.save_fact(user_id, fact, meta, embedding).await {
+        Ok(SaveOutcome::Saved(id)) => format!("Saved (ID: {id}): \"{fact}\" [{category}]{pin}"),
+        Ok(SaveOutcome::Duplicate(id, existing)) => format!(
+            "Not saved: already in memory as ID {id}: \"{existing}\". Use memory_update to change it."
+        ),
         Err(e) => format!("Error saving: {e}"),
     }
 }
 
-pub async fn memory_search(db: &Database, user_id: u64, keyword: &str) -> String {
+pub async fn memory_update(
+    db: &Database,
+    embedder: Option<&dyn Embedder>,
+    user_id: u64,
+    fact_id: i64,
+    update: FactUpdate,
+) -> String {
+    if update.fact.as_deref().is_some_and(str::is_empty) {
+        return "Error: fact cannot be empty".into();
+    }
+    let FactUpdate {
+        fact,
+        category,
+        importance,
+        pinned,
+        expires_in_days,
+    } = &update;
+    if fact.is_none() && category.is_none() && importance.is_none() && pinned.is_none() && expires_in_days.is_none() {
+        return "Error: nothing to update".into();
+    }
+    let new_fact = update.fact.clone();
+    match db.update_fact(user_id, fact_id, update).await {
+        Ok(true) => {
+            if let Some(fact) = new_fact {
+                reembed(db, embedder, fact_id, &fact).await;
+            }
+            format!("Updated memory ID: {fact_id}")
+        }
+        Ok(false) => format!("Memory ID {fact_id} not found or not yours"),
+        Err(e) => format!("Error updating: {e}"),
+    }
+}
+
+pub async fn memory_merge(
+    db: &Database,
+    embedder: Option<&dyn Embedder>,
+    user_id: u64,
+    ids: &[i64],
+    fact: &str,
+    category: Option<&str>,
+) -> String {
+    if fact.is_empty() {
+        return "Error: fact cannot be empty".into();
+    }
+    match db.merge_facts(user_id, ids, fact, category).await {
+        Ok(id) => {
+            reembed(db, embedder, id, fact).await;
+            format!("Merged {} memories into ID {id}: \"{fact}\"", ids.len())
+        }
+        Err(e) => format!("Error merging: {e}"),
+    }
+}
+
+/// Embed a fact's new text; on failure it waits for the next backfill.
+async fn reembed(db: &Database, embedder: Option<&dyn Embedder>, id: i64, fact: &str) {
+    let Some(embedder) = embedder else {
+        return;
+    };
+    if let Err(e) = embed_fact(db, embedder, id, fact).await {
+        warn!("Failed to embed memory {id}: {e}");
+    }
+}
+
+async fn embed_fact(db: &Database, embedder: &dyn Embedder, id: i64, fact: &str) -> Result<(), String> {
+    let vectors = embedder.embed(&[fact.to_string()]).await.map_err(|e| e.to_string())?;
+    let vector = vectors.first().ok_or("no vector returned")?;
+    db.set_fact_embedding(id, embedder.model(), vector).await
+}
+
+/// Embed a search query; without a vector, searches fall back to keywords only.
+async fn embed_query(embedder: Option<&dyn Embedder>, query: &str) -> Option<Vec<f32>> {
+    match embedder?.embed(&[query.to_string()]).await {
+        Ok(mut vectors) => vectors.pop(),
+        Err(e) => {
+            warn!("Failed to embed memory query: {e}");
+            None
+        }
+    }
+}
+
+/// The memory section of the system prompt for an incoming message.
+pub async fn memory_context(
+    db: &Database,
+    embedder: Option<&dyn Embedder>,
+    user_id: u64,
+    message: &str,
+    limits: MemoryContextLimits,
+) -> String {
+    // Attached files can make the message huge; its start says enough about the topic
+    let mut end = message.len().min(MAX_QUERY_BYTES);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    let message = &message[..end];
+    let query_vector = embed_query(embedder, message).await;
+    let semantic = embedder.zip(query_vector.as_deref()).map(|(e, v)| (e.model(), v));
+    db.build_memory_context(user_id, message, semantic, limits).await
+}
+
+pub async fn memory_search(db: &Database, embedder: Option<&dyn Embedder>, user_id: u64, keyword: &str) -> String {
     if keyword.is_empty() {
         return "Error: keyword cannot be empty".into();
     }
-    match db.search_facts(user_id, keyword) {
+    let query_vector = embed_query(embedder, keyword).await;
+    let semantic = embedder.zip(query_vector.as_deref()).map(|(e, v)| (e.model(), v));
+    match db.search_facts(user_id, keyword, semantic).await {
         Ok(results) if results.is_empty() => "No facts found.".into(),
         Ok(results) => {
             let lines: Vec<String> = results
@@ -28,7 +161,7 @@ pub async fn memory_search(db: &Database, user_id: u64, keyword: &str) -> String
 }
 
 pub async fn memory_delete(db: &Database, user_id: u64, fact_id: i64) -> String {
-    match db.delete_fact(user_id, fact_id) {
+    match db.delete_fact(user_id, fact_id).await {
         Ok(true) => format!("Deleted memory ID: {fact_id}"),
         Ok(false) => format!("Memory ID {fact_id} not found or not yours"),
         Err(e) => format!("Error deleting: {e}"),
@@ -36,7 +169,7 @@ pub async fn memory_delete(db: &Database, user_id: u64, fact_id: i64) -> String
 }
 
 pub async fn memory_list(db: &Database, user_id: u64, category: Option<&str>) -> String {
-    match db.list_facts(user_id, category) {
+    match db.list_facts(user_id, category).await {
         Ok(results) if results.is_empty() => "No facts saved yet.".into(),
         Ok(results) => {
             let lines: Vec<String> = results
@@ -48,3 +181,24 @@ pub async fn memory_list(db: &Database, user_id: u64, category: Option<&str>) ->
         Err(e) => format!("Error listing: {e}"),
     }
 }
+
+/// Embed every fact that has no vector from the current embedding model (new
+/// installs, facts saved while the API was down, or after switching models).
+/// Returns how many facts were embedded.
+pub async fn backfill_embeddings(db: &Database, embedder: &dyn Embedder) -> Result<usize, String> {
+    let mut done = 0;
+    loop {
+        let batch = db.facts_missing_embedding(embedder.model(), BACKFILL_BATCH).await;
+        if batch.is_empty() {
+            break;
+        }
+        let texts: Vec<String> = batch.iter().map(|(_, fact)| fact.clone()).collect();
+        let vectors = embedder.embed(&texts).await.map_err(|e| e.to_string())?;
+        for ((id, _), vector) in batch.iter().zip(&vectors) {
+            db.set_fact_embedding(*id, embedder.model(), vector).await?;
+        }
+        done += batch.len();
+    }
+    info!("Backfilled embeddings for {done} memories ({})", embedder.model());
+    Ok(done)
+}
@@ -1,5 +1,6 @@
 mod web;
 mod memory;
+mod conversation;
 pub mod gmail;
 mod sheets;
 mod datetime;
@@ -8,7 +9,8 @@ mod planning;
 pub mod claude_code;
 
 pub use web::{web_search, web_fetch};
-pub use memory::{memory_save, memory_search, memory_list, memory_delete};
+pub use memory::{backfill_embeddings, memory_context, memory_merge, memory_save, memory_update, memory_search, memory_list, memory_delete};
+pub use conversation::conversation_search;
 pub use gmail::{gmail_search, gmail_read, gmail_send, gmail_archive, gmail_trash, gmail_label, gmail_list_labels};
 pub use sheets::{sheets_read, sheets_write, sheets_append, sheets_list, sheets_create_tab};
 pub use datetime::get_datetime;
@@ -3,7 +3,7 @@ use crate::db::Database;
 // --- Plan tools ---
 
 pub async fn plan_read(db: &Database, user_id: u64) -> String {
-    let content = db.get_plan(user_id);
+    let content = db.get_plan(user_id).await;
     if content.is_empty() {
         "No plan set. Use plan_write to create one.".into()
     } else {
@@ -12,7 +12,7 @@ pub async fn plan_read(db: &Database, user_id: u64) -> String {
 }
 
 pub async fn plan_write(db: &Database, user_id: u64, content: &str) -> String {
-    match db.set_plan(user_id, content) {
+    match db.set_plan(user_id, content).await {
         Ok(()) => "Plan updated successfully.".into(),
         Err(e) => format!("Error saving plan: {e}"),
     }
@@ -21,14 +21,14 @@ pub async fn plan_write(db: &Database, user_id: u64, content: &str) -> String {
 // --- Todo tools ---
 
 pub async fn todo_add(db: &Database, user_id: u64, content: &str) -> String {
-    match db.add_todo(user_id, content) {
+    match db.add_todo(user_id, content).await {
         Ok(id) => format!("Todo #{id} added: {content}"),
         Err(e) => format!("Error adding todo: {e}"),
     }
 }
 
 pub async fn todo_list(db: &Database, user_id: u64) -> String {
-    match db.list_todos(user_id) {
+    match db.list_todos(user_id).await {
         Ok(todos) if todos.is_empty() => "No todos. Use todo_add to create one.".into(),
         Ok(todos) => {
             let lines: Vec<String> = todos
@@ -53,7 +53,7 @@ pub async fn todo_update(db: &Database, user_id: u64, todo_id: i64, status: &str
     if !valid.contains(&status) {
         return format!("Invalid status '{status}'. Use: pending, in_progress, completed");
     }
-    match db.update_todo_status(user_id, todo_id, status) {
+    match db.update_todo_status(user_id, todo_id, status).await {
         Ok(true) => format!("Todo #{todo_id} updated to {status}"),
         Ok(false) => format!("Todo #{todo_id} not found"),
         Err(e) => format!("Error updating todo: {e}"),
@@ -61,7 +61,7 @@ pub async fn todo_update(db: &Database, user_id: u64, todo_id: i64, status: &str
 }
 
 pub async fn todo_delete(db: &Database, user_id: u64, todo_id: i64) -> String {
-    match db.delete_todo(user_id, todo_id) {
+    match db.delete_todo(user_id, todo_id).await {
         Ok(true) => format!("Todo #{todo_id} deleted"),
         Ok(false) => format!("Todo #{todo_id} not found"),
         Err(e) => format!("Error deleting todo: {e}"),
@@ -69,7 +69,7 @@ pub async fn todo_delete(db: &Database, user_id: u64, todo_id: i64) -> String {
 }
 
 pub async fn todo_clear_completed(db: &Database, user_id: u64) -> String {
-    match db.clear_completed_todos(user_id) {
+    match db.clear_completed_todos(user_id).await {
         Ok(count) => format!("Cleared {count} completed todos"),
         Err(e) => format!("Error clearing todos: {e}"),
     }
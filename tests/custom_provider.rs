@@ -0,0 +1,72 @@
+//! A backend defined outside the crate, registered through the public API.
+
+use futures::future::BoxFuture;
+
+use free_agent::provider::{
+    Capabilities, ChatOptions, LlmProvider, LlmResponse, Message, MessageContent, ProviderError, ProviderPool, Role,
+    ToolDef, Usage,
+};
+
+/// Echoes the last message back, prefixed with the model it was asked for.
+struct EchoProvider;
+
+impl LlmProvider for EchoProvider {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: false,
+            tools: true,
+            vision: false,
+            json_mode: false,
+            max_context: 8_000,
+        }
+    }
+
+    fn chat<'a>(
+        &'a self,
+        messages: &'a [Message],
+        _tools: &'a [ToolDef],
+        _api_key: &'a str,
+        model: &'a str,
+    ) -> BoxFuture<'a, Result<LlmResponse, ProviderError>> {
+        let last = messages.last().map(|m| m.content.as_text().to_string()).unwrap_or_default();
+        Box::pin(async move {
+            Ok(LlmResponse {
+                content: Some(format!("{model}: {last}")),
+                tool_calls: Vec::new(),
+                usage: Usage::default(),
+            })
+        })
+    }
+}
+
+#[tokio::test]
+async fn registered_provider_serves_requests() {
+    let mut pool = ProviderPool::empty("echo");
+    pool.register(Box::new(EchoProvider), vec![String::new()], vec!["echo-1".into()])
+        .unwrap();
+
+    let messages = [Message {
+        role: Role::User,
+        content: MessageContent::Text("ping".into()),
+    }];
+    let (response, provider, model) = pool.chat(&messages, &[], ChatOptions::default()).await.unwrap();
+
+    assert_eq!(response.content.as_deref(), Some("echo-1: ping"));
+    assert_eq!((provider.as_str(), model.as_str()), ("echo", "echo-1"));
+}
+
+#[tokio::test]
+async fn duplicate_provider_names_are_rejected() {
+    let mut pool = ProviderPool::empty("echo");
+    pool.register(Box::new(EchoProvider), vec![String::new()], vec!["echo-1".into()])
+        .unwrap();
+
+    let err = pool
+        .register(Box::new(EchoProvider), vec![String::new()], vec!["echo-2".into()])
+        .unwrap_err();
+    assert!(err.contains("Duplicate"), "{err}");
+}